@@ -25,6 +25,9 @@ pub enum Error {
     Io(#[from] std::io::Error),
     #[error("Invalid uint")]
     InvalidUint,
+    #[error("a padding policy was not valid: {0}")]
+    #[cfg(feature = "write-bhttp")]
+    InvalidPadding(String),
     #[error("a field or line was missing a necessary character 0x{0:x}")]
     Missing(u8),
     #[error("a URL was missing a key component")]
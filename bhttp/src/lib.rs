@@ -10,6 +10,8 @@ use std::convert::TryFrom;
     feature = "write-bhttp"
 ))]
 use std::io;
+#[cfg(feature = "write-bhttp")]
+use std::str::FromStr;
 
 #[cfg(feature = "read-http")]
 use url::Url;
@@ -18,6 +20,8 @@ mod err;
 mod parse;
 #[cfg(any(feature = "read-bhttp", feature = "write-bhttp"))]
 mod rw;
+#[cfg(feature = "stream")]
+mod stream;
 
 #[cfg(any(feature = "read-http", feature = "read-bhttp",))]
 use std::borrow::BorrowMut;
@@ -33,10 +37,16 @@ use err::Res;
 #[cfg(feature = "read-http")]
 use parse::{downcase, is_ows, read_line, split_at, COLON, SEMICOLON, SLASH, SP};
 use parse::{index_of, trim_ows, COMMA};
+#[cfg(feature = "write-bhttp")]
+use rand::Rng;
 #[cfg(feature = "read-bhttp")]
 use rw::{read_varint, read_vec};
 #[cfg(feature = "write-bhttp")]
 use rw::{write_len, write_varint, write_vec};
+#[cfg(feature = "stream")]
+pub use stream::ContentDecoder;
+#[cfg(all(feature = "stream", feature = "write-bhttp"))]
+pub use stream::ContentEncoder;
 
 #[cfg(feature = "read-http")]
 const CONTENT_LENGTH: &[u8] = b"content-length";
@@ -99,6 +109,62 @@ pub enum Mode {
     IndeterminateLength,
 }
 
+/// How much zero padding to add after a binary HTTP message, so that its
+/// length reveals less about its content (RFC 9292, Section 3.8).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg(feature = "write-bhttp")]
+pub enum Padding {
+    #[default]
+    None,
+    /// Pad to a multiple of this many bytes.
+    Bucket(usize),
+    /// Pad to the next power of two.
+    PowerOfTwo,
+    /// Add a random number of bytes, up to this many.
+    Random(usize),
+}
+
+/// The most bytes that a parsed padding policy can pad to or add.
+#[cfg(feature = "write-bhttp")]
+const MAX_PADDING: usize = 16 * 1024 * 1024;
+
+#[cfg(feature = "write-bhttp")]
+impl Padding {
+    /// The length of a message of `len` bytes once it is padded.
+    /// If padding would overflow, the message is padded less, or not at all.
+    #[must_use]
+    pub fn padded_len(self, len: usize) -> usize {
+        match self {
+            Self::None | Self::Bucket(0) => len,
+            Self::Bucket(size) => (len.max(1).saturating_add(size - 1) / size * size).max(len),
+            Self::PowerOfTwo => len.checked_next_power_of_two().unwrap_or(len),
+            Self::Random(max) => len.saturating_add(rand::thread_rng().gen_range(0..=max)),
+        }
+    }
+}
+
+/// Parses `none`, `bucket:<bytes>`, `pow2`, or `random:<bytes>`.
+/// Sizes can be at most 16 MiB.
+#[cfg(feature = "write-bhttp")]
+impl FromStr for Padding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Res<Self> {
+        let invalid = || Error::InvalidPadding(s.to_owned());
+        let (kind, size) = match s.split_once(':') {
+            Some((kind, size)) => (kind, Some(size.parse().map_err(|_| invalid())?)),
+            None => (s, None),
+        };
+        match (kind, size) {
+            ("none", None) => Ok(Self::None),
+            ("bucket", Some(size)) if size > 0 && size <= MAX_PADDING => Ok(Self::Bucket(size)),
+            ("pow2", None) => Ok(Self::PowerOfTwo),
+            ("random", Some(max)) if max <= MAX_PADDING => Ok(Self::Random(max)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
 pub struct Field {
     name: Vec<u8>,
     value: Vec<u8>,
@@ -771,9 +837,12 @@ impl Message {
         Ok(())
     }
 
-    /// Read a BHTTP message.
+    /// Read the framing indicator, control data, and header section of a BHTTP message.
+    /// The content and trailer section are left unread, so the returned
+    /// message has no content.  With the `stream` feature, `ContentDecoder`
+    /// can be used to read the content incrementally.
     #[cfg(feature = "read-bhttp")]
-    pub fn read_bhttp<T, R>(r: &mut T) -> Res<Self>
+    pub fn read_bhttp_header<T, R>(r: &mut T) -> Res<(Mode, Self)>
     where
         T: BorrowMut<R> + ?Sized,
         R: ReadSeek + ?Sized,
@@ -797,6 +866,27 @@ impl Message {
         }
         let header = FieldSection::read_bhttp(mode, r)?;
 
+        Ok((
+            mode,
+            Self {
+                informational,
+                control,
+                header,
+                content: Vec::new(),
+                trailer: FieldSection::default(),
+            },
+        ))
+    }
+
+    /// Read a BHTTP message.
+    #[cfg(feature = "read-bhttp")]
+    pub fn read_bhttp<T, R>(r: &mut T) -> Res<Self>
+    where
+        T: BorrowMut<R> + ?Sized,
+        R: ReadSeek + ?Sized,
+    {
+        let (mode, mut message) = Self::read_bhttp_header(r)?;
+
         let mut content = read_vec(r)?.unwrap_or_default();
         if mode == Mode::IndeterminateLength && !content.is_empty() {
             loop {
@@ -808,25 +898,34 @@ impl Message {
             }
         }
 
-        let trailer = FieldSection::read_bhttp(mode, r)?;
-
-        Ok(Self {
-            informational,
-            control,
-            header,
-            content,
-            trailer,
-        })
+        message.content = content;
+        message.trailer = FieldSection::read_bhttp(mode, r)?;
+        Ok(message)
     }
 
+    /// Write everything up to the end of the header section.
     #[cfg(feature = "write-bhttp")]
-    pub fn write_bhttp(&self, mode: Mode, w: &mut impl io::Write) -> Res<()> {
+    fn write_bhttp_start(&self, mode: Mode, w: &mut impl io::Write) -> Res<()> {
         write_varint(self.control.code(mode), w)?;
         for info in &self.informational {
             info.write_bhttp(mode, w)?;
         }
         self.control.write_bhttp(w)?;
-        self.header.write_bhttp(mode, w)?;
+        self.header.write_bhttp(mode, w)
+    }
+
+    /// Write the start of a BHTTP message in the indeterminate-length form,
+    /// up to the end of the header section.  The content and trailer of this
+    /// message are not written; with the `stream` feature, `ContentEncoder`
+    /// writes content as it becomes available.
+    #[cfg(feature = "write-bhttp")]
+    pub fn write_bhttp_header(&self, w: &mut impl io::Write) -> Res<()> {
+        self.write_bhttp_start(Mode::IndeterminateLength, w)
+    }
+
+    #[cfg(feature = "write-bhttp")]
+    pub fn write_bhttp(&self, mode: Mode, w: &mut impl io::Write) -> Res<()> {
+        self.write_bhttp_start(mode, w)?;
 
         write_vec(&self.content, w)?;
         if mode == Mode::IndeterminateLength && !self.content.is_empty() {
@@ -835,6 +934,22 @@ impl Message {
         self.trailer.write_bhttp(mode, w)?;
         Ok(())
     }
+
+    /// Write a BHTTP message, followed by zero padding.
+    /// Readers ignore the padding, so this doesn't change the message.
+    #[cfg(feature = "write-bhttp")]
+    pub fn write_bhttp_padded(
+        &self,
+        mode: Mode,
+        padding: Padding,
+        w: &mut impl io::Write,
+    ) -> Res<()> {
+        let mut buf = Vec::new();
+        self.write_bhttp(mode, &mut buf)?;
+        buf.resize(padding.padded_len(buf.len()), 0);
+        w.write_all(&buf)?;
+        Ok(())
+    }
 }
 
 #[cfg(feature = "write-http")]
@@ -0,0 +1,147 @@
+use std::{cmp::min, convert::TryFrom};
+
+#[cfg(feature = "write-bhttp")]
+use crate::rw::{write_len, write_vec};
+use crate::{
+    err::{Error, Res},
+    Mode,
+};
+
+/// The largest encoding of a variable-length integer.
+const MAX_VARINT_LEN: usize = 8;
+
+enum State {
+    /// Reading the length of the content, or of the next chunk of content.
+    Length {
+        buf: [u8; MAX_VARINT_LEN],
+        read: usize,
+    },
+    /// Reading content, with this many bytes remaining.
+    Content(u64),
+    /// All of the content has been read.
+    Trailer,
+}
+
+impl State {
+    fn length() -> Self {
+        Self::Length {
+            buf: [0; MAX_VARINT_LEN],
+            read: 0,
+        }
+    }
+}
+
+/// Decodes the content of a BHTTP message incrementally, as it arrives.
+///
+/// This picks up where `Message::read_bhttp_header` stops, so that the
+/// content of a large message can be passed on without holding all of it.
+/// Anything that follows the content, such as the trailer section, is ignored.
+pub struct ContentDecoder {
+    mode: Mode,
+    state: State,
+}
+
+impl ContentDecoder {
+    #[must_use]
+    pub fn new(mode: Mode) -> Self {
+        Self {
+            mode,
+            state: State::length(),
+        }
+    }
+
+    /// Decode the next piece of a message.
+    /// This returns the parts of `data` that are content, in order.
+    pub fn decode<'a>(&mut self, mut data: &'a [u8]) -> Vec<&'a [u8]> {
+        let mut content = Vec::new();
+        while !data.is_empty() {
+            match &mut self.state {
+                State::Length { buf, read } => {
+                    buf[*read] = data[0];
+                    *read += 1;
+                    data = &data[1..];
+
+                    let len = 1 << (buf[0] >> 6);
+                    if *read == len {
+                        let v = buf[1..len]
+                            .iter()
+                            .fold(u64::from(buf[0] & 0x3f), |v, b| (v << 8) | u64::from(*b));
+                        // An empty chunk ends indeterminate-length content.
+                        self.state = if v == 0 {
+                            State::Trailer
+                        } else {
+                            State::Content(v)
+                        };
+                    }
+                }
+                State::Content(remaining) => {
+                    let n = usize::try_from(*remaining).map_or(data.len(), |r| min(r, data.len()));
+                    let (c, rest) = data.split_at(n);
+                    content.push(c);
+                    data = rest;
+                    *remaining -= c.len() as u64;
+                    if *remaining == 0 {
+                        self.state = match self.mode {
+                            Mode::KnownLength => State::Trailer,
+                            Mode::IndeterminateLength => State::length(),
+                        };
+                    }
+                }
+                State::Trailer => break,
+            }
+        }
+        content
+    }
+
+    /// Whether all of the content has been read.
+    #[must_use]
+    pub fn is_complete(&self) -> bool {
+        matches!(self.state, State::Trailer)
+    }
+
+    /// Check that the message ended in a valid place.
+    /// As with `Message::read_bhttp`, a message can be truncated
+    /// between chunks of content, but not in the middle of one.
+    pub fn finish(&self) -> Res<()> {
+        match self.state {
+            State::Trailer | State::Length { read: 0, .. } => Ok(()),
+            _ => Err(Error::Truncated),
+        }
+    }
+}
+
+/// Encodes the content of a BHTTP message incrementally, as it is produced.
+///
+/// This continues from `Message::write_bhttp_header`, using the
+/// indeterminate-length form, so the content doesn't need to be known in
+/// advance.  The message ends with an empty trailer section.
+#[cfg(feature = "write-bhttp")]
+#[derive(Debug, Default)]
+pub struct ContentEncoder {}
+
+#[cfg(feature = "write-bhttp")]
+impl ContentEncoder {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    /// Encode the next piece of content.
+    /// Empty content produces nothing, as an empty chunk ends the content.
+    pub fn encode(&mut self, content: &[u8]) -> Res<Vec<u8>> {
+        let mut buf = Vec::new();
+        if !content.is_empty() {
+            write_vec(content, &mut buf)?;
+        }
+        Ok(buf)
+    }
+
+    /// End the content and the message.
+    pub fn finish(self) -> Res<Vec<u8>> {
+        let mut buf = Vec::new();
+        // The end of the content, then an empty trailer section.
+        write_len(0, &mut buf)?;
+        write_len(0, &mut buf)?;
+        Ok(buf)
+    }
+}
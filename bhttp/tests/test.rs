@@ -3,7 +3,7 @@
 
 use std::{io::Cursor, mem::drop};
 
-use bhttp::{Error, Message, Mode};
+use bhttp::{Error, Message, Mode, Padding};
 
 const CHUNKED_HTTP: &[u8] = b"HTTP/1.1 200 OK\r\n\
                               Transfer-Encoding: camel, chunked\r\n\
@@ -104,6 +104,57 @@ fn padded_to_http() {
     assert_eq!(&buf[..], REQUEST);
 }
 
+#[test]
+fn write_padded() {
+    let m = Message::read_bhttp(&mut Cursor::new(REQUEST_KNOWN)).unwrap();
+    for (padding, len) in [
+        (Padding::None, REQUEST_KNOWN.len()),
+        (Padding::Bucket(64), 192),
+        (Padding::PowerOfTwo, 256),
+    ] {
+        let mut buf = Vec::new();
+        m.write_bhttp_padded(Mode::KnownLength, padding, &mut buf)
+            .unwrap();
+        assert_eq!(buf.len(), len);
+        assert_eq!(&buf[..REQUEST_KNOWN.len()], REQUEST_KNOWN);
+        assert!(buf[REQUEST_KNOWN.len()..].iter().all(|&b| b == 0));
+
+        let m = Message::read_bhttp(&mut Cursor::new(&buf[..])).unwrap();
+        let mut buf = Vec::new();
+        m.write_http(&mut buf).unwrap();
+        assert_eq!(&buf[..], REQUEST);
+    }
+
+    let len = Padding::Random(10).padded_len(100);
+    assert!((100..=110).contains(&len));
+
+    // Padding that would overflow is cut short.
+    assert_eq!(Padding::Bucket(usize::MAX).padded_len(100), usize::MAX);
+    assert_eq!(Padding::Bucket(64).padded_len(usize::MAX), usize::MAX);
+    assert_eq!(Padding::PowerOfTwo.padded_len(usize::MAX), usize::MAX);
+    assert_eq!(
+        Padding::Random(usize::MAX).padded_len(usize::MAX),
+        usize::MAX
+    );
+}
+
+#[test]
+fn parse_padding() {
+    assert_eq!("none".parse::<Padding>().unwrap(), Padding::None);
+    assert_eq!(
+        "bucket:512".parse::<Padding>().unwrap(),
+        Padding::Bucket(512)
+    );
+    assert_eq!("pow2".parse::<Padding>().unwrap(), Padding::PowerOfTwo);
+    assert_eq!("random:64".parse::<Padding>().unwrap(), Padding::Random(64));
+    assert!("bucket:0".parse::<Padding>().is_err());
+    assert!("bucket".parse::<Padding>().is_err());
+    assert!("pow2:8".parse::<Padding>().is_err());
+    assert!("bucket:16777216".parse::<Padding>().is_ok());
+    assert!("bucket:16777217".parse::<Padding>().is_err());
+    assert!("random:18446744073709551615".parse::<Padding>().is_err());
+}
+
 #[test]
 fn truncated_to_http() {
     let mut padded = Vec::from(REQUEST_KNOWN);
@@ -224,3 +275,66 @@ fn oversized_max() {
     let e = Message::read_bhttp(&mut Cursor::new(REQUEST)).unwrap_err();
     assert!(matches!(e, Error::Truncated));
 }
+
+/// Decoding content one byte at a time produces the same content as reading it all at once.
+#[cfg(feature = "stream")]
+#[test]
+fn stream_content() {
+    for encoded in [CHUNKED_KNOWN, CHUNKED_INDETERMINATE] {
+        let expected = Message::read_bhttp(&mut Cursor::new(encoded)).unwrap();
+
+        let mut r = Cursor::new(encoded);
+        let (mode, m) = Message::read_bhttp_header(&mut r).unwrap();
+        assert!(m.content().is_empty());
+        let rest = &encoded[usize::try_from(r.position()).unwrap()..];
+
+        let mut decoder = bhttp::ContentDecoder::new(mode);
+        let mut content = Vec::new();
+        for b in rest.chunks(1) {
+            for c in decoder.decode(b) {
+                content.extend_from_slice(c);
+            }
+        }
+        assert!(decoder.is_complete());
+        decoder.finish().unwrap();
+        assert_eq!(&content[..], expected.content());
+    }
+}
+
+/// Content that stops part way through is truncated.
+#[cfg(feature = "stream")]
+#[test]
+fn stream_content_truncated() {
+    let mut r = Cursor::new(CHUNKED_KNOWN);
+    let (mode, _) = Message::read_bhttp_header(&mut r).unwrap();
+    let start = usize::try_from(r.position()).unwrap();
+
+    let mut decoder = bhttp::ContentDecoder::new(mode);
+    let content = decoder.decode(&CHUNKED_KNOWN[start..start + 5]);
+    assert_eq!(content, [&CHUNKED_KNOWN[start + 1..start + 5]]);
+    assert!(matches!(decoder.finish(), Err(Error::Truncated)));
+}
+
+/// A message that is written incrementally reads back the same.
+#[cfg(feature = "stream")]
+#[test]
+fn stream_encode() {
+    let expected = Message::read_bhttp(&mut Cursor::new(CHUNKED_INDETERMINATE)).unwrap();
+    let mut header = Message::response(expected.control().status().unwrap());
+    for field in expected.header().iter() {
+        header.put_header(field.name(), field.value());
+    }
+
+    let mut encoded = Vec::new();
+    header.write_bhttp_header(&mut encoded).unwrap();
+    let mut encoder = bhttp::ContentEncoder::new();
+    for c in expected.content().chunks(7) {
+        encoded.append(&mut encoder.encode(c).unwrap());
+    }
+    assert!(encoder.encode(&[]).unwrap().is_empty());
+    encoded.append(&mut encoder.finish().unwrap());
+
+    let m = Message::read_bhttp(&mut Cursor::new(&encoded[..])).unwrap();
+    assert_eq!(m.content(), expected.content());
+    assert!(m.trailer().is_empty());
+}
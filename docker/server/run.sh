@@ -34,8 +34,16 @@ if [[ -n ${LOCAL_KEY} ]]; then
   CMD="$CMD --local-key"
 fi
 
-if [[ -n ${INJECT_HEADERS} ]]; then 
-  CMD="$CMD --inject-request-headers ${INJECT_HEADERS}"
+if [[ -n ${ROUTES} ]]; then
+  CMD="$CMD --routes ${ROUTES}"
+fi
+
+if [[ -n ${HEADER_RULES} ]]; then
+  CMD="$CMD --header-rules ${HEADER_RULES}"
+fi
+
+if [[ -n ${LOG_ALLOW_HEADERS} ]]; then
+  CMD="$CMD --log-allow-header ${LOG_ALLOW_HEADERS}"
 fi
 
 if [[ -n ${MAA_URL} ]]; then 
@@ -56,6 +64,55 @@ if [[ -n ${KMS_URL} ]]; then
   fi
 fi
 
+if [[ -n ${TLS_CERT} ]]; then
+  CMD="$CMD --tls-cert ${TLS_CERT} --tls-key ${TLS_KEY}"
+  if [[ -n ${TLS_CLIENT_CA} ]]; then
+    CMD="$CMD --tls-client-ca ${TLS_CLIENT_CA}"
+  fi
+fi
+
+if [[ -n ${FLUSH_CHUNKS} ]]; then
+  CMD="$CMD --flush-chunks"
+  if [[ -z ${CHUNK_SIZE} ]]; then
+    CMD="$CMD --align-events"
+  fi
+  if [[ -n ${KEEPALIVE_INTERVAL} ]]; then
+    CMD="$CMD --keepalive-interval ${KEEPALIVE_INTERVAL}"
+  fi
+fi
+
+if [[ -n ${CHUNK_SIZE} ]]; then
+  CMD="$CMD --chunk-size ${CHUNK_SIZE}"
+fi
+
+if [[ -n ${RESPONSE_PADDING} ]]; then
+  CMD="$CMD --response-padding ${RESPONSE_PADDING}"
+fi
+
+if [[ -n ${TARGET_READ_TIMEOUT} ]]; then
+  CMD="$CMD --target-read-timeout ${TARGET_READ_TIMEOUT}"
+fi
+
+if [[ -n ${TARGET_RETRIES} ]]; then
+  CMD="$CMD --target-retries ${TARGET_RETRIES}"
+fi
+
+if [[ -n ${TARGET_CA} ]]; then
+  CMD="$CMD --target-ca ${TARGET_CA}"
+fi
+
+if [[ -n ${TARGET_CLIENT_CERT} ]]; then
+  CMD="$CMD --target-client-cert ${TARGET_CLIENT_CERT} --target-client-key ${TARGET_CLIENT_KEY}"
+fi
+
+if [[ -n ${KMS_CERT} ]]; then
+  CMD="$CMD --kms-cert ${KMS_CERT}"
+fi
+
+if [[ -n ${KMS_CLIENT_CERT} ]]; then
+  CMD="$CMD --kms-client-cert ${KMS_CLIENT_CERT} --kms-client-key ${KMS_CLIENT_KEY}"
+fi
+
 # Run OHTTP server
 echo "Running $CMD..."
 eval $CMD
\ No newline at end of file
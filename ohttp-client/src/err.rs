@@ -0,0 +1,38 @@
+use http::StatusCode;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("a problem occurred with binary HTTP: {0}")]
+    Bhttp(#[from] bhttp::Error),
+    #[error("an invalid header field was received: {0}")]
+    Header(String),
+    #[error("the key configuration is not valid hex: {0}")]
+    Hex(#[from] hex::FromHexError),
+    #[error("the request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("the request is not valid: {0}")]
+    InvalidRequest(#[from] http::Error),
+    #[error("the list of keys from the KMS is not valid: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("the gateway rejected the key configuration: {0}")]
+    KeyRejected(String),
+    #[error("the KMS did not list any keys")]
+    KmsNoKeys,
+    #[error("the KMS returned an unexpected status code: {0}")]
+    KmsStatus(StatusCode),
+    #[error("max retries reached, giving up. Cannot reach key management service")]
+    KmsUnreachable,
+    #[error("none of the {0} key configurations can be used")]
+    NoUsableKey(usize),
+    #[error("a problem occurred with Oblivious HTTP: {0}")]
+    Ohttp(#[from] ohttp::Error),
+    #[error("the receipt for the key is not valid: {0}")]
+    Receipt(#[from] verifier::Error),
+    #[error("the receipt for the key is not endorsed by the KMS or its signature does not verify")]
+    ReceiptRejected,
+    #[error("HTTP request failed with status {0} and message: {1}")]
+    Status(StatusCode, String),
+}
+
+pub type Res<T> = Result<T, Error>;
@@ -0,0 +1,719 @@
+#![deny(clippy::pedantic)]
+
+//! A client for Oblivious HTTP, which sends requests to a gateway through a
+//! relay.  An `ObliviousClient` gets the key configuration of the gateway,
+//! encapsulates each request, and decapsulates the response as it arrives.
+
+mod err;
+
+use std::{
+    io::Cursor,
+    pin::Pin,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use bhttp::{ContentEncoder, Message, Mode, Padding};
+use bytes::Bytes;
+use futures_util::{
+    stream::{self, once, unfold, Stream},
+    StreamExt, TryStreamExt,
+};
+use http::{
+    header::{CACHE_CONTROL, CONTENT_TYPE},
+    HeaderMap, HeaderName, HeaderValue, StatusCode,
+};
+use ohttp::{rechunk, ChunkStream, ClientRequest, ClientResponse, KeyConfig, StreamOptions};
+use reqwest::{Certificate, Client};
+use serde::Deserialize;
+use tracing::{info, trace, warn};
+
+pub use crate::err::{Error, Res};
+
+/// The media type of a request that is encapsulated all at once.
+const REQUEST_CONTENT_TYPE: &str = "message/ohttp-req";
+/// The media type of a request that is encapsulated in chunks.
+const CHUNKED_REQUEST_CONTENT_TYPE: &str = "message/ohttp-chunked-req";
+/// The media type of a response that is encapsulated all at once.
+/// The gateway uses this for errors; other responses are chunked.
+const RESPONSE_CONTENT_TYPE: &str = "message/ohttp-res";
+/// The media type of a list of key configurations, from RFC 9540.
+const KEYS_CONTENT_TYPE: &str = "application/ohttp-keys";
+/// The media type of a problem report, from RFC 9457.
+const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+/// The problem type for key configuration errors, from RFC 9458 section 5.3.
+const PROBLEM_TYPE_OHTTP_KEY: &str = "https://iana.org/assignments/http-problem-types#ohttp-key";
+/// How long a key configuration is used when the key source doesn't say.
+const DEFAULT_KEY_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Where an `ObliviousClient` gets the key configuration of the gateway.
+#[derive(Debug, Clone)]
+pub enum KeySource {
+    /// An encoded list of key configurations, as `application/ohttp-keys`.
+    Config(Vec<u8>),
+    /// A URL that serves `application/ohttp-keys`, such as the
+    /// `/.well-known/ohttp-gateway` resource of the gateway.
+    Discovery(String),
+    /// A KMS that lists public keys with receipts.  `cert` is the service
+    /// certificate (PEM) of the KMS, which both the connection to the KMS and
+    /// the receipt for the key are checked against.
+    Kms { url: String, cert: String },
+}
+
+/// An entry in the list of public keys from the KMS.
+#[derive(Deserialize)]
+struct KmsKeyConfiguration {
+    #[serde(rename = "publicKey")]
+    key_config: String,
+    receipt: String,
+}
+
+/// A key configuration that was fetched from the key source.
+struct CachedConfig {
+    config: KeyConfig,
+    /// When the configuration has to be fetched again, if ever.
+    expires: Option<Instant>,
+}
+
+/// How long a response can be cached, from its `Cache-Control` header field.
+fn max_age(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .find_map(|directive| {
+            let (name, value) = directive.split_once('=')?;
+            if name.trim().eq_ignore_ascii_case("max-age") {
+                value.trim().trim_matches('"').parse().ok()
+            } else {
+                None
+            }
+        })
+        .map(Duration::from_secs)
+}
+
+/// Whether a problem report from the gateway says that it rejected the key
+/// configuration, which is what it does when the key has been rotated.
+fn is_key_problem(problem: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(problem).map_or(false, |problem| {
+        problem.get("type").and_then(serde_json::Value::as_str) == Some(PROBLEM_TYPE_OHTTP_KEY)
+    })
+}
+
+/// Check that a receipt from the KMS is endorsed by its service certificate
+/// and that the signature over the receipt verifies.
+fn verify_receipt(receipt: &str, cert: &str) -> Res<()> {
+    if verifier::verify(receipt, cert)? {
+        Ok(())
+    } else {
+        Err(Error::ReceiptRejected)
+    }
+}
+
+/// Choose from an encoded list of key configurations.  Like
+/// `ClientRequest::from_encoded_config_list`, this uses the last usable
+/// configuration, but it logs why each of the others can't be used.
+fn select_config(list: &[u8]) -> Res<KeyConfig> {
+    let report = KeyConfig::decode_list_report(list);
+    let count = report.len();
+    let mut usable = None;
+    for entry in report {
+        let key_id = entry
+            .key_id
+            .map_or_else(|| "?".to_owned(), |id| id.to_string());
+        let kem = entry
+            .kem
+            .map_or_else(|| "?".to_owned(), |kem| format!("{kem:#06x}"));
+        if !entry.stripped.is_empty() {
+            info!(
+                "Key config {key_id} (KEM {kem}): unsupported suites {:?}",
+                entry.stripped
+            );
+        }
+        match entry.config {
+            Ok(config) => usable = Some(config),
+            Err(e) => warn!(
+                "Key config {key_id} (KEM {kem}, suites {:x?}): {e}",
+                entry.suites
+            ),
+        }
+    }
+    usable.ok_or(Error::NoUsableKey(count))
+}
+
+/// Get the list of public keys from the KMS, waiting while the KMS prepares
+/// receipts.
+async fn get_kms_config(kms_url: &str, cert: &str) -> Res<String> {
+    // Create a client that only trusts the service certificate
+    let client = Client::builder()
+        .tls_built_in_root_certs(false)
+        .add_root_certificate(Certificate::from_pem(cert.as_bytes())?)
+        .build()?;
+
+    info!("Contacting key management service at {kms_url}...");
+    let max_retries = 3;
+    let mut retries = 0;
+    let url = format!("{kms_url}/listpubkeys");
+
+    loop {
+        let response = client.get(&url).send().await?.error_for_status()?;
+
+        // We may have to wait for receipt to be ready
+        match response.status() {
+            StatusCode::ACCEPTED => {
+                if retries < max_retries {
+                    retries += 1;
+                    trace!(
+                        "Received 202 status code, retrying... (attempt {retries}/{max_retries})"
+                    );
+                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                } else {
+                    return Err(Error::KmsUnreachable);
+                }
+            }
+            StatusCode::OK => return Ok(response.text().await?),
+            status => return Err(Error::KmsStatus(status)),
+        }
+    }
+}
+
+/// Build a binary HTTP request from an `http::Request`.  Without a scheme in
+/// the URI, the request uses `https`.
+#[must_use]
+pub fn message_from_http<B: AsRef<[u8]>>(request: http::Request<B>) -> Message {
+    let (parts, content) = request.into_parts();
+    let uri = parts.uri;
+    let mut message = Message::request(
+        parts.method.as_str().as_bytes().to_vec(),
+        uri.scheme_str().unwrap_or("https").as_bytes().to_vec(),
+        uri.authority()
+            .map_or_else(Vec::new, |a| a.as_str().as_bytes().to_vec()),
+        uri.path_and_query()
+            .map_or_else(|| b"/".to_vec(), |p| p.as_str().as_bytes().to_vec()),
+    );
+    for (name, value) in &parts.headers {
+        message.put_header(name.as_str(), value.as_bytes());
+    }
+    message.write_content(content);
+    message
+}
+
+/// A response to an oblivious request.  For a response that is streamed
+/// by the gateway, the status and header fields are those of the outer
+/// response, and the content is decapsulated as it arrives.
+pub struct ObliviousResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: ChunkStream,
+}
+
+impl ObliviousResponse {
+    /// Take the status and header fields of a complete binary HTTP response.
+    fn from_message(message: &Message) -> Res<Self> {
+        let status = message
+            .control()
+            .status()
+            .map(|s| StatusCode::from_u16(s.code()))
+            .transpose()
+            .map_err(|e| Error::Header(e.to_string()))?
+            .ok_or_else(|| Error::Header("the response has no status".into()))?;
+        let mut headers = HeaderMap::new();
+        for field in message.header().iter() {
+            let name = HeaderName::from_bytes(field.name());
+            let value = HeaderValue::from_bytes(field.value());
+            match (name, value) {
+                (Ok(name), Ok(value)) => {
+                    headers.append(name, value);
+                }
+                _ => return Err(Error::Header(String::from_utf8_lossy(field.name()).into())),
+            }
+        }
+        let content = Bytes::copy_from_slice(message.content());
+        Ok(Self {
+            status,
+            headers,
+            body: Box::pin(stream::iter([Ok(content)])),
+        })
+    }
+
+    #[must_use]
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    #[must_use]
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// The next piece of content, or `None` when there is no more.
+    ///
+    /// # Errors
+    /// If a chunk could not be decapsulated.
+    pub async fn chunk(&mut self) -> Res<Option<Bytes>> {
+        Ok(self.body.next().await.transpose()?)
+    }
+
+    /// The content, as a stream of decapsulated chunks.
+    #[must_use]
+    pub fn bytes_stream(self) -> ChunkStream {
+        self.body
+    }
+
+    /// Wait for all of the content.
+    ///
+    /// # Errors
+    /// If a chunk could not be decapsulated.
+    pub async fn bytes(self) -> Res<Vec<u8>> {
+        let content = self
+            .body
+            .try_fold(Vec::new(), |mut all, chunk| async move {
+                all.extend_from_slice(&chunk);
+                Ok(all)
+            })
+            .await?;
+        Ok(content)
+    }
+}
+
+/// A client that sends oblivious requests through a relay.
+///
+/// The key configuration is fetched when the first request is sent, and
+/// then used until it expires.  If the gateway rejects it, which happens
+/// when the key has been rotated, the key configuration is fetched again and
+/// the request is sent once more.
+///
+/// ```no_run
+/// # async fn f() -> ohttp_client::Res<()> {
+/// use ohttp_client::{KeySource, ObliviousClient};
+///
+/// let keys = KeySource::Discovery("https://gateway.example/.well-known/ohttp-gateway".into());
+/// let client = ObliviousClient::new("https://relay.example/", keys);
+/// let request = http::Request::get("https://target.example/").body(Vec::new())?;
+/// let response = client.send(request).await?;
+/// println!("{}", response.status());
+/// # Ok(())
+/// # }
+/// ```
+pub struct ObliviousClient {
+    relay: String,
+    keys: KeySource,
+    http: Client,
+    outer_headers: Vec<(String, String)>,
+    padding: Padding,
+    chunk_size: Option<usize>,
+    cached: Mutex<Option<CachedConfig>>,
+}
+
+impl ObliviousClient {
+    /// Create a client that sends requests to `relay`, for the gateway that
+    /// `keys` identifies.
+    #[must_use]
+    pub fn new(relay: impl Into<String>, keys: KeySource) -> Self {
+        Self {
+            relay: relay.into(),
+            keys,
+            http: Client::new(),
+            outer_headers: Vec::new(),
+            padding: Padding::None,
+            chunk_size: None,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Use `http` for requests to the relay and for key discovery, so that
+    /// it can be configured with certificates or timeouts.
+    #[must_use]
+    pub fn with_http_client(mut self, http: Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Add a header field to the outer request.  The relay sees this.
+    #[must_use]
+    pub fn with_outer_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.outer_headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Pad requests that are encapsulated all at once.
+    #[must_use]
+    pub fn with_padding(mut self, padding: Padding) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// Send requests in chunks of exactly this many bytes, except for the
+    /// last, using chunked Oblivious HTTP.
+    #[must_use]
+    pub fn with_chunk_size(mut self, size: usize) -> Self {
+        self.chunk_size = Some(size).filter(|&size| size > 0);
+        self
+    }
+
+    /// Get a key configuration for the gateway, from the key source unless
+    /// one that hasn't expired was already fetched.
+    ///
+    /// # Errors
+    /// If the key source can't be reached, or doesn't have a usable key.
+    pub async fn key_config(&self) -> Res<KeyConfig> {
+        if let Some(config) = self.cached_config() {
+            return Ok(config);
+        }
+        let (config, ttl) = self.fetch_key_config().await?;
+        self.cache_config(config.clone(), ttl);
+        Ok(config)
+    }
+
+    fn cache_config(&self, config: KeyConfig, ttl: Option<Duration>) {
+        let expires = ttl.map(|ttl| Instant::now() + ttl);
+        *self.cached.lock().unwrap() = Some(CachedConfig { config, expires });
+    }
+
+    fn cached_config(&self) -> Option<KeyConfig> {
+        let cached = self.cached.lock().unwrap();
+        let cached = cached.as_ref()?;
+        let fresh = cached.expires.map_or(true, |t| Instant::now() < t);
+        fresh.then(|| cached.config.clone())
+    }
+
+    /// Whether a key configuration that is rejected can be replaced by
+    /// fetching it again.
+    fn can_refresh(&self) -> bool {
+        !matches!(self.keys, KeySource::Config(_))
+    }
+
+    /// Fetch a key configuration from the key source, along with how long it
+    /// can be used for.
+    async fn fetch_key_config(&self) -> Res<(KeyConfig, Option<Duration>)> {
+        match &self.keys {
+            KeySource::Config(list) => Ok((select_config(list)?, None)),
+            KeySource::Discovery(url) => {
+                info!("Fetching key configurations from {url}");
+                let response = self
+                    .http
+                    .get(url)
+                    .header(http::header::ACCEPT, KEYS_CONTENT_TYPE)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                let ttl = max_age(response.headers()).unwrap_or(DEFAULT_KEY_TTL);
+                let list = response.bytes().await?;
+                Ok((select_config(&list)?, Some(ttl)))
+            }
+            KeySource::Kms { url, cert } => {
+                let config = get_kms_config(url, cert).await?;
+                let mut kms_configs: Vec<KmsKeyConfiguration> = serde_json::from_str(&config)?;
+                let kms_config = kms_configs.pop().ok_or(Error::KmsNoKeys)?;
+                info!("Establishing trust in key management service...");
+                verify_receipt(&kms_config.receipt, cert)?;
+                info!("The receipt for the generation of the OHTTP key is valid.");
+                let encoded_config = hex::decode(&kms_config.key_config)?;
+                Ok((KeyConfig::decode(&encoded_config)?, Some(DEFAULT_KEY_TTL)))
+            }
+        }
+    }
+
+    async fn client_request(&self) -> Res<ClientRequest> {
+        let mut config = self.key_config().await?;
+        let request = ClientRequest::from_config(&mut config)?;
+        trace!("Created ohttp client request");
+        Ok(request)
+    }
+
+    /// Send a request.
+    ///
+    /// # Errors
+    /// If the request can't be encapsulated or sent, or if the relay or the
+    /// gateway reject it.
+    pub async fn send<B: AsRef<[u8]>>(&self, request: http::Request<B>) -> Res<ObliviousResponse> {
+        self.send_message(&message_from_http(request)).await
+    }
+
+    /// Send a binary HTTP request.
+    ///
+    /// # Errors
+    /// If the request can't be encapsulated or sent, or if the relay or the
+    /// gateway reject it.
+    pub async fn send_message(&self, request: &Message) -> Res<ObliviousResponse> {
+        let mut request_buf = Vec::new();
+        request.write_bhttp_padded(Mode::KnownLength, self.padding, &mut request_buf)?;
+
+        match self.send_encoded(&request_buf).await {
+            Err(Error::KeyRejected(problem)) if self.can_refresh() => {
+                info!("The key configuration was rejected, fetching it again: {problem}");
+                // This encapsulates the request again, with a new HPKE context.
+                self.send_encoded(&request_buf).await
+            }
+            res => res,
+        }
+    }
+
+    /// Encapsulate and send an encoded binary HTTP request.
+    async fn send_encoded(&self, request_buf: &[u8]) -> Res<ObliviousResponse> {
+        let ohttp_request = self.client_request().await?;
+        let Some(size) = self.chunk_size else {
+            let (enc_request, client_response) = ohttp_request.encapsulate(request_buf)?;
+            trace!(
+                "Encapsulated the OHTTP request {}",
+                hex::encode(&enc_request[..enc_request.len().min(60)])
+            );
+            return self
+                .post(enc_request.into(), REQUEST_CONTENT_TYPE, client_response)
+                .await;
+        };
+
+        // The request is complete, so the chunks can be collected and sent at once.
+        let request_buf = request_buf.to_vec();
+        let input = rechunk(once(async { Ok::<_, ohttp::Error>(request_buf) }), size);
+        let (enc_request, client_response) = ohttp_request.encapsulate_stream(input)?;
+        let enc_request = enc_request
+            .try_fold(Vec::new(), |mut all, chunk| async move {
+                all.extend_from_slice(&chunk);
+                Ok(all)
+            })
+            .await?;
+        self.post(
+            enc_request.into(),
+            CHUNKED_REQUEST_CONTENT_TYPE,
+            client_response,
+        )
+        .await
+    }
+
+    /// Send a request with content that is sent as it becomes available.
+    /// The content of `request` is not used.  Each piece of `content` is sent
+    /// as a chunk right away, so the response can arrive while the request
+    /// is still being sent.
+    ///
+    /// Content can't be sent twice, so if the gateway rejects the key
+    /// configuration, this fails, and only the next request uses a new one.
+    ///
+    /// # Errors
+    /// If the request can't be encapsulated or sent, or if the relay or the
+    /// gateway reject it.  An error in `content` ends the request without a
+    /// final chunk, so the gateway can tell that it is incomplete.
+    pub async fn send_streaming<S, B>(
+        &self,
+        request: &Message,
+        content: S,
+    ) -> Res<ObliviousResponse>
+    where
+        S: Stream<Item = std::io::Result<B>> + Send + 'static,
+        B: AsRef<[u8]>,
+    {
+        let mut header = Vec::new();
+        request.write_bhttp_header(&mut header)?;
+
+        let content = Box::pin(content);
+        let content = unfold(Some((content, ContentEncoder::new())), |state| async move {
+            let (mut content, mut encoder) = state?;
+            match content.next().await {
+                Some(Ok(c)) => Some((encoder.encode(c.as_ref()), Some((content, encoder)))),
+                Some(Err(e)) => Some((Err(bhttp::Error::Io(e)), None)),
+                None => Some((encoder.finish(), None)),
+            }
+        });
+
+        let input = once(async { Ok(header) }).chain(content);
+        let ohttp_request = self.client_request().await?;
+        let (enc_request, client_response) =
+            ohttp_request.encapsulate_stream_with(input, StreamOptions::default())?;
+        self.post(
+            reqwest::Body::wrap_stream(enc_request),
+            CHUNKED_REQUEST_CONTENT_TYPE,
+            client_response,
+        )
+        .await
+    }
+
+    /// Post an encapsulated request to the relay and decapsulate the response.
+    async fn post(
+        &self,
+        enc_request: reqwest::Body,
+        content_type: &str,
+        client_response: ClientResponse,
+    ) -> Res<ObliviousResponse> {
+        let mut builder = self
+            .http
+            .post(&self.relay)
+            .header(CONTENT_TYPE, content_type);
+        for (name, value) in &self.outer_headers {
+            trace!("Adding {name}: {value}");
+            builder = builder.header(name, value);
+        }
+
+        let response = builder.body(enc_request).send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let is_problem = response
+                .headers()
+                .get(CONTENT_TYPE)
+                .map_or(false, |v| v == PROBLEM_CONTENT_TYPE);
+            let body = response.text().await?;
+            if is_problem && is_key_problem(&body) {
+                self.cached.lock().unwrap().take();
+                return Err(Error::KeyRejected(body));
+            }
+            return Err(Error::Status(status, body));
+        }
+        trace!("Posted the OHTTP request to {}", self.relay);
+        trace!("response status: {status}");
+        for (name, value) in response.headers() {
+            trace!("{name}: {}", String::from_utf8_lossy(value.as_bytes()));
+        }
+
+        // Errors from the target are returned as a complete encapsulated response.
+        let is_complete = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .map_or(false, |v| v == RESPONSE_CONTENT_TYPE);
+        if is_complete {
+            let enc_response = response.bytes().await?;
+            let response = client_response.decapsulate(&enc_response)?;
+            let message = Message::read_bhttp(&mut Cursor::new(&response[..]))?;
+            return ObliviousResponse::from_message(&message);
+        }
+
+        let headers = response.headers().clone();
+        let chunks: Pin<Box<dyn Stream<Item = Result<Bytes, ohttp::Error>> + Send>> =
+            Box::pin(unfold(Some(response), |response| async move {
+                let mut response = response?;
+                match response.chunk().await {
+                    Ok(Some(chunk)) => Some((Ok(chunk), Some(response))),
+                    Ok(None) => None,
+                    // Pass the error on, then stop.
+                    Err(e) => Some((Err(ohttp::Error::Stream(e.to_string())), None)),
+                }
+            }));
+        Ok(ObliviousResponse {
+            status,
+            headers,
+            body: client_response.decapsulate_stream(chunks).await,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        is_key_problem, max_age, message_from_http, select_config, verify_receipt, Error,
+        PROBLEM_TYPE_OHTTP_KEY,
+    };
+    use http::{header::CACHE_CONTROL, HeaderMap};
+    use ohttp::{
+        hpke::{Aead, Kdf, Kem},
+        KeyConfig, SymmetricSuite,
+    };
+    use openssl::{
+        asn1::Asn1Time,
+        base64,
+        ec::{EcGroup, EcKey},
+        ecdsa::EcdsaSig,
+        hash::MessageDigest,
+        nid::Nid,
+        pkey::PKey,
+        x509::{X509Builder, X509NameBuilder},
+    };
+
+    #[test]
+    fn convert_request() {
+        let request = http::Request::post("https://example.com/v1/score?x=1")
+            .header("content-type", "text/plain")
+            .body(b"hello".to_vec())
+            .unwrap();
+        let message = message_from_http(request);
+        let control = message.control();
+        assert_eq!(control.method(), Some(&b"POST"[..]));
+        assert_eq!(control.scheme(), Some(&b"https"[..]));
+        assert_eq!(control.authority(), Some(&b"example.com"[..]));
+        assert_eq!(control.path(), Some(&b"/v1/score?x=1"[..]));
+        assert_eq!(
+            message.header().get(b"content-type"),
+            Some(&b"text/plain"[..])
+        );
+        assert_eq!(message.content(), b"hello");
+
+        // Without a scheme or authority.
+        let request = http::Request::get("/").body(Vec::new()).unwrap();
+        let message = message_from_http(request);
+        assert_eq!(message.control().scheme(), Some(&b"https"[..]));
+        assert_eq!(message.control().authority(), None);
+    }
+
+    #[test]
+    fn select_usable_config() {
+        ohttp::init();
+        let suites = vec![SymmetricSuite::new(Kdf::HkdfSha256, Aead::Aes128Gcm)];
+        let config = KeyConfig::new(3, Kem::X25519Sha256, suites).unwrap();
+        let mut list = KeyConfig::encode_list(&[config]).unwrap();
+        assert_eq!(select_config(&list).unwrap().key_id(), 3);
+
+        // Make the KEM unknown.
+        list[3] = 0xff;
+        assert!(matches!(select_config(&list), Err(Error::NoUsableKey(1))));
+    }
+
+    #[test]
+    fn cache_lifetime() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(max_age(&headers), None);
+        headers.insert(CACHE_CONTROL, "public, Max-Age=300".parse().unwrap());
+        assert_eq!(max_age(&headers).unwrap().as_secs(), 300);
+        headers.insert(CACHE_CONTROL, "no-store".parse().unwrap());
+        assert_eq!(max_age(&headers), None);
+    }
+
+    #[test]
+    fn key_problem() {
+        let rejected = format!(r#"{{"type":"{PROBLEM_TYPE_OHTTP_KEY}","status":400}}"#);
+        assert!(is_key_problem(&rejected));
+        assert!(!is_key_problem(
+            r#"{"title":"Key temporarily unavailable","status":503}"#
+        ));
+        assert!(!is_key_problem("not json"));
+    }
+
+    /// A receipt that is signed by a certificate that the service certificate
+    /// endorses, but where the signature is not over the receipt.
+    #[test]
+    fn receipt_bad_signature() {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = EcKey::generate(&group).unwrap();
+        let pkey = PKey::from_ec_key(key.clone()).unwrap();
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "kms").unwrap();
+        let name = name.build();
+        let mut cert = X509Builder::new().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(&pkey).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        cert.sign(&pkey, MessageDigest::sha256()).unwrap();
+        let cert = String::from_utf8(cert.build().to_pem().unwrap()).unwrap();
+
+        let signature = EcdsaSig::sign(&[0; 32], &key).unwrap();
+        let receipt = serde_json::json!({
+            "signature": base64::encode_block(&signature.to_der().unwrap()),
+            "cert": cert,
+            "leaf_components": {
+                "write_set_digest": hex::encode([1; 32]),
+                "commit_evidence": "ce:2.1:00",
+                "claims_digest": hex::encode([2; 32]),
+            },
+            "proof": [],
+        })
+        .to_string();
+
+        assert!(matches!(
+            verify_receipt(&receipt, &cert),
+            Err(Error::ReceiptRejected)
+        ));
+    }
+}
@@ -1,21 +1,24 @@
-use bhttp::{Message, Mode};
+use bhttp::{Message, Padding};
 use clap::Parser;
-use futures_util::{stream::unfold, StreamExt};
-use ohttp::ClientRequest;
-use reqwest::Client;
-use serde::Deserialize;
+use futures_util::stream::unfold;
+use ohttp_client::{KeySource, ObliviousClient, ObliviousResponse};
 use std::{
     fs::{self, File},
     io::{self, Cursor, Read, Write},
     ops::Deref,
-    path::PathBuf,
+    path::{Path, PathBuf},
+    pin::Pin,
     str::FromStr,
 };
+use tokio::io::{AsyncRead, AsyncReadExt};
 use tracing::{error, info, trace};
 use tracing_subscriber::{EnvFilter, FmtSubscriber};
 
 type Res<T> = Result<T, Box<dyn std::error::Error>>;
 
+/// The most content that is read from streamed input at once.
+const INPUT_READ_SIZE: usize = 16 * 1024;
+
 #[derive(Debug, Clone)]
 /// This allows a `HexArg` to be created from a string slice (`&str`) by decoding
 /// the string as hexadecimal.
@@ -51,6 +54,11 @@ struct Args {
     #[arg(long, short = 'c')]
     config: Option<HexArg>,
 
+    /// URL to get key configurations from, such as the
+    /// `/.well-known/ohttp-gateway` resource of the gateway
+    #[arg(long, conflicts_with = "config")]
+    discovery_url: Option<String>,
+
     /// URL of the KMS to obtain HPKE keys from
     #[arg(long, short = 'f')]
     kms_url: Option<String>,
@@ -83,6 +91,21 @@ struct Args {
     /// List of headers in the outer request
     #[arg(long, short = 'O')]
     outer_headers: Option<Vec<String>>,
+
+    /// Pad the request: `none`, `bucket:<bytes>`, `pow2`, or `random:<bytes>`.
+    #[arg(long, default_value = "none")]
+    padding: Padding,
+
+    /// Send the request in chunks of exactly this many bytes, except for the
+    /// last, using chunked oblivious HTTP.
+    #[arg(long)]
+    chunk_size: Option<usize>,
+
+    /// Stream the content of a POST request from this file, or from `stdin`
+    /// for `-`, as it is read.  The response is written as it arrives, while
+    /// the request is still being sent.
+    #[arg(long, conflicts_with_all = ["form_fields", "chunk_size"])]
+    stream_input: Option<PathBuf>,
 }
 
 /// Writes the request line for an HTTP POST request to the provided buffer.
@@ -206,12 +229,12 @@ fn create_multipart_request(
 }
 
 /// Prepares a http message based on the `is_bhttp` flag and other parameters.
-fn create_request_buffer(
+fn create_request(
     is_bhttp: bool,
     target_path: &str,
     headers: &Option<Vec<String>>,
     form_fields: &Option<Vec<String>>,
-) -> Res<Vec<u8>> {
+) -> Res<Message> {
     let request = create_multipart_request(target_path, headers, form_fields)?;
     let mut cursor = Cursor::new(request);
 
@@ -220,165 +243,83 @@ fn create_request_buffer(
     } else {
         Message::read_http(&mut cursor)?
     };
-
-    let mut request_buf = Vec::new();
-    request.write_bhttp(Mode::KnownLength, &mut request_buf)?;
-    Ok(request_buf)
+    Ok(request)
 }
 
-// Get key configuration from KMS
-async fn get_kms_config(kms_url: String, cert: &str) -> Res<String> {
-    // Create a client with the CA certificate
-    let client = Client::builder()
-        .add_root_certificate(reqwest::Certificate::from_pem(cert.as_bytes())?)
-        .build()?;
-
-    info!("Contacting key management service at {kms_url}...");
-    let max_retries = 3;
-    let mut retries = 0;
-    let url = kms_url + "/listpubkeys";
-
-    loop {
-        // Make the GET request
-        let response = client.get(url.clone()).send().await?.error_for_status()?;
-
-        // We may have to wait for receipt to be ready
-        match response.status().as_u16() {
-            202 => {
-                if retries < max_retries {
-                    retries += 1;
-                    trace!(
-                        "Received 202 status code, retrying... (attempt {}/{})",
-                        retries,
-                        max_retries
-                    );
-                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-                } else {
-                    Err("Max retries reached, giving up. Cannot reach key management service")?;
-                }
-            }
-            200 => {
-                let body = response.text().await?;
-                assert!(!body.is_empty());
-                return Ok(body);
-            }
-            e => {
-                Err(format!("KMS returned unexpected {} status code.", e))?;
-            }
-        }
+/// Chooses where to get keys from: the KMS, a discovery URL, or the static
+/// config in Args.
+fn key_source(args: &Args) -> Res<KeySource> {
+    if let (Some(kms_url), Some(kms_cert)) = (&args.kms_url, &args.kms_cert) {
+        let cert = fs::read_to_string(kms_cert)?;
+        Ok(KeySource::Kms {
+            url: kms_url.clone(),
+            cert,
+        })
+    } else if let Some(url) = &args.discovery_url {
+        Ok(KeySource::Discovery(url.clone()))
+    } else {
+        let config = args.config.clone().expect("Config expected.");
+        Ok(KeySource::Config(config.0))
     }
 }
 
-#[derive(Deserialize)]
-struct KmsKeyConfiguration {
-    #[serde(rename = "publicKey")]
-    key_config: String,
-    receipt: String,
-}
-
-/// Reads a json containing key configurations with receipts and constructs
-/// a single use client sender from the first supported configuration.
-trait ClientRequestBuilder {
-    fn from_kms_config(config: &str, cert: &str) -> Res<ClientRequest>;
-}
-
-impl ClientRequestBuilder for ClientRequest {
-    /// Reads a json containing key configurations with receipts and constructs
-    /// a single use client sender from the first supported configuration.
-    fn from_kms_config(config: &str, cert: &str) -> Res<ClientRequest> {
-        let mut kms_configs: Vec<KmsKeyConfiguration> = serde_json::from_str(config)?;
-        let kms_config = match kms_configs.pop() {
-            Some(config) => config,
-            None => return Err("No KMS configuration found".into()),
-        };
-        info!("{}", "Establishing trust in key management service...");
-        let _ = verifier::verify(&kms_config.receipt, cert)?;
-        info!(
-            "{}",
-            "The receipt for the generation of the OHTTP key is valid."
-        );
-        let encoded_config = hex::decode(&kms_config.key_config)?;
-        Ok(ClientRequest::from_encoded_config(&encoded_config)?)
+/// Creates a client from the arguments.
+fn create_client(args: &Args) -> Res<ObliviousClient> {
+    let mut client =
+        ObliviousClient::new(args.url.clone(), key_source(args)?).with_padding(args.padding);
+    if let Some(size) = args.chunk_size {
+        client = client.with_chunk_size(size);
     }
+    for header in args.outer_headers.iter().flatten() {
+        let (name, value) = header.split_once(':').ok_or("header needs a colon")?;
+        client = client.with_outer_header(name, value);
+    }
+    Ok(client)
 }
 
-/// Creates an OHTTP client from the static config provided in Args.
-///
-fn create_request_from_encoded_config_list(config: &Option<HexArg>) -> Res<ohttp::ClientRequest> {
-    let config = config.clone().expect("Config expected.");
-    Ok(ohttp::ClientRequest::from_encoded_config_list(&config)?)
-}
-
-/// Creates an OHTTP client from KMS.
-///
-async fn create_request_from_kms_config(
-    kms_url: &String,
-    kms_cert: &PathBuf,
-) -> Res<ohttp::ClientRequest> {
-    let cert = fs::read_to_string(kms_cert)?;
-    let config = get_kms_config(kms_url.to_owned(), &cert).await?;
-    ClientRequest::from_kms_config(&config, &cert)
-}
-
-async fn post_request(
-    url: &String,
-    outer_headers: &Option<Vec<String>>,
-    enc_request: Vec<u8>,
-) -> Res<reqwest::Response> {
-    let client = reqwest::ClientBuilder::new().build()?;
-
-    let mut builder = client
-        .post(url)
-        .header("content-type", "message/ohttp-chunked-req");
-
-    // Add outer headers
-    trace!("Outer request headers:");
-    let outer_headers = outer_headers.clone();
-    if let Some(headers) = outer_headers {
-        for header in headers {
-            let (key, value) = header.split_once(':').unwrap();
-            trace!("Adding {key}: {value}");
-            builder = builder.header(key, value);
-        }
+/// Sends a POST request with content that is read from `input` as it
+/// becomes available, or from `stdin` for `-`.  Each read is sent as a chunk
+/// right away, so the response can arrive while the request is still open.
+async fn send_input(
+    client: &ObliviousClient,
+    target_path: &str,
+    headers: &Option<Vec<String>>,
+    input: &Path,
+) -> Res<ObliviousResponse> {
+    let mut message = Message::request(
+        b"POST".to_vec(),
+        b"https".to_vec(),
+        Vec::new(),
+        target_path.as_bytes().to_vec(),
+    );
+    for header in headers.iter().flatten() {
+        let (name, value) = header.split_once(':').ok_or("header needs a colon")?;
+        message.put_header(name.trim().to_ascii_lowercase(), value.trim());
     }
 
-    match builder.body(enc_request).send().await {
-        Ok(response) => {
-            if response.status().is_success() {
-                trace!("response status: {}\n", response.status());
-                trace!("Response headers:");
-                for (key, value) in response.headers() {
-                    trace!(
-                        "{}: {}",
-                        key,
-                        std::str::from_utf8(value.as_bytes()).unwrap()
-                    );
-                }
-                Ok(response)
-            } else {
-                let error_msg = format!(
-                    "HTTP request failed with status {} and message: {}",
-                    response.status(),
-                    response.text().await?
-                );
-                error!(error_msg);
-                Err(error_msg.into())
+    let reader: Pin<Box<dyn AsyncRead + Send>> = if input == Path::new("-") {
+        Box::pin(tokio::io::stdin())
+    } else {
+        Box::pin(tokio::fs::File::open(input).await?)
+    };
+    let content = unfold(Some(reader), |reader| async move {
+        let mut reader = reader?;
+        let mut buf = vec![0; INPUT_READ_SIZE];
+        match reader.read(&mut buf).await {
+            Ok(0) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok(buf), Some(reader)))
             }
+            Err(e) => Some((Err(e), None)),
         }
-        Err(e) => {
-            error!("Request failed: {}", e);
-            Err(Box::new(e))
-        }
-    }
+    });
+    Ok(client.send_streaming(&message, content).await?)
 }
 
-/// Decapsulate the http response
+/// Writes the content of the response.
 /// The response can be saved to a file or printed to stdout, based on the value of args.output
-async fn handle_response(
-    response: reqwest::Response,
-    client_response: ohttp::ClientResponse,
-    output: &Option<PathBuf>,
-) -> Res<()> {
+async fn handle_response(mut response: ObliviousResponse, output: &Option<PathBuf>) -> Res<()> {
     let mut output: Box<dyn io::Write> = if let Some(outfile) = output {
         match File::create(outfile) {
             Ok(file) => Box::new(file),
@@ -390,20 +331,22 @@ async fn handle_response(
         Box::new(std::io::stdout())
     };
 
-    let stream = Box::pin(unfold(response, |mut response| async move {
-        match response.chunk().await {
-            Ok(Some(chunk)) => Some((Ok(chunk.to_vec()), response)),
-            _ => None,
-        }
-    }));
+    // Errors from the target are returned as a complete encapsulated response.
+    if !response.status().is_success() {
+        let status = response.status().as_u16();
+        output.write_all(&response.bytes().await?)?;
+        let error_msg = format!("Target returned status {status}");
+        error!(error_msg);
+        return Err(error_msg.into());
+    }
 
-    let mut stream = client_response.decapsulate_stream(stream).await;
-    while let Some(result) = stream.next().await {
-        match result {
-            Ok(chunk) => {
+    loop {
+        match response.chunk().await {
+            Ok(Some(chunk)) => {
                 output.write_all("\n".as_bytes())?;
                 output.write_all(&chunk)?;
             }
+            Ok(None) => break,
             Err(e) => {
                 error!("Error in stream {e}")
             }
@@ -429,62 +372,38 @@ async fn main() -> Res<()> {
 
     let args = Args::parse();
 
-    //  Create ohttp request buffer
-    let request_buf = match create_request_buffer(
-        args.binary,
-        &args.target_path,
-        &args.headers,
-        &args.form_fields,
-    ) {
-        Ok(result) => result,
-        Err(e) => {
-            error!(e);
-            return Err(e);
-        }
-    };
-
-    trace!("Created the ohttp request buffer");
-
-    //  create the OHTTP request using the KMS or the static config file
-    let result = if let (Some(kms_url), Some(kms_cert)) = (&args.kms_url, &args.kms_cert) {
-        create_request_from_kms_config(kms_url, kms_cert).await
-    } else {
-        create_request_from_encoded_config_list(&args.config)
-    };
-    let ohttp_request = match result {
-        Ok(request) => request,
+    let client = match create_client(&args) {
+        Ok(client) => client,
         Err(e) => {
             error!(e);
             return Err(e);
         }
     };
-    trace!("Created ohttp client request");
 
-    // Encapsulate the http buffer using the OHTTP request
-    let (enc_request, ohttp_response) = match ohttp_request.encapsulate(&request_buf) {
-        Ok(result) => result,
-        Err(e) => {
-            error!("{e}");
-            return Err(Box::new(e));
-        }
+    // Send the request, either all at once or as the input is read
+    let sent = match &args.stream_input {
+        Some(input) => send_input(&client, &args.target_path, &args.headers, input).await,
+        None => match create_request(
+            args.binary,
+            &args.target_path,
+            &args.headers,
+            &args.form_fields,
+        ) {
+            Ok(request) => client.send_message(&request).await.map_err(Into::into),
+            Err(e) => Err(e),
+        },
     };
-    trace!(
-        "Encapsulated the OHTTP request {}",
-        hex::encode(&enc_request[0..60])
-    );
-
-    // Post the encapsulated ohttp request buffer to args.url
-    let response = match post_request(&args.url, &args.outer_headers, enc_request).await {
+    let response = match sent {
         Ok(response) => response,
         Err(e) => {
             error!(e);
             return Err(e);
         }
     };
-    trace!("Posted the OHTTP request to {}", args.url);
+    trace!("Sent the OHTTP request to {}", args.url);
 
     // decapsulate and output the http response
-    if let Err(e) = handle_response(response, ohttp_response, &args.output).await {
+    if let Err(e) = handle_response(response, &args.output).await {
         error!(e);
         return Err(e);
     }
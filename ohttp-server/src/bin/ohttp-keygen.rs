@@ -0,0 +1,116 @@
+#![deny(clippy::pedantic)]
+
+use std::{fs, path::PathBuf};
+
+use clap::{Parser, ValueEnum};
+use ohttp::{
+    hpke::{Aead, Kdf, Kem},
+    KeyConfig, SymmetricSuite,
+};
+
+type Res<T> = Result<T, Box<dyn std::error::Error>>;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum KemArg {
+    X25519,
+    P384,
+}
+
+impl From<KemArg> for Kem {
+    fn from(kem: KemArg) -> Self {
+        match kem {
+            KemArg::X25519 => Kem::X25519Sha256,
+            KemArg::P384 => Kem::P384Sha384,
+        }
+    }
+}
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "ohttp-keygen",
+    about = "Generate a key configuration list and check that clients can decode it."
+)]
+struct Args {
+    /// The key identifier.
+    #[arg(long, default_value_t = 0)]
+    key_id: u8,
+
+    /// The KEM of the key.
+    #[arg(long, value_enum, default_value_t = KemArg::P384)]
+    kem: KemArg,
+
+    /// Input keying material (hex) to derive the key from, so that the same
+    /// key can be derived again.  Without this, the key is random.
+    #[arg(long)]
+    ikm: Option<String>,
+
+    /// Write the list, as `application/ohttp-keys`, to this file instead of
+    /// printing it in hex.
+    #[arg(long, short)]
+    out: Option<PathBuf>,
+}
+
+/// Decode `list` the way a client would and print why any part of it is
+/// unusable.  This fails if the list doesn't decode to exactly `config`,
+/// with all of its suites.
+fn check(list: &[u8], config: &KeyConfig) -> Res<()> {
+    let report = KeyConfig::decode_list_report(list);
+    let mut ok = report.len() == 1;
+    for entry in report {
+        let key_id = entry
+            .key_id
+            .map_or_else(|| "?".to_owned(), |id| id.to_string());
+        let kem = entry
+            .kem
+            .map_or_else(|| "?".to_owned(), |kem| format!("{kem:#06x}"));
+        eprintln!(
+            "Key config {key_id} (KEM {kem}, suites {:x?})",
+            entry.suites
+        );
+        if !entry.stripped.is_empty() {
+            eprintln!("  unsupported suites {:?}", entry.stripped);
+            ok = false;
+        }
+        match entry.config {
+            Ok(decoded) => {
+                if decoded.encode()? != config.encode()? {
+                    eprintln!("  decodes to a different configuration");
+                    ok = false;
+                }
+            }
+            Err(e) => {
+                eprintln!("  rejected: {e}");
+                ok = false;
+            }
+        }
+    }
+    if ok {
+        Ok(())
+    } else {
+        Err("the key configuration list doesn't decode cleanly".into())
+    }
+}
+
+fn main() -> Res<()> {
+    ohttp::init();
+    let args = Args::parse();
+
+    let kem = Kem::from(args.kem);
+    let symmetric = vec![
+        SymmetricSuite::new(Kdf::HkdfSha384, Aead::Aes256Gcm),
+        SymmetricSuite::new(Kdf::HkdfSha256, Aead::Aes128Gcm),
+        SymmetricSuite::new(Kdf::HkdfSha256, Aead::ChaCha20Poly1305),
+    ];
+    let config = match &args.ikm {
+        Some(ikm) => KeyConfig::derive(args.key_id, kem, symmetric, &hex::decode(ikm)?)?,
+        None => KeyConfig::new(args.key_id, kem, symmetric)?,
+    };
+    let list = KeyConfig::encode_list(&[&config])?;
+    check(&list, &config)?;
+
+    match &args.out {
+        Some(path) => fs::write(path, &list)?,
+        None => println!("{}", hex::encode(&list)),
+    }
+    Ok(())
+}
@@ -0,0 +1,218 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ohttp::KeyConfig;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+type Res<T> = Result<T, Box<dyn std::error::Error>>;
+
+/// The media type for a list of key configurations, from RFC 9540.
+pub const KEYS_CONTENT_TYPE: &str = "application/ohttp-keys";
+/// The media type for a list of key configurations with their receipts and
+/// attestation tokens.
+pub const BUNDLE_CONTENT_TYPE: &str = "application/json";
+
+/// The number of bytes of the hash of the content that are used in an `ETag`.
+const ETAG_LEN: usize = 16;
+
+/// A key that the gateway holds, with what a client needs to check it.
+#[derive(Clone)]
+pub struct LoadedKey {
+    pub config: KeyConfig,
+    /// The attestation token that was presented to the KMS for this key.
+    /// This is empty for a local key.
+    pub token: String,
+    /// The receipt from the KMS for this key.  This is empty for a local key.
+    pub receipt: String,
+    /// When the key stops being usable, which is when its attestation token
+    /// expires.  This is `None` for a local key or a token without an expiry.
+    pub expires: Option<SystemTime>,
+}
+
+impl LoadedKey {
+    /// Whether the key expires before `time`.
+    #[must_use]
+    pub fn expires_before(&self, time: SystemTime) -> bool {
+        self.expires.map_or(false, |expires| expires < time)
+    }
+}
+
+/// The expiry of an attestation token, from the `exp` claim of the JWT.
+#[must_use]
+pub fn token_expiry(token: &str) -> Option<SystemTime> {
+    let payload = base64_url::decode(token.split('.').nth(1)?).ok()?;
+    let claims: serde_json::Value = serde_json::from_slice(&payload).ok()?;
+    UNIX_EPOCH.checked_add(Duration::from_secs(claims.get("exp")?.as_u64()?))
+}
+
+/// An entry in a bundle.  This uses the names from the list of public keys
+/// that the KMS provides, so clients can read either.
+#[derive(Serialize)]
+struct BundleEntry<'a> {
+    kid: u8,
+    #[serde(rename = "publicKey")]
+    key_config: String,
+    receipt: &'a str,
+    #[serde(rename = "attestationToken")]
+    attestation_token: &'a str,
+}
+
+/// The content of a response from the discovery endpoint.
+pub struct Discovery {
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+    /// A strong validator for `body`, including the quotes.
+    pub etag: String,
+    /// How long the response can be cached: until the first key expires.
+    /// This is `None` if no key has an expiry.
+    pub max_age: Option<u64>,
+}
+
+impl Discovery {
+    /// List `keys`, in order of key ID, either as `application/ohttp-keys`
+    /// or, for a bundle, as JSON that includes the receipt and attestation
+    /// token for each key.
+    ///
+    /// # Errors
+    /// If a key configuration cannot be encoded.
+    pub fn new(keys: &[LoadedKey], bundle: bool, now: SystemTime) -> Res<Self> {
+        let mut keys = keys.iter().collect::<Vec<_>>();
+        keys.sort_by_key(|k| k.config.key_id());
+
+        let (content_type, body) = if bundle {
+            let entries = keys
+                .iter()
+                .map(|k| {
+                    Ok(BundleEntry {
+                        kid: k.config.key_id(),
+                        key_config: hex::encode(k.config.encode()?),
+                        receipt: &k.receipt,
+                        attestation_token: &k.token,
+                    })
+                })
+                .collect::<Result<Vec<_>, ohttp::Error>>()?;
+            (BUNDLE_CONTENT_TYPE, serde_json::to_vec(&entries)?)
+        } else {
+            let configs = keys.iter().map(|k| &k.config).collect::<Vec<_>>();
+            (KEYS_CONTENT_TYPE, KeyConfig::encode_list(&configs)?)
+        };
+
+        let etag = format!("\"{}\"", hex::encode(&Sha256::digest(&body)[..ETAG_LEN]));
+        let max_age = keys
+            .iter()
+            .filter_map(|k| k.expires)
+            .map(|t| t.duration_since(now).unwrap_or_default().as_secs())
+            .min();
+        Ok(Self {
+            content_type,
+            body,
+            etag,
+            max_age,
+        })
+    }
+
+    /// Whether an `If-None-Match` header field matches this response, so that
+    /// the client can use what it has cached.
+    #[must_use]
+    pub fn not_modified(&self, if_none_match: &str) -> bool {
+        if_none_match
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == self.etag)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{token_expiry, Discovery, LoadedKey, BUNDLE_CONTENT_TYPE, KEYS_CONTENT_TYPE};
+    use ohttp::{
+        hpke::{Aead, Kdf, Kem},
+        KeyConfig, SymmetricSuite,
+    };
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    fn key(kid: u8, now: SystemTime, ttl: Option<u64>) -> LoadedKey {
+        let config = KeyConfig::new(
+            kid,
+            Kem::X25519Sha256,
+            vec![SymmetricSuite::new(Kdf::HkdfSha256, Aead::Aes128Gcm)],
+        )
+        .unwrap();
+        LoadedKey {
+            config,
+            token: format!("token{kid}"),
+            receipt: format!("receipt{kid}"),
+            expires: ttl.map(|ttl| now + Duration::from_secs(ttl)),
+        }
+    }
+
+    #[test]
+    fn list_keys() {
+        ohttp::init();
+        let now = SystemTime::now();
+        let keys = [
+            key(2, now, Some(600)),
+            key(1, now, Some(300)),
+            key(3, now, None),
+        ];
+
+        let discovery = Discovery::new(&keys, false, now).unwrap();
+        assert_eq!(discovery.content_type, KEYS_CONTENT_TYPE);
+        assert_eq!(discovery.max_age, Some(300));
+        let configs = KeyConfig::decode_list(&discovery.body).unwrap();
+        let kids = configs.iter().map(KeyConfig::key_id).collect::<Vec<_>>();
+        assert_eq!(kids, [1, 2, 3]);
+
+        // The order that keys are found in doesn't change the validator.
+        let reversed = [keys[2].clone(), keys[1].clone(), keys[0].clone()];
+        let again = Discovery::new(&reversed, false, now).unwrap();
+        assert_eq!(again.etag, discovery.etag);
+
+        assert!(discovery.not_modified(&discovery.etag));
+        assert!(discovery.not_modified(&format!("\"x\", W/{}", discovery.etag)));
+        assert!(discovery.not_modified("*"));
+        assert!(!discovery.not_modified("\"x\""));
+
+        let empty = Discovery::new(&[], false, now).unwrap();
+        assert!(empty.body.is_empty());
+        assert_eq!(empty.max_age, None);
+
+        // A key with no expiry doesn't limit caching.
+        let local = Discovery::new(&keys[2..], false, now).unwrap();
+        assert_eq!(local.max_age, None);
+    }
+
+    #[test]
+    fn token_exp() {
+        let token = |claims: &str| format!("e30.{}.sig", base64_url::encode(claims));
+        assert_eq!(
+            token_expiry(&token(r#"{"exp":1700000000,"iat":1699990000}"#)),
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        );
+        assert_eq!(token_expiry(&token(r#"{"iat":1699990000}"#)), None);
+        assert_eq!(token_expiry("not a token"), None);
+        assert_eq!(token_expiry(""), None);
+
+        let now = SystemTime::now();
+        let key = key(1, now, Some(60));
+        assert!(!key.expires_before(now));
+        assert!(key.expires_before(now + Duration::from_secs(61)));
+    }
+
+    #[test]
+    fn bundle() {
+        ohttp::init();
+        let now = SystemTime::now();
+        let keys = [key(1, now, Some(300))];
+
+        let discovery = Discovery::new(&keys, true, now).unwrap();
+        assert_eq!(discovery.content_type, BUNDLE_CONTENT_TYPE);
+        let bundle: serde_json::Value = serde_json::from_slice(&discovery.body).unwrap();
+        let entry = &bundle[0];
+        assert_eq!(entry["kid"], 1);
+        assert_eq!(entry["receipt"], "receipt1");
+        assert_eq!(entry["attestationToken"], "token1");
+        let config = hex::decode(entry["publicKey"].as_str().unwrap()).unwrap();
+        assert_eq!(config, keys[0].config.encode().unwrap());
+    }
+}
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -16,6 +18,185 @@ pub enum ServerError {
     KMSUnexpected(u16),
     #[error("Max retries reached, giving up. Cannot reach key management service")]
     KMSUnreachable,
+    #[error("Invalid receipt in SKR response: {0}")]
+    KMSReceipt(String),
+    #[error("Cannot establish the KMS service certificate: {0}")]
+    KMSServiceCert(String),
+    #[error("No KMS is configured")]
+    KMSNotConfigured,
     #[error("Private key missing from SKR response")]
     PrivateKeyMissing,
+    #[error("Invalid routing configuration: {0}")]
+    InvalidRoute(String),
+    #[error("Invalid header rules: {0}")]
+    InvalidRules(String),
+    #[error("Invalid TLS configuration: {0}")]
+    Tls(String),
+}
+
+/// The problem type for key configuration errors, from RFC 9458 section 5.3.
+const PROBLEM_TYPE_OHTTP_KEY: &str = "https://iana.org/assignments/http-problem-types#ohttp-key";
+
+/// Seconds a client should wait before retrying when no key is available.
+const KEY_UNAVAILABLE_RETRY_AFTER: u64 = 5;
+
+/// Errors from handling a single gateway request.
+///
+/// Following RFC 9458 section 5, errors that occur before the request
+/// has been decapsulated are returned as bare outer responses, while
+/// errors that occur afterwards are encapsulated for the client.
+#[derive(Error, Debug)]
+pub enum GatewayError {
+    #[error("The encapsulated request is empty")]
+    EmptyRequest,
+    #[error("The request body could not be read: {0}")]
+    RequestBody(String),
+    #[error("The request is larger than the gateway accepts")]
+    RequestTooLarge,
+    #[error("The key configuration used by the request was rejected: {0}")]
+    KeyConfig(ohttp::Error),
+    #[error("The request could not be decapsulated: {0}")]
+    Decapsulation(ohttp::Error),
+    #[error("No key is currently available: {0}")]
+    KeyUnavailable(String),
+    #[error("Internal gateway error: {0}")]
+    Internal(String),
+    #[error("The encapsulated request is malformed: {0}")]
+    InnerRequest(String),
+    #[error("No route matches the encapsulated request")]
+    NoRoute,
+    #[error("The route does not allow the method {0}")]
+    MethodNotAllowed(String),
+    #[error("The target could not be reached: {0}")]
+    TargetUnreachable(reqwest::Error),
+    #[error("The target did not respond in time: {0}")]
+    TargetTimeout(reqwest::Error),
+    #[error("The target did not respond in time")]
+    TargetNoResponse,
+    #[error("The target is failing, so requests are stopped for {0:?}")]
+    TargetUnavailable(Duration),
+}
+
+impl GatewayError {
+    /// Classify an error from `Server::decapsulate` or `Server::decapsulate_stream`.
+    #[must_use]
+    pub fn from_decapsulation(e: ohttp::Error) -> Self {
+        match e {
+            ohttp::Error::KeyId
+            | ohttp::Error::KeyIdMismatch(..)
+            | ohttp::Error::InvalidKem
+            | ohttp::Error::Unsupported => Self::KeyConfig(e),
+            ohttp::Error::ChunkTooLarge => Self::RequestTooLarge,
+            _ => Self::Decapsulation(e),
+        }
+    }
+
+    /// Classify an error from loading the key that a request names.  If the
+    /// KMS returns a different key, because the key has been rotated, or if
+    /// there is no KMS to load the key from, the client has a stale key
+    /// configuration and needs to fetch a new one.
+    #[must_use]
+    pub fn from_key_load(e: Box<dyn std::error::Error>) -> Self {
+        match e.downcast::<ohttp::Error>() {
+            Ok(e) if matches!(*e, ohttp::Error::KeyIdMismatch(..)) => Self::KeyConfig(*e),
+            Ok(e) => Self::KeyUnavailable(e.to_string()),
+            Err(e) if matches!(e.downcast_ref(), Some(ServerError::KMSNotConfigured)) => {
+                Self::KeyConfig(ohttp::Error::KeyId)
+            }
+            Err(e) => Self::KeyUnavailable(e.to_string()),
+        }
+    }
+
+    /// Classify an error from sending the request to the target.  The URL is
+    /// removed, as it includes the path and query of the inner request.
+    #[must_use]
+    pub fn from_target(e: reqwest::Error) -> Self {
+        let e = e.without_url();
+        if e.is_timeout() {
+            Self::TargetTimeout(e)
+        } else {
+            Self::TargetUnreachable(e)
+        }
+    }
+
+    /// The HTTP status code for the (outer or inner) error response.
+    #[must_use]
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::EmptyRequest
+            | Self::RequestBody(_)
+            | Self::KeyConfig(_)
+            | Self::Decapsulation(_)
+            | Self::InnerRequest(_) => 400,
+            Self::NoRoute => 404,
+            Self::MethodNotAllowed(_) => 405,
+            Self::RequestTooLarge => 413,
+            Self::KeyUnavailable(_) | Self::TargetUnavailable(_) => 503,
+            Self::Internal(_) => 500,
+            Self::TargetUnreachable(_) => 502,
+            Self::TargetTimeout(_) | Self::TargetNoResponse => 504,
+        }
+    }
+
+    /// A label for this class of error, for metrics.
+    #[must_use]
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::EmptyRequest => "empty_request",
+            Self::RequestBody(_) => "request_body",
+            Self::RequestTooLarge => "request_too_large",
+            Self::KeyConfig(_) => "key_config",
+            Self::Decapsulation(_) => "decapsulation",
+            Self::KeyUnavailable(_) => "key_unavailable",
+            Self::Internal(_) => "internal",
+            Self::InnerRequest(_) => "inner_request",
+            Self::NoRoute => "no_route",
+            Self::MethodNotAllowed(_) => "method_not_allowed",
+            Self::TargetUnreachable(_) => "target_unreachable",
+            Self::TargetTimeout(_) | Self::TargetNoResponse => "target_timeout",
+            Self::TargetUnavailable(_) => "target_unavailable",
+        }
+    }
+
+    /// The value of the `Retry-After` header field, if any.
+    #[must_use]
+    pub fn retry_after(&self) -> Option<u64> {
+        match self {
+            Self::KeyUnavailable(_) => Some(KEY_UNAVAILABLE_RETRY_AFTER),
+            Self::TargetUnavailable(cooldown) => Some(cooldown.as_secs().max(1)),
+            _ => None,
+        }
+    }
+
+    /// A short description that is safe to return to clients.
+    fn title(&self) -> &'static str {
+        match self {
+            Self::EmptyRequest => "Empty encapsulated request",
+            Self::RequestBody(_) => "Request body could not be read",
+            Self::RequestTooLarge => "Request too large",
+            Self::KeyConfig(_) => "Key configuration rejected",
+            Self::Decapsulation(_) => "Request could not be decapsulated",
+            Self::KeyUnavailable(_) => "Key temporarily unavailable",
+            Self::Internal(_) => "Internal gateway error",
+            Self::InnerRequest(_) => "Malformed encapsulated request",
+            Self::NoRoute => "No route for request",
+            Self::MethodNotAllowed(_) => "Method not allowed",
+            Self::TargetUnreachable(_) => "Target unreachable",
+            Self::TargetTimeout(_) | Self::TargetNoResponse => "Target timed out",
+            Self::TargetUnavailable(_) => "Target unavailable",
+        }
+    }
+
+    /// An `application/problem+json` (RFC 9457) body for this error.
+    /// This never includes internal details.
+    #[must_use]
+    pub fn problem(&self) -> String {
+        let mut problem = serde_json::Map::new();
+        if let Self::KeyConfig(_) = self {
+            problem.insert("type".into(), PROBLEM_TYPE_OHTTP_KEY.into());
+        }
+        problem.insert("title".into(), self.title().into());
+        problem.insert("status".into(), self.status().into());
+        serde_json::Value::Object(problem).to_string()
+    }
 }
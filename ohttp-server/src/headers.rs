@@ -0,0 +1,177 @@
+use bhttp::Message;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONNECTION};
+
+use crate::err::GatewayError;
+
+type Res<T> = Result<T, Box<dyn std::error::Error>>;
+
+/// Headers that only apply to a single connection (RFC 9110 section 7.6.1),
+/// or that describe framing that the gateway redoes for the target.
+/// `Host` is replaced by the authority of the target.
+const DROPPED_HEADERS: &[&str] = &[
+    "connection",
+    "proxy-connection",
+    "keep-alive",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "content-length",
+    "host",
+];
+
+/// Headers of a response from the target that are not passed on to the
+/// client, besides the ones above, as they identify the target or carry its
+/// state.
+const DROPPED_RESPONSE_HEADERS: &[&str] = &["server", "set-cookie", "set-cookie2"];
+
+/// The fields named in `Connection`, which are also hop-by-hop.
+fn connection_fields(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|c| c.trim().to_ascii_lowercase())
+        .collect()
+}
+
+/// Remove the headers of a response from the target that are not copied to
+/// the outer response: hop-by-hop, framing, and identifying headers.
+pub fn remove_response_headers(headers: &mut HeaderMap) {
+    let connection = connection_fields(headers);
+    let names = headers.keys().cloned().collect::<Vec<_>>();
+    for name in names {
+        if DROPPED_HEADERS.contains(&name.as_str())
+            || DROPPED_RESPONSE_HEADERS.contains(&name.as_str())
+            || connection.iter().any(|c| c == name.as_str())
+        {
+            headers.remove(&name);
+        }
+    }
+}
+
+/// Headers that clients cannot set by default, because the target might
+/// trust them to describe the connection to the gateway.
+pub const DEFAULT_DENIED_HEADERS: &str =
+    "forwarded,x-forwarded-for,x-forwarded-host,x-forwarded-proto,x-real-ip";
+
+/// Validates and filters the header fields of inner requests.
+#[derive(Debug)]
+pub struct HeaderPolicy {
+    denied: Vec<HeaderName>,
+}
+
+impl HeaderPolicy {
+    /// # Errors
+    /// If a denied header name is not valid.
+    pub fn new(denied: &[String]) -> Res<Self> {
+        let denied = denied
+            .iter()
+            .map(|name| HeaderName::from_bytes(name.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { denied })
+    }
+
+    fn dropped(&self, name: &HeaderName, connection: &[String]) -> bool {
+        DROPPED_HEADERS.contains(&name.as_str())
+            || connection.iter().any(|c| c == name.as_str())
+            || self.denied.contains(name)
+    }
+
+    /// Build the headers for the request to the target from an inner request.
+    /// Hop-by-hop, framing, and denied headers are removed.
+    ///
+    /// # Errors
+    /// If a field name or value is not valid (RFC 9110 section 5).
+    pub fn inner_headers(&self, request: &Message) -> Result<HeaderMap, GatewayError> {
+        let mut headers = HeaderMap::new();
+        for field in request.header().fields() {
+            let name = HeaderName::from_bytes(field.name())
+                .map_err(|_| GatewayError::InnerRequest("invalid header field name".into()))?;
+            let value = HeaderValue::from_bytes(field.value()).map_err(|_| {
+                GatewayError::InnerRequest(format!("invalid value for header field {name}"))
+            })?;
+            headers.append(name, value);
+        }
+
+        let connection = connection_fields(&headers);
+        let names = headers.keys().cloned().collect::<Vec<_>>();
+        for name in names {
+            if self.dropped(&name, &connection) {
+                headers.remove(&name);
+            }
+        }
+        Ok(headers)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{remove_response_headers, HeaderPolicy, DEFAULT_DENIED_HEADERS};
+    use bhttp::Message;
+    use reqwest::header::HeaderMap;
+
+    fn policy() -> HeaderPolicy {
+        let denied = DEFAULT_DENIED_HEADERS
+            .split(',')
+            .map(ToOwned::to_owned)
+            .collect::<Vec<_>>();
+        HeaderPolicy::new(&denied).unwrap()
+    }
+
+    fn request(fields: &[(&[u8], &[u8])]) -> Message {
+        let mut m = Message::request(
+            b"POST".to_vec(),
+            b"https".to_vec(),
+            b"example.com".to_vec(),
+            b"/".to_vec(),
+        );
+        for (name, value) in fields {
+            m.put_header(*name, *value);
+        }
+        m
+    }
+
+    #[test]
+    fn drop_headers() {
+        let m = request(&[
+            (b"content-type", b"text/plain"),
+            (b"connection", b"close, x-hop"),
+            (b"x-hop", b"1"),
+            (b"host", b"internal"),
+            (b"content-length", b"100"),
+            (b"x-forwarded-for", b"10.0.0.1"),
+        ]);
+        let headers = policy().inner_headers(&m).unwrap();
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers.get("content-type").unwrap(), "text/plain");
+    }
+
+    #[test]
+    fn drop_response_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", "text/plain".parse().unwrap());
+        headers.insert("connection", "keep-alive, x-hop".parse().unwrap());
+        headers.insert("keep-alive", "timeout=5".parse().unwrap());
+        headers.insert("x-hop", "1".parse().unwrap());
+        headers.insert("transfer-encoding", "chunked".parse().unwrap());
+        headers.insert("server", "uvicorn".parse().unwrap());
+        headers.insert("set-cookie", "session=1".parse().unwrap());
+        headers.insert("x-request-id", "1".parse().unwrap());
+        remove_response_headers(&mut headers);
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers.get("content-type").unwrap(), "text/plain");
+        assert_eq!(headers.get("x-request-id").unwrap(), "1");
+    }
+
+    #[test]
+    fn reject_invalid() {
+        let p = policy();
+        assert!(p.inner_headers(&request(&[(b"bad name", b"1")])).is_err());
+        assert!(p.inner_headers(&request(&[(b"x-ok", b"a\nb")])).is_err());
+        assert!(p.inner_headers(&request(&[(b"x-ok", &[0xff])])).is_ok());
+    }
+}
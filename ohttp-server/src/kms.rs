@@ -0,0 +1,53 @@
+use std::{fs, path::Path};
+
+use reqwest::{Certificate, Client, ClientBuilder};
+
+use crate::err::ServerError;
+
+type Res<T> = Result<T, Box<dyn std::error::Error>>;
+
+/// A client for the KMS, which only trusts the service certificate of the
+/// KMS.  Receipts for exported keys are checked against the same certificate.
+pub struct Kms {
+    client: Client,
+    service_cert: String,
+}
+
+impl Kms {
+    /// Create a KMS client that only trusts the service certificate in
+    /// `service_cert`.  `builder` can add a client certificate.
+    ///
+    /// # Errors
+    /// If the service certificate cannot be read or is not valid.
+    pub fn new(builder: ClientBuilder, service_cert: &Path) -> Res<Self> {
+        let service_cert = fs::read_to_string(service_cert)?;
+        let client = builder
+            .tls_built_in_root_certs(false)
+            .add_root_certificate(Certificate::from_pem(service_cert.as_bytes())?)
+            .build()?;
+        Ok(Self {
+            client,
+            service_cert,
+        })
+    }
+
+    #[must_use]
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Check that a receipt from the KMS is valid and is endorsed by
+    /// the service certificate.
+    ///
+    /// # Errors
+    /// If the receipt is malformed or its signature does not verify.
+    pub fn verify_receipt(&self, receipt: &str) -> Res<()> {
+        match verifier::verify(receipt, &self.service_cert) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Box::new(ServerError::KMSReceipt(
+                "signature does not verify".into(),
+            ))),
+            Err(e) => Err(Box::new(ServerError::KMSReceipt(e.to_string()))),
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use reqwest::{
+    header::{HeaderMap, HeaderName},
+    Url,
+};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+type Res<T> = Result<T, Box<dyn std::error::Error>>;
+
+/// The number of bytes of the hash that are kept for a correlation ID.
+const CORRELATION_ID_LEN: usize = 8;
+
+/// Controls what the gateway logs about the requests it handles.
+///
+/// By default, nothing from the content of inner requests or responses is
+/// logged, and only the values of allowlisted headers are.  The sensitive
+/// mode logs all headers and full target URLs, for debugging only.
+#[derive(Debug, Default)]
+pub struct LogPolicy {
+    sensitive: bool,
+    allowed_headers: Vec<HeaderName>,
+}
+
+impl LogPolicy {
+    /// # Errors
+    /// If an allowlisted header name is not valid.
+    pub fn new(sensitive: bool, allowed_headers: &[String]) -> Res<Self> {
+        let allowed_headers = allowed_headers
+            .iter()
+            .map(|name| HeaderName::from_bytes(name.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            sensitive,
+            allowed_headers,
+        })
+    }
+
+    /// Warn that sensitive logging is enabled.  This is meant to be hard to miss.
+    pub fn announce(&self) {
+        if self.sensitive {
+            for _ in 0..3 {
+                warn!("SENSITIVE LOGGING IS ENABLED: decrypted headers and target URLs are logged");
+            }
+            warn!("This defeats the privacy of Oblivious HTTP; never use it in production");
+        }
+    }
+
+    /// Log a set of headers.  Only the values of allowlisted headers are
+    /// logged, unless sensitive logging is enabled.
+    pub fn headers(&self, what: &str, headers: &HeaderMap) {
+        if self.sensitive {
+            info!("[SENSITIVE] {what} ({}):", headers.len());
+            for (name, value) in headers {
+                info!(
+                    "[SENSITIVE]     {name}: {}",
+                    String::from_utf8_lossy(value.as_bytes())
+                );
+            }
+            return;
+        }
+
+        info!("{what} ({}):", headers.len());
+        for (name, value) in headers {
+            if self.allowed_headers.contains(name) {
+                info!("    {name}: {}", String::from_utf8_lossy(value.as_bytes()));
+            }
+        }
+    }
+
+    /// Describe a target URL for logging.  The path and query come from the
+    /// inner request, so only the origin is included unless sensitive
+    /// logging is enabled.
+    #[must_use]
+    pub fn url(&self, url: &Url) -> String {
+        if self.sensitive {
+            url.to_string()
+        } else {
+            url.origin().ascii_serialization()
+        }
+    }
+
+    /// Hash an identifier that is supplied with a request, so that log
+    /// entries can be correlated with other logs without recording it.
+    #[must_use]
+    pub fn correlation_id(id: &[u8]) -> String {
+        hex::encode(&Sha256::digest(id)[..CORRELATION_ID_LEN])
+    }
+}
@@ -1,25 +1,49 @@
 #![deny(clippy::pedantic)]
 
+pub mod discovery;
 pub mod err;
-
-use std::{io::Cursor, net::SocketAddr, sync::Arc};
+pub mod headers;
+pub mod kms;
+pub mod logging;
+pub mod metrics;
+pub mod routes;
+pub mod rules;
+pub mod targets;
+pub mod tls;
+
+use std::{
+    io::Cursor,
+    net::SocketAddr,
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime},
+};
 
 use lazy_static::lazy_static;
 use moka::future::Cache;
 
-use futures_util::stream::unfold;
+use futures::{Stream, StreamExt, TryStreamExt};
+use futures_util::stream::{iter, once, unfold};
 use reqwest::{
-    header::{HeaderMap, HeaderName, HeaderValue},
+    header::{HeaderName, ACCEPT, CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_NONE_MATCH, VARY},
     Method, Response, Url,
 };
 
-use bhttp::{Message, Mode};
+use bhttp::{ContentDecoder, Message, Mode, Padding, StatusCode};
 use clap::Parser;
 use ohttp::{
     hpke::{Aead, Kdf, Kem},
-    Error, KeyConfig, Server as OhttpServer, ServerResponse, SymmetricSuite,
+    rechunk, ChunkStream, Error, KeyConfig, Server as OhttpServer, ServerResponse, StreamOptions,
+    SymmetricSuite,
+};
+use warp::{
+    hyper::{
+        body::{Buf, Bytes},
+        Body,
+    },
+    Filter,
 };
-use warp::{hyper::Body, Filter};
 
 use tokio::time::{sleep, Duration};
 
@@ -34,8 +58,15 @@ use serde_json::from_str;
 use hpke::Deserializable;
 use serde::Deserialize;
 
-use err::ServerError;
-use tracing::{error, info, instrument, trace};
+use discovery::{token_expiry, Discovery, LoadedKey};
+use err::{GatewayError, ServerError};
+use headers::{HeaderPolicy, DEFAULT_DENIED_HEADERS};
+use kms::Kms;
+use logging::LogPolicy;
+use routes::{normalize_path, RoutingTable};
+use rules::{HeaderRules, RequestContext};
+use targets::{TargetSettings, Targets};
+use tracing::{error, field, info, instrument, trace, warn, Span};
 use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter, FmtSubscriber};
 use uuid::Uuid;
 
@@ -50,10 +81,14 @@ struct ExportedKey {
 
 const DEFAULT_KMS_URL: &str = "https://accconfinferencedebug.confidential-ledger.azure.com/app/key";
 const DEFAULT_MAA_URL: &str = "https://maanosecureboottestyfu.eus.attest.azure.net";
-const FILTERED_RESPONSE_HEADERS: [&str; 2] = ["content-type", "content-length"];
+const CHUNKED_REQUEST_CONTENT_TYPE: &str = "message/ohttp-chunked-req";
+/// An identifier that a relay can set on the outer request.
+/// Only a hash of this is logged, as `correlation_id`.
+const CORRELATION_HEADER: &str = "x-ms-client-request-id";
 
 #[derive(Debug, Parser, Clone)]
 #[command(name = "ohttp-server", about = "Serve oblivious HTTP requests.")]
+#[allow(clippy::struct_excessive_bools)] // These are command-line flags.
 struct Args {
     /// The address to bind to.
     #[arg(default_value = "127.0.0.1:9443")]
@@ -67,6 +102,12 @@ struct Args {
     #[arg(long, short = 't', default_value = "http://127.0.0.1:8000")]
     target: Url,
 
+    /// JSON file with a routing table that maps the authority and path of
+    /// inner requests to backends. This replaces `--target`; requests that
+    /// match no route are rejected.
+    #[arg(long, short = 'r')]
+    routes: Option<PathBuf>,
+
     /// Use locally generated key, for testing without KMS
     #[arg(long, short = 'l')]
     local_key: bool,
@@ -79,8 +120,143 @@ struct Args {
     #[arg(long, short = 's')]
     kms_url: Option<String>,
 
-    #[arg(long, short = 'i')]
-    inject_request_headers: Vec<String>,
+    /// A JSON file with rules that rewrite the headers of requests to targets
+    /// and responses to clients.  Without this, the `enginetarget` header of
+    /// the outer request sets the target path, unless `--routes` is used.
+    #[arg(long)]
+    header_rules: Option<PathBuf>,
+
+    /// Headers that are removed from inner requests, so that clients
+    /// cannot set them.
+    #[arg(long, value_delimiter = ',', default_value = DEFAULT_DENIED_HEADERS)]
+    deny_inner_header: Vec<String>,
+
+    /// Headers whose values can be logged.
+    /// The values of other headers are never logged, unless `--log-sensitive` is set.
+    #[arg(long, value_delimiter = ',')]
+    log_allow_header: Vec<String>,
+
+    /// Log all decrypted headers and target URLs, for debugging only.
+    /// This defeats the privacy that Oblivious HTTP provides.
+    #[arg(long)]
+    log_sensitive: bool,
+
+    /// The most that is buffered for a single request, in bytes.
+    /// This limits the size of requests that are not chunked.  For chunked
+    /// requests, it limits the size of each chunk and of the header section,
+    /// while the content is streamed to the target.
+    #[arg(long, default_value_t = 64 * 1024 * 1024)]
+    max_request_size: usize,
+
+    /// Encapsulate each chunk of a response as soon as it arrives from the
+    /// target, and end the response with a separate final chunk.
+    #[arg(long)]
+    flush_chunks: bool,
+
+    /// With --flush-chunks, only end chunks of `text/event-stream` responses
+    /// where an event ends.
+    #[arg(long, requires = "flush_chunks")]
+    align_events: bool,
+
+    /// With --flush-chunks, send an empty chunk when the target sends nothing
+    /// for this many seconds.
+    #[arg(long, requires = "flush_chunks")]
+    keepalive_interval: Option<u64>,
+
+    /// Send streamed responses in chunks of exactly this many bytes, except
+    /// for the last, so that chunk sizes don't reveal the size of each token.
+    #[arg(long, conflicts_with = "align_events")]
+    chunk_size: Option<usize>,
+
+    /// Pad complete responses: `none`, `bucket:<bytes>`, `pow2`, or
+    /// `random:<bytes>`.
+    #[arg(long, default_value = "none")]
+    response_padding: Padding,
+
+    /// The longest time to wait for a connection to a target, in seconds.
+    #[arg(long, default_value_t = 10)]
+    target_connect_timeout: u64,
+
+    /// The longest time to wait for response headers from a target, or for
+    /// the next piece of response content, in seconds.
+    #[arg(long, default_value_t = 300)]
+    target_read_timeout: u64,
+
+    /// The longest time for a whole request to a target, in seconds.
+    /// Routes can set their own timeout.  By default, there is no limit.
+    #[arg(long)]
+    target_timeout: Option<u64>,
+
+    /// The number of idle connections to keep for each target.
+    #[arg(long, default_value_t = 32)]
+    target_pool_max_idle: usize,
+
+    /// How many times idempotent requests are retried when a target can't be
+    /// reached or responds with 502, 503, or 504.
+    #[arg(long, default_value_t = 2)]
+    target_retries: u32,
+
+    /// The number of consecutive failures after which requests to a target
+    /// are stopped for a while.  Zero disables this.
+    #[arg(long, default_value_t = 5)]
+    target_failure_threshold: u32,
+
+    /// How long requests to a failing target are stopped for, in seconds.
+    #[arg(long, default_value_t = 30)]
+    target_cooldown: u64,
+
+    /// Serve HTTPS with this certificate chain (PEM).
+    /// The certificate and key are reloaded when their files change.
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// The private key (PEM) for `--tls-cert`.
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Require clients (normally relays) to present a certificate that is
+    /// signed by one of the CA certificates (PEM) in this file.
+    #[arg(long, requires = "tls_cert")]
+    tls_client_ca: Option<PathBuf>,
+
+    /// CA certificates (PEM) to trust for connections to targets,
+    /// in addition to the built-in roots.
+    #[arg(long)]
+    target_ca: Option<PathBuf>,
+
+    /// Client certificate (PEM) to present to targets.
+    #[arg(long, requires = "target_client_key")]
+    target_client_cert: Option<PathBuf>,
+
+    /// The private key (PEM) for `--target-client-cert`.
+    #[arg(long, requires = "target_client_cert")]
+    target_client_key: Option<PathBuf>,
+
+    /// The service certificate (PEM) of the KMS.
+    /// Connections to the KMS and receipts for keys are checked against it.
+    /// This is needed unless --local-key is used.
+    #[arg(long)]
+    kms_cert: Option<PathBuf>,
+
+    /// Client certificate (PEM) to present to the KMS.
+    #[arg(long, requires = "kms_client_key")]
+    kms_client_cert: Option<PathBuf>,
+
+    /// The private key (PEM) for `--kms-client-cert`.
+    #[arg(long, requires = "kms_client_cert")]
+    kms_client_key: Option<PathBuf>,
+
+    /// Keys that are loaded from the KMS at startup, and loaded again before
+    /// they expire, so that the discovery endpoint lists them.  Other keys are
+    /// listed once a request has used them.
+    #[arg(long, value_delimiter = ',')]
+    discover_kid: Vec<u8>,
+
+    /// Serve Prometheus metrics at `/metrics` on this address, over plain
+    /// HTTP.  This should not be reachable by clients.  Metrics are not
+    /// served without this.
+    #[arg(long)]
+    metrics_listen: Option<SocketAddr>,
 }
 
 impl Args {
@@ -93,12 +269,99 @@ impl Args {
     }
 }
 
+/// Clients for the services that the gateway connects to.
+/// There is no KMS when a local key is used.
+struct Upstream {
+    targets: Targets,
+    kms: Option<Kms>,
+}
+
+impl Upstream {
+    fn new(args: &Args, routes: &RoutingTable) -> Res<Self> {
+        let settings = TargetSettings {
+            connect_timeout: Duration::from_secs(args.target_connect_timeout),
+            read_timeout: Duration::from_secs(args.target_read_timeout),
+            timeout: args.target_timeout.map(Duration::from_secs),
+            pool_max_idle: args.target_pool_max_idle,
+            retries: args.target_retries,
+            failure_threshold: args.target_failure_threshold,
+            cooldown: Duration::from_secs(args.target_cooldown),
+        };
+        let builder = || {
+            tls::client_builder(
+                Client::builder(),
+                args.target_ca.as_deref(),
+                args.target_client_cert.as_deref(),
+                args.target_client_key.as_deref(),
+            )
+        };
+        let targets = Targets::new(routes, builder, settings)?;
+
+        let kms = if args.local_key {
+            None
+        } else {
+            let kms_cert = args.kms_cert.as_deref().ok_or_else(|| {
+                ServerError::KMSServiceCert("use --kms-cert to provide the certificate".into())
+            })?;
+            let builder = tls::client_builder(
+                Client::builder(),
+                None,
+                args.kms_client_cert.as_deref(),
+                args.kms_client_key.as_deref(),
+            )?;
+            Some(Kms::new(builder, kms_cert)?)
+        };
+        Ok(Self { targets, kms })
+    }
+}
+
+/// How the gateway treats the inner requests that it handles.
+struct Policy {
+    headers: HeaderPolicy,
+    rules: HeaderRules,
+    log: LogPolicy,
+    /// How responses are streamed, or `None` to hold back each chunk until
+    /// the next one arrives.
+    stream: Option<StreamOptions>,
+    /// The size of streamed response chunks, if they are resized.
+    chunk_size: Option<usize>,
+    /// The padding for complete responses.
+    padding: Padding,
+}
+
+impl Policy {
+    fn new(args: &Args) -> Res<Self> {
+        let rules = match &args.header_rules {
+            Some(path) => HeaderRules::load(path).map_err(|e| {
+                error!("Failed to load header rules from {}: {e}", path.display());
+                e
+            })?,
+            None => HeaderRules::default_rules(args.routes.is_none()),
+        };
+        Ok(Self {
+            headers: HeaderPolicy::new(&args.deny_inner_header)?,
+            rules,
+            log: LogPolicy::new(args.log_sensitive, &args.log_allow_header)?,
+            stream: args.flush_chunks.then(|| StreamOptions {
+                event_stream: args.align_events,
+                keepalive: args.keepalive_interval.map(Duration::from_secs),
+            }),
+            chunk_size: args.chunk_size.filter(|&size| size > 0),
+            padding: args.response_padding,
+        })
+    }
+}
+
+/// How long a key is used before it is loaded from the KMS again.
+const KEY_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// How often the keys in `--discover-kid` are checked.
+const DISCOVERY_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+/// How long before it expires a key in `--discover-kid` is loaded again.
+const DISCOVERY_REFRESH_MARGIN: Duration = Duration::from_secs(10 * 60);
+
 lazy_static! {
-    static ref cache: Arc<Cache<u8, (KeyConfig, String)>> = Arc::new(
-        Cache::builder()
-            .time_to_live(Duration::from_secs(24 * 60 * 60))
-            .build()
-    );
+    static ref cache: Arc<Cache<u8, LoadedKey>> =
+        Arc::new(Cache::builder().time_to_live(KEY_TTL).build());
 }
 
 fn parse_cbor_key(key: &str, kid: u8) -> Res<(Option<Vec<u8>>, u8)> {
@@ -145,12 +408,12 @@ fn parse_cbor_key(key: &str, kid: u8) -> Res<(Option<Vec<u8>>, u8)> {
                     _ => {
                         return Err(Box::new(ServerError::KMSField));
                     }
-                };
-            };
+                }
+            }
         }
     } else {
         return Err(Box::new(ServerError::KMSCBOREncoding));
-    };
+    }
     Ok((d, returned_kid))
 }
 
@@ -168,11 +431,12 @@ fn fetch_maa_token(maa: &str) -> Res<String> {
 
 /// Retrieves the HPKE private key from Azure KMS.
 ///
-async fn get_hpke_private_key_from_kms(kms: &str, kid: u8, token: &str) -> Res<String> {
-    let client = Client::builder()
-        .danger_accept_invalid_certs(true)
-        .build()?;
-
+async fn get_hpke_private_key_from_kms(
+    client: &Kms,
+    kms: &str,
+    kid: u8,
+    token: &str,
+) -> Res<(String, String)> {
     // Retrying logic for receipt
     let max_retries = 3;
     let mut retries = 0;
@@ -183,6 +447,7 @@ async fn get_hpke_private_key_from_kms(kms: &str, kid: u8, token: &str) -> Res<S
 
         // Get HPKE private key from Azure KMS
         let response = client
+            .client()
             .post(url)
             .header("Authorization", format!("Bearer {token}"))
             .send()
@@ -218,8 +483,10 @@ async fn get_hpke_private_key_from_kms(kms: &str, kid: u8, token: &str) -> Res<S
                 if skr.kid != kid {
                     return Err(Box::new(Error::KeyIdMismatch(skr.kid, kid)));
                 }
+                client.verify_receipt(&skr.receipt)?;
+                info!("The receipt for KID {kid} is valid");
 
-                return Ok(skr.key);
+                return Ok((skr.key, skr.receipt));
             }
             e => {
                 return Err(Box::new(ServerError::KMSUnexpected(e)));
@@ -228,16 +495,44 @@ async fn get_hpke_private_key_from_kms(kms: &str, kid: u8, token: &str) -> Res<S
     }
 }
 
-async fn load_config(maa: &str, kms: &str, kid: u8) -> Res<(KeyConfig, String)> {
-    // Check if the key configuration is in cache
-    if let Some((config, token)) = cache.get(&kid).await {
+async fn load_config(kms_client: Option<&Kms>, maa: &str, kms: &str, kid: u8) -> Res<LoadedKey> {
+    // Check if the key configuration is in cache, and hasn't expired
+    let cached = cache
+        .get(&kid)
+        .await
+        .filter(|key| !key.expires_before(SystemTime::now()));
+    metrics::key_cache(cached.is_some());
+    if let Some(key) = cached {
         info!("Found OHTTP configuration for KID {kid} in cache.");
-        return Ok((config, token));
+        return Ok(key);
     }
+    fetch_config(kms_client, maa, kms, kid).await
+}
+
+/// Loads a key from the KMS, with a new attestation token, and caches it.
+async fn fetch_config(kms_client: Option<&Kms>, maa: &str, kms: &str, kid: u8) -> Res<LoadedKey> {
+    let kms_client = kms_client.ok_or(ServerError::KMSNotConfigured)?;
 
     // Get MAA token from CVM guest attestation library
-    let token = fetch_maa_token(maa)?;
-    let key = get_hpke_private_key_from_kms(kms, kid, &token).await?;
+    let start = Instant::now();
+    let token = match fetch_maa_token(maa) {
+        Ok(token) => token,
+        Err(e) => {
+            metrics::key_fetch("maa", start.elapsed(), false);
+            return Err(e);
+        }
+    };
+    metrics::key_fetch("maa", start.elapsed(), true);
+
+    let start = Instant::now();
+    let (key, receipt) = match get_hpke_private_key_from_kms(kms_client, kms, kid, &token).await {
+        Ok(key) => key,
+        Err(e) => {
+            metrics::key_fetch("kms", start.elapsed(), false);
+            return Err(e);
+        }
+    };
+    metrics::key_fetch("kms", start.elapsed(), true);
     let (d, returned_kid) = parse_cbor_key(&key, kid)?;
 
     let sk = match d {
@@ -258,254 +553,631 @@ async fn load_config(maa: &str, kms: &str, kid: u8) -> Res<(KeyConfig, String)>
         ],
     )?;
 
-    cache.insert(kid, (config.clone(), token.clone())).await;
-    Ok((config, token))
+    let expires = token_expiry(&token);
+    let key = LoadedKey {
+        config,
+        token,
+        receipt,
+        expires,
+    };
+    cache.insert(kid, key.clone()).await;
+    Ok(key)
 }
 
-/// Copies headers from the encapsulated request and logs them.
-///
-fn get_headers_from_request(bin_request: &Message) -> HeaderMap {
-    info!("Inner request headers");
-    let mut headers = HeaderMap::new();
-    for field in bin_request.header().fields() {
-        info!(
-            "    {}: {}",
-            std::str::from_utf8(field.name()).unwrap(),
-            std::str::from_utf8(field.value()).unwrap()
-        );
-
-        headers.append(
-            HeaderName::from_bytes(field.name()).unwrap(),
-            HeaderValue::from_bytes(field.value()).unwrap(),
-        );
+/// The outer request body.
+type OuterBody = Pin<Box<dyn Stream<Item = Result<Bytes, warp::Error>> + Send>>;
+
+/// The header of an inner request and a body for the target.
+type InnerRequest = Result<(Message, reqwest::Body), GatewayError>;
+
+/// Holds the first error in the content of a streamed inner request, so that
+/// it can be reported in place of the failure to send that content on.
+type ContentError = Arc<Mutex<Option<GatewayError>>>;
+
+/// Waits for the first byte of the outer request body, which is normally the KID.
+/// This returns the KID and the whole of the body.
+async fn peek_kid(mut body: OuterBody) -> Result<(u8, OuterBody), GatewayError> {
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| GatewayError::RequestBody(e.to_string()))?;
+        if let Some(kid) = chunk.first().copied() {
+            return Ok((kid, Box::pin(once(async { Ok(chunk) }).chain(body))));
+        }
     }
+    Err(GatewayError::EmptyRequest)
+}
+
+/// Reads all of the outer request body, up to `limit` bytes.
+async fn read_body(mut body: OuterBody, limit: usize) -> Result<Vec<u8>, GatewayError> {
+    let mut buf = Vec::new();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| GatewayError::RequestBody(e.to_string()))?;
+        if buf.len() + chunk.len() > limit {
+            return Err(GatewayError::RequestTooLarge);
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(buf)
+}
+
+/// Splits a complete decapsulated request into its header and a body for the target.
+/// The body refers to the decapsulated request rather than copying it.
+fn inner_request(request: Vec<u8>) -> InnerRequest {
+    let request = Bytes::from(request);
+    let mut r = Cursor::new(&request[..]);
+    let (mode, bin_request) = Message::read_bhttp_header(&mut r)
+        .map_err(|e| GatewayError::InnerRequest(e.to_string()))?;
+    let header_len = usize::try_from(r.position()).unwrap();
+
+    let mut decoder = ContentDecoder::new(mode);
+    let content = decoder.decode(&request[header_len..]);
+    decoder
+        .finish()
+        .map_err(|e| GatewayError::InnerRequest(e.to_string()))?;
+    let body = match content[..] {
+        [] => reqwest::Body::from(Bytes::new()),
+        [c] => reqwest::Body::from(request.slice_ref(c)),
+        _ => reqwest::Body::from(content.concat()),
+    };
+    Ok((bin_request, body))
+}
+
+/// Reads the header of a chunked decapsulated request, buffering no more than
+/// `limit` bytes, and returns a body that streams the content to the target.
+async fn inner_request_stream(
+    mut chunks: ChunkStream,
+    limit: usize,
+    content_error: ContentError,
+) -> InnerRequest {
+    let mut buf = Vec::new();
+    let (mode, bin_request, header_len) = loop {
+        let chunk = chunks.next().await;
+        let done = chunk.is_none();
+        if let Some(chunk) = chunk {
+            buf.extend_from_slice(&chunk.map_err(GatewayError::from_decapsulation)?);
+        }
+
+        let mut r = Cursor::new(&buf[..]);
+        match Message::read_bhttp_header(&mut r) {
+            // A header section that ends with the buffer might continue in the next chunk.
+            Ok((mode, bin_request))
+                if done || usize::try_from(r.position()).unwrap() < buf.len() =>
+            {
+                break (mode, bin_request, usize::try_from(r.position()).unwrap());
+            }
+            Ok(_) => {}
+            Err(bhttp::Error::Truncated) if !done => {}
+            Err(e) => return Err(GatewayError::InnerRequest(e.to_string())),
+        }
+        if buf.len() > limit {
+            return Err(GatewayError::RequestTooLarge);
+        }
+    };
+
+    let rest = Bytes::from(buf.split_off(header_len));
+    let chunks = iter([Ok(rest)]).chain(chunks);
+    let content = unfold(
+        Some((chunks, ContentDecoder::new(mode), content_error)),
+        |state| async move {
+            let (mut chunks, mut decoder, content_error) = state?;
+            let e = loop {
+                match chunks.next().await {
+                    Some(Ok(chunk)) => {
+                        let content = match decoder.decode(&chunk)[..] {
+                            [] => continue,
+                            [c] => chunk.slice_ref(c),
+                            ref content => Bytes::from(content.concat()),
+                        };
+                        return Some((Ok(content), Some((chunks, decoder, content_error))));
+                    }
+                    Some(Err(e)) => break GatewayError::from_decapsulation(e),
+                    None => match decoder.finish() {
+                        Ok(()) => return None,
+                        Err(e) => break GatewayError::InnerRequest(e.to_string()),
+                    },
+                }
+            };
+            error!("{e}");
+            let message = e.to_string();
+            *content_error.lock().unwrap() = Some(e);
+            Some((Err(message), None))
+        },
+    );
+    Ok((bin_request, reqwest::Body::wrap_stream(content)))
+}
+
+/// Creates an OHTTP server for the key with the given KID, and returns the MAA token.
+async fn load_server(
+    args: &Args,
+    upstream: &Upstream,
+    kid: u8,
+) -> Result<(OhttpServer, String), GatewayError> {
+    let maa_url = args.maa_url.clone().unwrap_or(DEFAULT_MAA_URL.to_string());
+    let kms_url = args.kms_url.clone().unwrap_or(DEFAULT_KMS_URL.to_string());
+    let key = load_config(upstream.kms.as_ref(), &maa_url, &kms_url, kid)
+        .await
+        .map_err(|e| {
+            let e = GatewayError::from_key_load(e);
+            error!("Failed to get or load OHTTP configuration. {e}");
+            e
+        })?;
+    let server = OhttpServer::new(key.config).map_err(|e| {
+        let e = GatewayError::Internal(e.to_string());
+        error!("Failed to create OHTTP server from config. {e}");
+        e
+    })?;
+    Ok((server, key.token))
+}
+
+/// Whether a request uses chunked OHTTP.  The content of these requests is
+/// streamed to the target as it arrives.
+fn is_chunked(headers: &warp::hyper::HeaderMap) -> bool {
     headers
+        .get(CONTENT_TYPE)
+        .map_or(false, |v| v == CHUNKED_REQUEST_CONTENT_TYPE)
 }
 
-async fn generate_reply(
+/// Removes the outer encapsulation and splits the inner request into its
+/// header and a body for the target.  Chunked requests are streamed to the
+/// target; others are read in full first.
+///
+/// Errors in the inner request are returned separately, as they need to be
+/// encapsulated using the `ServerResponse`.
+async fn decapsulate(
     ohttp: &OhttpServer,
-    inject_headers: HeaderMap,
-    enc_request: &[u8],
-    target: Url,
-    target_path: Option<&HeaderValue>,
-    _mode: Mode,
-) -> Res<(Response, ServerResponse)> {
-    let (request, server_response) = ohttp.decapsulate(enc_request)?;
-    let bin_request = Message::read_bhttp(&mut Cursor::new(&request[..]))?;
+    headers: &warp::hyper::HeaderMap,
+    body: OuterBody,
+    limit: usize,
+    content_error: &ContentError,
+) -> Result<(InnerRequest, ServerResponse), GatewayError> {
+    if is_chunked(headers) {
+        let chunks = body.map_err(|e| Error::Io(std::io::Error::new(std::io::ErrorKind::Other, e)));
+        let (chunks, server_response) = ohttp
+            .decapsulate_stream(chunks, limit)
+            .await
+            .map_err(GatewayError::from_decapsulation)?;
+        let chunks = Box::pin(chunks.inspect_ok(|c| metrics::chunk("request", c.len())));
+        let inner = inner_request_stream(chunks, limit, Arc::clone(content_error)).await;
+        Ok((inner, server_response))
+    } else {
+        let enc_request = read_body(body, limit).await?;
+        let (request, server_response) = ohttp
+            .decapsulate(&enc_request)
+            .map_err(GatewayError::from_decapsulation)?;
+        Ok((inner_request(request), server_response))
+    }
+}
+
+async fn generate_reply(
+    targets: &Targets,
+    inner: InnerRequest,
+    content_error: &ContentError,
+    routes: &RoutingTable,
+    context: &RequestContext<'_>,
+    policy: &Policy,
+) -> Result<Response, GatewayError> {
+    let (bin_request, body) = inner?;
 
     let method: Method = if let Some(method_bytes) = bin_request.control().method() {
-        Method::from_bytes(method_bytes)?
+        Method::from_bytes(method_bytes).map_err(|e| GatewayError::InnerRequest(e.to_string()))?
     } else {
         Method::GET
     };
 
     // Copy headers from the encapsulated request
-    let mut headers = get_headers_from_request(&bin_request);
-
-    // Inject additional headers from the outer request
-    if !inject_headers.is_empty() {
-        info!("Appending injected headers");
-        for (key, value) in inject_headers {
-            if let Some(key) = key {
-                info!("    {}: {}", key.as_str(), value.to_str().unwrap());
-                headers.append(key, value);
-            }
-        }
-    };
+    let mut headers = policy.headers.inner_headers(&bin_request)?;
 
-    let mut t = target;
+    // Rewrite headers, possibly using the outer request
+    let target_path = policy.rules.apply_request(context, &mut headers)?;
 
     // Set resource path to either the one provided in the outer request header
-    // If none provided, use the path set by the client
-    if let Some(path_bytes) = target_path {
-        if let Ok(path_str) = std::str::from_utf8(path_bytes.as_bytes()) {
-            t.set_path(path_str);
-        }
-    } else if let Some(path_bytes) = bin_request.control().path() {
-        if let Ok(path_str) = std::str::from_utf8(path_bytes) {
-            t.set_path(path_str);
+    // If none provided, use the path set by the client.  Routes are matched
+    // on this path, so that an outer header can't reach paths they exclude.
+    let control = bin_request.control();
+    let path = match target_path {
+        Some(path_bytes) => Some(path_bytes.as_bytes()),
+        None => control.path(),
+    };
+    let path = path
+        .map(|p| normalize_path(p).ok_or(GatewayError::NoRoute))
+        .transpose()?;
+    let route = routes
+        .select(control.authority(), path.as_deref())
+        .ok_or(GatewayError::NoRoute)?;
+    if !route.allows(&method) {
+        return Err(GatewayError::MethodNotAllowed(method.to_string()));
+    }
+    route.apply_headers(&mut headers);
+    policy.log.headers("Request headers", &headers);
+
+    let t = route.target_url(path.as_deref());
+    info!("Forwarding request to {}", policy.log.url(&t));
+
+    targets
+        .send(method, t, headers, body, route.timeout())
+        .await
+        .map_err(|e| {
+            // If the content of the request was bad, report that instead.
+            content_error.lock().unwrap().take().unwrap_or(e)
+        })
+}
+
+/// Builds a bare outer response for an error that occurs before the request
+/// could be decapsulated.
+fn error_response(e: &GatewayError) -> warp::http::Result<warp::http::Response<Body>> {
+    metrics::request(e.label());
+    let mut builder = warp::http::Response::builder()
+        .status(e.status())
+        .header("Content-Type", "application/problem+json");
+    if let Some(retry_after) = e.retry_after() {
+        builder = builder.header("Retry-After", retry_after);
+    }
+    builder.body(Body::from(e.problem()))
+}
+
+/// Encapsulates a complete binary HTTP response with the given status.
+/// This is sent as `message/ohttp-res`, so that clients can tell it apart
+/// from a chunked response.
+fn encapsulated_response(
+    server_response: ServerResponse,
+    mode: Mode,
+    padding: Padding,
+    status: u16,
+    content_type: Option<&str>,
+    content: &[u8],
+) -> warp::http::Result<warp::http::Response<Body>> {
+    let encapsulate = || -> Res<Vec<u8>> {
+        let mut message = Message::response(StatusCode::try_from(status)?);
+        if let Some(content_type) = content_type {
+            message.put_header("content-type", content_type);
         }
+        message.write_content(content);
+        let mut buf = Vec::new();
+        message.write_bhttp_padded(mode, padding, &mut buf)?;
+        Ok(server_response.encapsulate(&buf)?)
+    };
+
+    match encapsulate() {
+        Ok(enc_response) => warp::http::Response::builder()
+            .header("Content-Type", "message/ohttp-res")
+            .body(Body::from(enc_response)),
+        Err(e) => error_response(&GatewayError::Internal(e.to_string())),
     }
+}
 
-    let client = reqwest::ClientBuilder::new().build()?;
-    let response = client
-        .request(method, t)
-        .headers(headers)
-        .body(bin_request.content().to_vec())
-        .send()
-        .await?
-        .error_for_status()?;
-
-    Ok((response, server_response))
-}
-
-// Compute the set of headers that need to be injected into the inner request
-fn compute_injected_headers(headers: &HeaderMap, keys: Vec<String>) -> HeaderMap {
-    let mut result = HeaderMap::new();
-    for key in keys {
-        if let Ok(header_name) = HeaderName::try_from(key) {
-            if let Some(value) = headers.get(&header_name) {
-                result.insert(header_name, value.clone());
-            }
+/// Encapsulates an error that occurs after the request was decapsulated.
+fn encapsulated_error(
+    server_response: ServerResponse,
+    mode: Mode,
+    padding: Padding,
+    e: &GatewayError,
+) -> warp::http::Result<warp::http::Response<Body>> {
+    metrics::request(e.label());
+    encapsulated_response(
+        server_response,
+        mode,
+        padding,
+        e.status(),
+        Some("application/problem+json"),
+        e.problem().as_bytes(),
+    )
+}
+
+/// Encapsulates a failed response from the target, so that the client
+/// sees the status code that the target produced.
+async fn encapsulate_target_failure(
+    server_response: ServerResponse,
+    mode: Mode,
+    padding: Padding,
+    response: Response,
+) -> warp::http::Result<warp::http::Response<Body>> {
+    metrics::request("target_status");
+    let status = response.status().as_u16();
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(ToOwned::to_owned);
+    match response.bytes().await {
+        Ok(content) => encapsulated_response(
+            server_response,
+            mode,
+            padding,
+            status,
+            content_type.as_deref(),
+            &content,
+        ),
+        Err(e) => {
+            let e = GatewayError::from_target(e);
+            encapsulated_error(server_response, mode, padding, &e)
         }
     }
-    result
 }
 
-#[instrument(skip(headers, body, args), fields(version = %VERSION))]
+#[instrument(
+    skip(headers, body, args, routes, upstream, policy),
+    fields(version = %VERSION, correlation_id = field::Empty)
+)]
 async fn score(
     headers: warp::hyper::HeaderMap,
-    body: warp::hyper::body::Bytes,
+    body: impl Stream<Item = Result<impl Buf, warp::Error>> + Send + 'static,
     args: Arc<Args>,
+    routes: Arc<RoutingTable>,
+    upstream: Arc<Upstream>,
+    policy: Arc<Policy>,
     x_ms_request_id: Uuid,
 ) -> Result<impl warp::Reply, std::convert::Infallible> {
-    let target = args.target.clone();
-    info!("Received encapsulated score request for target {}", target);
+    if let Some(id) = headers.get(CORRELATION_HEADER) {
+        Span::current().record("correlation_id", LogPolicy::correlation_id(id.as_bytes()));
+    }
+    info!("Received encapsulated score request");
+    let start = Instant::now();
 
     info!("Request headers length = {}", headers.len());
     let return_token = headers.contains_key("x-attestation-token");
 
-    // The KID is normally the first byte of the request
-    let kid = match body.first().copied() {
-        None => {
-            let error_msg = "No key found in request.";
-            error!("{error_msg}");
-            return Ok(warp::http::Response::builder()
-                .status(500)
-                .body(Body::from(error_msg.as_bytes())));
+    let body = Box::pin(body.map_ok(|mut b| b.copy_to_bytes(b.remaining())));
+    let (kid, body) = match peek_kid(body).await {
+        Ok(s) => s,
+        Err(e) => {
+            error!("{e}");
+            return Ok(error_response(&e));
         }
-        Some(kid) => kid,
     };
-    let maa_url = args.maa_url.clone().unwrap_or(DEFAULT_MAA_URL.to_string());
-    let kms_url = args.kms_url.clone().unwrap_or(DEFAULT_KMS_URL.to_string());
-    let (ohttp, token) = match load_config(&maa_url, &kms_url, kid).await {
+
+    let (ohttp, token) = match load_server(&args, &upstream, kid).await {
+        Ok(s) => s,
+        Err(e) => return Ok(error_response(&e)),
+    };
+
+    let content_error = ContentError::default();
+    let decapsulated = decapsulate(
+        &ohttp,
+        &headers,
+        body,
+        args.max_request_size,
+        &content_error,
+    )
+    .await;
+    let (inner, server_response) = match decapsulated {
+        Ok(s) => s,
         Err(e) => {
-            let error_msg = "Failed to get or load OHTTP configuration.";
-            error!("{error_msg} {e}");
-            return Ok(warp::http::Response::builder()
-                .status(500)
-                .body(Body::from(error_msg.as_bytes())));
+            error!("{e}");
+            return Ok(error_response(&e));
         }
-        Ok((config, token)) => match OhttpServer::new(config) {
-            Ok(server) => (server, token),
-            Err(e) => {
-                let error_msg = "Failed to create OHTTP server from config.";
-                error!("{error_msg} {e}");
-                return Ok(warp::http::Response::builder()
-                    .status(500)
-                    .body(Body::from(error_msg.as_bytes())));
-            }
-        },
     };
+    metrics::decapsulated(&server_response);
 
-    let inject_request_headers = args.inject_request_headers.clone();
-    info!(
-        "Request inject headers length = {}",
-        inject_request_headers.len()
+    let context = RequestContext {
+        outer: &headers,
+        key_id: server_response.key_id(),
+        suite: server_response.suite(),
+        attestation_token: &token,
+    };
+    let (mode, padding) = (args.mode(), policy.padding);
+    let reply = generate_reply(
+        &upstream.targets,
+        inner,
+        &content_error,
+        &routes,
+        &context,
+        &policy,
     );
-    for key in &inject_request_headers {
-        info!("    {}", key);
-    }
+    let response = match reply.await {
+        Ok(response) => response,
+        Err(e) => {
+            error!("{e}");
+            return Ok(encapsulated_error(server_response, mode, padding, &e));
+        }
+    };
 
-    let inject_headers = compute_injected_headers(&headers, inject_request_headers);
-    info!("Injected headers length = {}", inject_headers.len());
-    for (key, value) in &inject_headers {
-        info!("    {}: {}", key, value.to_str().unwrap());
+    if response.status().is_client_error() || response.status().is_server_error() {
+        error!("Target returned status {}", response.status());
+        let failure = encapsulate_target_failure(server_response, mode, padding, response);
+        return Ok(failure.await);
     }
 
-    let target_path = headers.get("enginetarget");
-    let mode = args.mode();
-    let (response, server_response) =
-        match generate_reply(&ohttp, inject_headers, &body[..], target, target_path, mode).await {
-            Ok(s) => s,
-            Err(e) => {
-                error!(e);
-
-                if let Ok(oe) = e.downcast::<::ohttp::Error>() {
-                    return Ok(warp::http::Response::builder()
-                        .status(422)
-                        .body(Body::from(format!("Error: {oe:?}"))));
-                }
-
-                let error_msg = "Request error.";
-                error!("{error_msg}");
-                return Ok(warp::http::Response::builder()
-                    .status(400)
-                    .body(Body::from(error_msg.as_bytes())));
-            }
-        };
+    let token = if return_token { Some(token) } else { None };
+    Ok(encapsulate_target_response(
+        server_response,
+        response,
+        token,
+        start,
+        is_chunked(&headers),
+        upstream.targets.read_timeout(),
+        &policy,
+    ))
+}
 
+/// Streams a successful response from the target, encapsulating each chunk.
+/// The MAA token is added to the outer response if it was requested.
+/// The stream ends if the target stops sending for longer than `read_timeout`.
+/// With `duplex`, the request is chunked and might still be arriving, so
+/// each chunk of the response is sent as soon as it arrives.
+fn encapsulate_target_response(
+    server_response: ServerResponse,
+    response: Response,
+    token: Option<String>,
+    start: Instant,
+    duplex: bool,
+    read_timeout: Duration,
+    policy: &Policy,
+) -> warp::http::Result<warp::http::Response<Body>> {
+    metrics::request("ok");
     let mut builder =
         warp::http::Response::builder().header("Content-Type", "message/ohttp-chunked-res");
 
     // Add HTTP header with MAA token, for client auditing.
-    if return_token {
-        builder = builder.header(
-            HeaderName::from_static("x-attestation-token"),
-            token.clone(),
-        );
+    if let Some(token) = token {
+        builder = builder.header(HeaderName::from_static("x-attestation-token"), token);
     }
 
+    let event_stream = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map_or(false, |v| v.starts_with("text/event-stream"));
+
     // Move headers from the inner response into the outer response
-    info!("Response headers:");
-    for (key, value) in response.headers() {
-        if !FILTERED_RESPONSE_HEADERS
-            .iter()
-            .any(|h| h.eq_ignore_ascii_case(key.as_str()))
-        {
-            info!(
-                "    {}: {}",
-                key,
-                std::str::from_utf8(value.as_bytes()).unwrap()
-            );
-            builder = builder.header(key.as_str(), value.as_bytes());
-        }
+    let headers = policy.rules.response_headers(response.headers());
+    policy.log.headers("Response headers", &headers);
+    for (key, value) in &headers {
+        builder = builder.header(key, value);
     }
 
-    let stream = Box::pin(unfold(response, |mut response| async move {
-        match response.chunk().await {
-            Ok(Some(chunk)) => Some((Ok::<Vec<u8>, ohttp::Error>(chunk.to_vec()), response)),
-            _ => None,
-        }
+    // A failure ends the stream with an error, so that no final chunk is
+    // sent and the client can tell that the response is incomplete.
+    let stream = Box::pin(unfold(Some(response), move |response| async move {
+        let mut response = response?;
+        let error = match tokio::time::timeout(read_timeout, response.chunk()).await {
+            Ok(Ok(Some(chunk))) => {
+                metrics::chunk("response", chunk.len());
+                return Some((Ok(chunk), Some(response)));
+            }
+            Ok(Ok(None)) => return None,
+            Ok(Err(e)) => format!("target response failed: {}", e.without_url()),
+            Err(_) => format!("target response stalled for {read_timeout:?}"),
+        };
+        warn!("{error}");
+        Some((Err(ohttp::Error::Stream(error)), None))
     }));
 
-    let stream = server_response.encapsulate_stream(stream);
-    Ok(builder.body(Body::wrap_stream(stream)))
-}
+    let stream: ChunkStream = match policy.chunk_size {
+        Some(size) => Box::pin(rechunk(stream, size)),
+        None => stream,
+    };
+    let stream = match &policy.stream {
+        Some(options) => {
+            let options = StreamOptions {
+                event_stream: options.event_stream && event_stream,
+                ..options.clone()
+            };
+            server_response.encapsulate_stream_with(stream, options)
+        }
+        // The request might still be streaming, and the client could be
+        // waiting on each response chunk before it sends more, so nothing
+        // is held back.
+        None if duplex => server_response.encapsulate_stream_with(stream, StreamOptions::default()),
+        None => server_response.encapsulate_stream(stream),
+    };
 
-async fn discover(args: Arc<Args>) -> Result<impl warp::Reply, std::convert::Infallible> {
-    let kms_url = &args.kms_url.clone().unwrap_or(DEFAULT_KMS_URL.to_string());
-    let maa_url = &args.maa_url.clone().unwrap_or(DEFAULT_MAA_URL.to_string());
+    let mut first = Some(start);
+    let stream = stream.inspect(move |_| {
+        if let Some(start) = first.take() {
+            metrics::first_chunk(start.elapsed());
+        }
+    });
+    builder.body(Body::wrap_stream(stream))
+}
 
-    // The discovery endpoint is only enabled for local testing
-    if !args.local_key {
-        return Ok(warp::http::Response::builder()
-            .status(404)
-            .body(Body::from(&b"Not found"[..])));
+async fn metrics() -> Result<impl warp::Reply, std::convert::Infallible> {
+    match metrics::encode() {
+        Ok(text) => Ok(warp::http::Response::builder()
+            .header("Content-Type", "text/plain; version=0.0.4")
+            .body(text)),
+        Err(e) => {
+            error!("Failed to encode metrics: {e}");
+            Ok(warp::http::Response::builder()
+                .status(500)
+                .body(String::new()))
+        }
     }
+}
 
-    match load_config(maa_url, kms_url, 0).await {
-        Ok((config, _)) => match KeyConfig::encode_list(&[config]) {
-            Ok(list) => {
-                let hex = hex::encode(list);
-                trace!("Discover config: {}", hex);
-
-                Ok(warp::http::Response::builder()
-                    .status(200)
-                    .body(Vec::from(hex).into()))
+/// Keeps the keys in `--discover-kid` loaded, so that the discovery endpoint
+/// can list them without contacting the KMS.  A key is loaded at startup,
+/// and again when it is missing from the cache or is about to expire.
+/// A key that can't be loaded is tried again later.
+async fn refresh_discovery_keys(args: Arc<Args>, upstream: Arc<Upstream>) {
+    let kms_url = args.kms_url.clone().unwrap_or(DEFAULT_KMS_URL.to_string());
+    let maa_url = args.maa_url.clone().unwrap_or(DEFAULT_MAA_URL.to_string());
+    let mut interval = tokio::time::interval(DISCOVERY_REFRESH_INTERVAL);
+    loop {
+        interval.tick().await;
+        for &kid in &args.discover_kid {
+            let refresh = SystemTime::now() + DISCOVERY_REFRESH_MARGIN;
+            if let Some(key) = cache.get(&kid).await {
+                if !key.expires_before(refresh) {
+                    continue;
+                }
             }
-            Err(e) => {
-                error!("{e}");
-                Ok(warp::http::Response::builder().status(500).body(Body::from(
-                    &b"Invalid key configuration (check KeyConfig written to initial cache)"[..],
-                )))
+            info!("Loading KID {kid} for discovery");
+            if let Err(e) = fetch_config(upstream.kms.as_ref(), &maa_url, &kms_url, kid).await {
+                warn!("Failed to load KID {kid} for discovery: {e}");
             }
-        },
+        }
+    }
+}
+
+/// Lists the keys in the cache that haven't expired.
+fn active_keys() -> Vec<LoadedKey> {
+    let now = SystemTime::now();
+    cache
+        .iter()
+        .map(|(_, key)| key)
+        .filter(|key| !key.expires_before(now))
+        .collect()
+}
+
+/// Serves the key configurations of the gateway as `application/ohttp-keys`,
+/// or as a JSON bundle with receipts and attestation tokens for clients that
+/// accept `application/json`.  Responses can be cached until the first key
+/// expires, or are revalidated if no key has an expiry.
+async fn discover(
+    headers: warp::hyper::HeaderMap,
+) -> Result<warp::http::Response<Body>, std::convert::Infallible> {
+    let bundle = headers
+        .get(ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .map_or(false, |v| v.contains(discovery::BUNDLE_CONTENT_TYPE));
+    let keys = active_keys();
+    let discovery = match Discovery::new(&keys, bundle, SystemTime::now()) {
+        Ok(discovery) => discovery,
+        Err(e) => {
+            error!("Failed to list key configurations: {e}");
+            return Ok(warp::http::Response::builder()
+                .status(500)
+                .body(Body::empty())
+                .unwrap());
+        }
+    };
+    trace!("Discovery lists {} keys", keys.len());
+
+    let not_modified = headers
+        .get(IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .map_or(false, |v| discovery.not_modified(v));
+    let builder = warp::http::Response::builder()
+        .header(ETAG, &discovery.etag)
+        .header(
+            CACHE_CONTROL,
+            discovery
+                .max_age
+                .map_or_else(|| "no-cache".to_owned(), |age| format!("max-age={age}")),
+        )
+        .header(VARY, "accept");
+    let response = if not_modified {
+        builder.status(304).body(Body::empty())
+    } else {
+        builder
+            .header(CONTENT_TYPE, discovery.content_type)
+            .body(Body::from(discovery.body))
+    };
+    Ok(response.unwrap())
+}
+
+/// The key configurations as hex, for scripts that pass the output to
+/// `ohttp-client --config`.
+async fn discover_hex() -> Result<impl warp::Reply, std::convert::Infallible> {
+    let keys = active_keys();
+    match Discovery::new(&keys, false, SystemTime::now()) {
+        Ok(discovery) => Ok(warp::http::Response::builder()
+            .status(200)
+            .body(Body::from(hex::encode(discovery.body)))),
         Err(e) => {
-            error!(e);
-            Ok(warp::http::Response::builder().status(500).body(Body::from(
-                &b"KID 0 missing from cache (should be impossible with local keying)"[..],
-            )))
+            error!("Failed to list key configurations: {e}");
+            Ok(warp::http::Response::builder()
+                .status(500)
+                .body(Body::empty()))
         }
     }
 }
@@ -544,29 +1216,78 @@ async fn main() -> Res<()> {
             error!("{e}");
             e
         })?;
-        cache.insert(0, (config, String::new())).await;
+        let key = LoadedKey {
+            config,
+            token: String::new(),
+            receipt: String::new(),
+            expires: None,
+        };
+        cache.insert(0, key).await;
     }
 
+    let routes = match &args.routes {
+        Some(path) => RoutingTable::load(path).map_err(|e| {
+            error!("Failed to load routing table from {}: {e}", path.display());
+            e
+        })?,
+        None => RoutingTable::single(args.target.clone()),
+    };
+    let routes = Arc::new(routes);
+    let upstream = Arc::new(Upstream::new(&args, &routes)?);
+    let upstream1 = Arc::clone(&upstream);
+    let policy = Arc::new(Policy::new(&args)?);
+    policy.log.announce();
+    let tls = args.tls_cert.clone().zip(args.tls_key.clone());
+    let tls_client_ca = args.tls_client_ca.clone();
+
     let argsc = Arc::new(args);
     let args1 = Arc::clone(&argsc);
     let score = warp::post()
         .and(warp::path::path("score"))
         .and(warp::path::end())
         .and(warp::header::headers_cloned())
-        .and(warp::body::bytes())
+        .and(warp::body::stream())
         .and(warp::any().map(move || Arc::clone(&args1)))
+        .and(warp::any().map(move || Arc::clone(&routes)))
+        .and(warp::any().map(move || Arc::clone(&upstream1)))
+        .and(warp::any().map(move || Arc::clone(&policy)))
         .and(warp::any().map(Uuid::new_v4))
         .and_then(score);
 
-    let args2 = Arc::clone(&argsc);
+    if !argsc.discover_kid.is_empty() {
+        if upstream.kms.is_some() {
+            tokio::spawn(refresh_discovery_keys(Arc::clone(&argsc), upstream));
+        } else {
+            warn!("--discover-kid is ignored, as there is no KMS");
+        }
+    }
+
     let discover = warp::get()
+        .and(warp::path!(".well-known" / "ohttp-gateway"))
+        .and(warp::header::headers_cloned())
+        .and_then(discover);
+
+    let discover_hex = warp::get()
         .and(warp::path("discover"))
         .and(warp::path::end())
-        .and(warp::any().map(move || Arc::clone(&args2)))
-        .and_then(discover);
+        .and_then(discover_hex);
+
+    if let Some(metrics_address) = argsc.metrics_listen {
+        let metrics = warp::get()
+            .and(warp::path("metrics"))
+            .and(warp::path::end())
+            .and_then(metrics);
+        info!("Serving metrics on {metrics_address}");
+        tokio::spawn(warp::serve(metrics).run(metrics_address));
+    }
 
-    let routes = score.or(discover);
-    warp::serve(routes).run(address).await;
+    let routes = score.or(discover).or(discover_hex);
+    if let Some((cert, key)) = tls {
+        let incoming = tls::incoming(address, cert, key, tls_client_ca.as_deref()).await?;
+        warp::serve(routes).run_incoming(incoming).await;
+    } else {
+        warp::serve(routes).run(address).await;
+    }
 
     Ok(())
 }
@@ -0,0 +1,130 @@
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use ohttp::ServerResponse;
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter_vec, Encoder, Histogram,
+    HistogramVec, IntCounterVec, TextEncoder,
+};
+
+type Res<T> = Result<T, Box<dyn std::error::Error>>;
+
+lazy_static! {
+    static ref REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "ohttp_requests_total",
+        "Requests handled by the gateway, by outcome",
+        &["outcome"]
+    )
+    .unwrap();
+    static ref SUITES: IntCounterVec = register_int_counter_vec!(
+        "ohttp_decapsulated_requests_total",
+        "Requests that were decapsulated, by key ID and symmetric suite",
+        &["key_id", "kdf", "aead"]
+    )
+    .unwrap();
+    static ref KEY_FETCH_SECONDS: HistogramVec = register_histogram_vec!(
+        "ohttp_key_fetch_duration_seconds",
+        "Time taken to fetch an attestation token (maa) or a key (kms)",
+        &["service"]
+    )
+    .unwrap();
+    static ref KEY_FETCH_FAILURES: IntCounterVec = register_int_counter_vec!(
+        "ohttp_key_fetch_failures_total",
+        "Failures to fetch an attestation token (maa) or a key (kms)",
+        &["service"]
+    )
+    .unwrap();
+    static ref KEY_CACHE: IntCounterVec = register_int_counter_vec!(
+        "ohttp_key_cache_lookups_total",
+        "Lookups in the key cache, by result (hit or miss)",
+        &["result"]
+    )
+    .unwrap();
+    static ref FIRST_CHUNK_SECONDS: Histogram = register_histogram!(
+        "ohttp_first_chunk_seconds",
+        "Time from receiving a request to sending the first encapsulated chunk"
+    )
+    .unwrap();
+    static ref CHUNKS: IntCounterVec = register_int_counter_vec!(
+        "ohttp_streamed_chunks_total",
+        "Chunks streamed, by direction (request or response)",
+        &["direction"]
+    )
+    .unwrap();
+    static ref BYTES: IntCounterVec = register_int_counter_vec!(
+        "ohttp_streamed_bytes_total",
+        "Decapsulated bytes streamed, by direction (request or response)",
+        &["direction"]
+    )
+    .unwrap();
+}
+
+/// Count a request with the given outcome.
+pub fn request(outcome: &str) {
+    REQUESTS.with_label_values(&[outcome]).inc();
+}
+
+/// Count the key and suite that a request was decapsulated with.
+pub fn decapsulated(server_response: &ServerResponse) {
+    let suite = server_response.suite();
+    SUITES
+        .with_label_values(&[
+            &server_response.key_id().to_string(),
+            &format!("{:?}", suite.kdf()),
+            &format!("{:?}", suite.aead()),
+        ])
+        .inc();
+}
+
+/// Record the time taken by a fetch from the MAA or KMS, and whether it failed.
+pub fn key_fetch(service: &str, elapsed: Duration, ok: bool) {
+    KEY_FETCH_SECONDS
+        .with_label_values(&[service])
+        .observe(elapsed.as_secs_f64());
+    if !ok {
+        KEY_FETCH_FAILURES.with_label_values(&[service]).inc();
+    }
+}
+
+/// Count a lookup in the key cache.
+pub fn key_cache(hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+    KEY_CACHE.with_label_values(&[result]).inc();
+}
+
+/// Record the time until the first encapsulated chunk of a response is sent.
+pub fn first_chunk(elapsed: Duration) {
+    FIRST_CHUNK_SECONDS.observe(elapsed.as_secs_f64());
+}
+
+/// Count a chunk of `len` bytes streamed in the given direction.
+pub fn chunk(direction: &str, len: usize) {
+    CHUNKS.with_label_values(&[direction]).inc();
+    BYTES
+        .with_label_values(&[direction])
+        .inc_by(u64::try_from(len).unwrap_or(u64::MAX));
+}
+
+/// Encode all metrics in the Prometheus text format.
+///
+/// # Errors
+/// If the metrics cannot be encoded.
+pub fn encode() -> Res<String> {
+    let mut buf = Vec::new();
+    TextEncoder::new().encode(&prometheus::gather(), &mut buf)?;
+    Ok(String::from_utf8(buf)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{encode, request};
+
+    #[test]
+    fn render_counter() {
+        request("render_counter_test");
+        request("render_counter_test");
+        let text = encode().unwrap();
+        assert!(text.contains("# TYPE ohttp_requests_total counter"));
+        assert!(text.contains(r#"ohttp_requests_total{outcome="render_counter_test"} 2"#));
+    }
+}
@@ -0,0 +1,377 @@
+use std::{collections::HashMap, fs, path::Path, time::Duration};
+
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Method, Url,
+};
+use serde::Deserialize;
+
+use crate::err::ServerError;
+
+type Res<T> = Result<T, Box<dyn std::error::Error>>;
+
+/// A route, as written in the routing configuration file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RouteConfig {
+    /// Match the authority of the inner request, ignoring case.
+    authority: Option<String>,
+    /// Match a prefix of the path of the inner request, in whole segments.
+    path_prefix: Option<String>,
+    /// The backend URL that matching requests are sent to.  The path of
+    /// the inner request is appended to the path of this URL.
+    target: String,
+    /// Timeout for the whole request to the backend.
+    timeout_secs: Option<u64>,
+    /// Allowed methods. Any method is allowed if this is absent.
+    methods: Option<Vec<String>>,
+    /// Headers that are set on the request to the backend, replacing any
+    /// value provided by the client.
+    #[serde(default)]
+    set_headers: HashMap<String, String>,
+    /// Headers that are removed from the request to the backend.
+    #[serde(default)]
+    remove_headers: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RoutingConfig {
+    routes: Vec<RouteConfig>,
+}
+
+/// A single entry in the routing table.
+#[derive(Debug)]
+pub struct Route {
+    authority: Option<String>,
+    path_prefix: Option<String>,
+    target: Url,
+    timeout: Option<Duration>,
+    methods: Option<Vec<Method>>,
+    set_headers: HeaderMap,
+    remove_headers: Vec<HeaderName>,
+}
+
+impl Route {
+    /// A route that matches every request and sends it to `target`.
+    fn catch_all(target: Url) -> Self {
+        Self {
+            authority: None,
+            path_prefix: None,
+            target,
+            timeout: None,
+            methods: None,
+            set_headers: HeaderMap::new(),
+            remove_headers: Vec::new(),
+        }
+    }
+
+    fn from_config(config: RouteConfig) -> Res<Self> {
+        let target = Url::parse(&config.target)?;
+        if !matches!(target.scheme(), "http" | "https") {
+            return Err(Box::new(ServerError::InvalidRoute(format!(
+                "unsupported target scheme in {target}"
+            ))));
+        }
+        if target.query().is_some() || target.fragment().is_some() {
+            return Err(Box::new(ServerError::InvalidRoute(format!(
+                "target {target} can't have a query or fragment"
+            ))));
+        }
+        let methods = config
+            .methods
+            .map(|methods| {
+                methods
+                    .iter()
+                    .map(|m| Method::from_bytes(m.as_bytes()))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?;
+        let mut set_headers = HeaderMap::new();
+        for (name, value) in config.set_headers {
+            set_headers.insert(
+                HeaderName::from_bytes(name.as_bytes())?,
+                HeaderValue::from_str(&value)?,
+            );
+        }
+        let remove_headers = config
+            .remove_headers
+            .iter()
+            .map(|name| HeaderName::from_bytes(name.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            authority: config.authority,
+            path_prefix: config.path_prefix,
+            target,
+            timeout: config.timeout_secs.map(Duration::from_secs),
+            methods,
+            set_headers,
+            remove_headers,
+        })
+    }
+
+    fn matches(&self, authority: Option<&str>, path: Option<&str>) -> bool {
+        let authority_matches = self
+            .authority
+            .iter()
+            .all(|a| authority.is_some_and(|authority| a.eq_ignore_ascii_case(authority)));
+        let path_matches = self
+            .path_prefix
+            .iter()
+            .all(|prefix| path.is_some_and(|path| has_prefix(path, prefix)));
+        authority_matches && path_matches
+    }
+
+    /// Whether the route accepts requests with this method.
+    #[must_use]
+    pub fn allows(&self, method: &Method) -> bool {
+        self.methods.iter().all(|m| m.contains(method))
+    }
+
+    /// The timeout for requests sent on this route, if any.
+    #[must_use]
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// The URL for a request on this route, given a path from
+    /// [`normalize_path`].  The path of the inner request is appended to
+    /// the path of the target, and its query is used as is.
+    #[must_use]
+    pub fn target_url(&self, path: Option<&str>) -> Url {
+        let mut url = self.target.clone();
+        if let Some(path) = path {
+            let (path, query) = path
+                .split_once('?')
+                .map_or((path, None), |(p, q)| (p, Some(q)));
+            let base = url.path().trim_end_matches('/').to_owned();
+            url.set_path(&(base + path));
+            url.set_query(query);
+        }
+        url
+    }
+
+    /// Apply the header rules of this route.
+    pub fn apply_headers(&self, headers: &mut HeaderMap) {
+        for name in &self.remove_headers {
+            headers.remove(name);
+        }
+        for (name, value) in &self.set_headers {
+            headers.insert(name, value.clone());
+        }
+    }
+}
+
+/// Whether `path`, without its query, is `prefix` or is below it.  A prefix
+/// that doesn't end in `/` only matches whole segments, so `/v1/chat` matches
+/// `/v1/chat/completions` but not `/v1/chatty`.
+fn has_prefix(path: &str, prefix: &str) -> bool {
+    let path = path.split_once('?').map_or(path, |(p, _)| p);
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| prefix.ends_with('/') || rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Whether a byte can be decoded from a percent-encoding without changing
+/// the meaning of a path.  These are the unreserved characters of RFC 3986.
+fn is_unreserved(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~')
+}
+
+/// Prepare the path of an inner request for routing.  The path, without its
+/// query, has percent-encoded unreserved characters decoded, so that
+/// `/v1/%63hat` is matched as `/v1/chat`, which is also what is sent to the
+/// target.
+///
+/// This returns `None` if the path can't be routed safely: if it doesn't
+/// start with `/`, has a bad or encoded `/` or `\`, or has `.` or `..`
+/// segments.  Backends might decode or resolve any of these, so a path that
+/// matches a prefix could reach a path outside of it.
+#[must_use]
+pub fn normalize_path(path: &[u8]) -> Option<String> {
+    let path = std::str::from_utf8(path).ok()?;
+    let (path, query) = path
+        .split_once('?')
+        .map_or((path, None), |(p, q)| (p, Some(q)));
+    if !path.starts_with('/') {
+        return None;
+    }
+
+    let mut normalized = String::with_capacity(path.len());
+    let mut rest = path;
+    while let Some(i) = rest.find('%') {
+        normalized.push_str(&rest[..i]);
+        let hex = rest.get(i + 1..i + 3)?;
+        if !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let b = u8::from_str_radix(hex, 16).ok()?;
+        if is_unreserved(b) {
+            normalized.push(char::from(b));
+        } else if b == b'/' || b == b'\\' {
+            return None;
+        } else {
+            normalized.push('%');
+            normalized.push_str(&hex.to_ascii_uppercase());
+        }
+        rest = &rest[i + 3..];
+    }
+    normalized.push_str(rest);
+
+    if normalized
+        .split(['/', '\\'])
+        .any(|segment| segment == "." || segment == "..")
+    {
+        return None;
+    }
+    if let Some(query) = query {
+        normalized.push('?');
+        normalized.push_str(query);
+    }
+    Some(normalized)
+}
+
+/// Maps inner requests to backends, based on their authority and path.
+#[derive(Debug)]
+pub struct RoutingTable {
+    routes: Vec<Route>,
+}
+
+impl RoutingTable {
+    /// A table that sends every request to a single target.
+    #[must_use]
+    pub fn single(target: Url) -> Self {
+        Self {
+            routes: vec![Route::catch_all(target)],
+        }
+    }
+
+    /// Load a routing table from a JSON file.
+    /// Routes are matched in the order that they appear.
+    ///
+    /// # Errors
+    /// If the file cannot be read or parsed, or if it contains no routes.
+    pub fn load(path: &Path) -> Res<Self> {
+        let config: RoutingConfig = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let routes = config
+            .routes
+            .into_iter()
+            .map(Route::from_config)
+            .collect::<Res<Vec<_>>>()?;
+        if routes.is_empty() {
+            return Err(Box::new(ServerError::InvalidRoute(
+                "no routes are configured".into(),
+            )));
+        }
+        Ok(Self { routes })
+    }
+
+    /// The targets of all of the routes.
+    pub fn targets(&self) -> impl Iterator<Item = &Url> {
+        self.routes.iter().map(|r| &r.target)
+    }
+
+    /// Find the first route that matches the authority and path of a request.
+    /// The path comes from [`normalize_path`].
+    #[must_use]
+    pub fn select(&self, authority: Option<&[u8]>, path: Option<&str>) -> Option<&Route> {
+        let authority = authority.and_then(|a| std::str::from_utf8(a).ok());
+        self.routes.iter().find(|r| r.matches(authority, path))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{normalize_path, Route, RouteConfig, RoutingTable};
+    use reqwest::{Method, Url};
+
+    fn route(authority: Option<&str>, path_prefix: Option<&str>, target: &str) -> Route {
+        Route::from_config(RouteConfig {
+            authority: authority.map(ToOwned::to_owned),
+            path_prefix: path_prefix.map(ToOwned::to_owned),
+            target: target.to_owned(),
+            timeout_secs: None,
+            methods: Some(vec!["POST".into()]),
+            set_headers: [("x-route".to_owned(), "1".to_owned())].into(),
+            remove_headers: vec!["authorization".into()],
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn select_route() {
+        let table = RoutingTable {
+            routes: vec![
+                route(Some("whisper.internal"), None, "http://127.0.0.1:3000"),
+                route(None, Some("/v1/chat"), "http://127.0.0.1:8000"),
+            ],
+        };
+
+        let r = table
+            .select(Some(b"Whisper.Internal"), Some("/v1/chat"))
+            .unwrap();
+        assert_eq!(r.target.port(), Some(3000));
+        let r = table
+            .select(Some(b"other"), Some("/v1/chat/completions"))
+            .unwrap();
+        assert_eq!(r.target.port(), Some(8000));
+        assert!(table.select(Some(b"other"), Some("/v1/embed")).is_none());
+        assert!(table.select(None, None).is_none());
+
+        // Prefixes match whole segments.
+        assert!(table.select(None, Some("/v1/chat?stream=1")).is_some());
+        assert!(table.select(None, Some("/v1/chatty")).is_none());
+    }
+
+    #[test]
+    fn normalize() {
+        let n = |p: &[u8]| normalize_path(p);
+        assert_eq!(n(b"/v1/chat?x=%2F").unwrap(), "/v1/chat?x=%2F");
+        assert_eq!(n(b"/v1/%63hat/%7e%3f").unwrap(), "/v1/chat/~%3F");
+        assert_eq!(n(b"/v1/chat/..x").unwrap(), "/v1/chat/..x");
+
+        // Dot segments and encoded separators could leave a prefix once a
+        // backend resolves or decodes them.
+        for path in [
+            &b"/v1/chat/../admin"[..],
+            b"/v1/chat/%2E%2e/admin",
+            b"/v1/chat\\..\\admin",
+            b"/v1/chat/./completions",
+            b"/v1/chat/x%2F..%2F..%2Fadmin",
+            b"/v1/chat/x%5c..%5cadmin",
+            b"/v1/chat/%zz",
+            b"/v1/chat/%+f",
+            b"/v1/chat/%2",
+            b"v1/chat",
+            b"*",
+        ] {
+            assert!(n(path).is_none(), "{}", String::from_utf8_lossy(path));
+        }
+    }
+
+    #[test]
+    fn route_rules() {
+        let r = route(None, None, "http://127.0.0.1:3000/api/");
+        assert!(r.allows(&Method::POST));
+        assert!(!r.allows(&Method::GET));
+        assert_eq!(
+            r.target_url(Some("/whisper?lang=en")),
+            Url::parse("http://127.0.0.1:3000/api/whisper?lang=en").unwrap()
+        );
+        assert_eq!(
+            route(None, None, "http://127.0.0.1:3000").target_url(Some("/whisper")),
+            Url::parse("http://127.0.0.1:3000/whisper").unwrap()
+        );
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("authorization", "secret".parse().unwrap());
+        headers.insert("x-route", "client".parse().unwrap());
+        r.apply_headers(&mut headers);
+        assert!(headers.get("authorization").is_none());
+        assert_eq!(headers.get("x-route").unwrap(), "1");
+
+        let config: RouteConfig =
+            serde_json::from_str(r#"{"target": "http://127.0.0.1:3000/?key=1"}"#).unwrap();
+        assert!(Route::from_config(config).is_err());
+    }
+}
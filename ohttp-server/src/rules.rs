@@ -0,0 +1,325 @@
+use std::{fs, path::Path};
+
+use ohttp::SymmetricSuite;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE};
+use serde::Deserialize;
+
+use crate::{
+    err::{GatewayError, ServerError},
+    headers::remove_response_headers,
+};
+
+type Res<T> = Result<T, Box<dyn std::error::Error>>;
+
+/// The outer header that sets the target path when no rules are configured.
+const DEFAULT_PATH_HEADER: &str = "enginetarget";
+
+/// Values that the gateway knows about a request, which rules can use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Source {
+    /// The identifier of the key that the request was encapsulated with.
+    KeyId,
+    /// The KDF and AEAD that the client chose, as `kdf/aead`.
+    Suite,
+    /// The attestation token of the gateway.
+    AttestationToken,
+}
+
+/// A rule for the request to the target, as written in the rules file.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case", deny_unknown_fields)]
+enum RequestRuleConfig {
+    Set { name: String, value: String },
+    Copy { from: String, to: Option<String> },
+    Remove { name: String },
+    SetFrom { name: String, source: Source },
+    PathFrom { from: String },
+}
+
+/// A rule for the response from the target, as written in the rules file.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case", deny_unknown_fields)]
+enum ResponseRuleConfig {
+    Set { name: String, value: String },
+    Remove { name: String },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesConfig {
+    #[serde(default)]
+    request: Vec<RequestRuleConfig>,
+    #[serde(default)]
+    response: Vec<ResponseRuleConfig>,
+}
+
+#[derive(Debug)]
+enum RequestRule {
+    /// Set an inner header to a fixed value.
+    Set(HeaderName, HeaderValue),
+    /// Copy an outer header into the inner request, possibly renaming it.
+    Copy(HeaderName, HeaderName),
+    /// Remove an inner header.
+    Remove(HeaderName),
+    /// Set an inner header from what the gateway knows about the request.
+    SetFrom(HeaderName, Source),
+    /// Take the target path from an outer header.
+    PathFrom(HeaderName),
+}
+
+#[derive(Debug)]
+enum ResponseRule {
+    Set(HeaderName, HeaderValue),
+    Remove(HeaderName),
+}
+
+fn header_name(name: &str) -> Res<HeaderName> {
+    Ok(HeaderName::from_bytes(name.as_bytes())?)
+}
+
+/// What a request rule can refer to.
+pub struct RequestContext<'a> {
+    /// The headers of the outer request.
+    pub outer: &'a HeaderMap,
+    pub key_id: u8,
+    pub suite: SymmetricSuite,
+    pub attestation_token: &'a str,
+}
+
+impl RequestContext<'_> {
+    fn value(&self, source: Source) -> Result<HeaderValue, GatewayError> {
+        let value = match source {
+            Source::KeyId => HeaderValue::from(u16::from(self.key_id)),
+            Source::Suite => {
+                HeaderValue::from_str(&format!("{:?}/{:?}", self.suite.kdf(), self.suite.aead()))
+                    .map_err(|e| GatewayError::Internal(e.to_string()))?
+            }
+            Source::AttestationToken => HeaderValue::from_str(self.attestation_token)
+                .map_err(|e| GatewayError::Internal(e.to_string()))?,
+        };
+        Ok(value)
+    }
+}
+
+/// Rules that rewrite the headers of requests to targets and of the
+/// responses that come back.
+#[derive(Debug)]
+pub struct HeaderRules {
+    request: Vec<RequestRule>,
+    response: Vec<ResponseRule>,
+}
+
+impl HeaderRules {
+    /// The rules that apply when no rules file is given.  Unless there is
+    /// a routing table, the `enginetarget` outer header sets the target path.
+    #[must_use]
+    pub fn default_rules(path_from_outer: bool) -> Self {
+        let mut request = Vec::new();
+        if path_from_outer {
+            request.push(RequestRule::PathFrom(HeaderName::from_static(
+                DEFAULT_PATH_HEADER,
+            )));
+        }
+        Self {
+            request,
+            response: Vec::new(),
+        }
+    }
+
+    fn from_config(config: RulesConfig) -> Res<Self> {
+        let mut request = Vec::new();
+        for rule in config.request {
+            request.push(match rule {
+                RequestRuleConfig::Set { name, value } => {
+                    RequestRule::Set(header_name(&name)?, HeaderValue::from_str(&value)?)
+                }
+                RequestRuleConfig::Copy { from, to } => {
+                    let from = header_name(&from)?;
+                    let to = to
+                        .as_deref()
+                        .map_or_else(|| Ok(from.clone()), header_name)?;
+                    RequestRule::Copy(from, to)
+                }
+                RequestRuleConfig::Remove { name } => RequestRule::Remove(header_name(&name)?),
+                RequestRuleConfig::SetFrom { name, source } => {
+                    RequestRule::SetFrom(header_name(&name)?, source)
+                }
+                RequestRuleConfig::PathFrom { from } => RequestRule::PathFrom(header_name(&from)?),
+            });
+        }
+
+        let mut response = Vec::new();
+        for rule in config.response {
+            let rule = match rule {
+                ResponseRuleConfig::Set { name, value } => {
+                    ResponseRule::Set(header_name(&name)?, HeaderValue::from_str(&value)?)
+                }
+                ResponseRuleConfig::Remove { name } => ResponseRule::Remove(header_name(&name)?),
+            };
+            let (ResponseRule::Set(name, _) | ResponseRule::Remove(name)) = &rule;
+            if name == CONTENT_TYPE || name == CONTENT_LENGTH {
+                return Err(Box::new(ServerError::InvalidRules(format!(
+                    "{name} cannot be changed in responses"
+                ))));
+            }
+            response.push(rule);
+        }
+        Ok(Self { request, response })
+    }
+
+    /// Load rules from a JSON file.
+    /// Rules are applied in the order that they appear.
+    ///
+    /// # Errors
+    /// If the file cannot be read or parsed, or if a header is not valid.
+    pub fn load(path: &Path) -> Res<Self> {
+        let config: RulesConfig = serde_json::from_str(&fs::read_to_string(path)?)?;
+        Self::from_config(config)
+    }
+
+    /// Apply the request rules to the headers of an inner request.
+    /// This returns the target path that was set from an outer header, if any.
+    ///
+    /// # Errors
+    /// If a value from the request context is not a valid header value.
+    pub fn apply_request<'a>(
+        &self,
+        context: &RequestContext<'a>,
+        headers: &mut HeaderMap,
+    ) -> Result<Option<&'a HeaderValue>, GatewayError> {
+        let mut path = None;
+        for rule in &self.request {
+            match rule {
+                RequestRule::Set(name, value) => {
+                    headers.insert(name, value.clone());
+                }
+                RequestRule::Copy(from, to) => {
+                    for value in context.outer.get_all(from) {
+                        headers.append(to, value.clone());
+                    }
+                }
+                RequestRule::Remove(name) => {
+                    headers.remove(name);
+                }
+                RequestRule::SetFrom(name, source) => {
+                    headers.insert(name, context.value(*source)?);
+                }
+                RequestRule::PathFrom(from) => {
+                    if let Some(value) = context.outer.get(from) {
+                        path = Some(value);
+                    }
+                }
+            }
+        }
+        Ok(path)
+    }
+
+    /// Select the headers of a response from the target that are passed on
+    /// to the client, and apply the response rules to them.
+    /// `Content-Type` and `Content-Length` describe the content of the inner
+    /// response, so they are never copied to the outer response.  Neither are
+    /// hop-by-hop headers, `Server`, or `Set-Cookie`.
+    #[must_use]
+    pub fn response_headers(&self, target: &HeaderMap) -> HeaderMap {
+        let mut headers = target.clone();
+        headers.remove(CONTENT_TYPE);
+        remove_response_headers(&mut headers);
+        for rule in &self.response {
+            match rule {
+                ResponseRule::Set(name, value) => {
+                    headers.insert(name, value.clone());
+                }
+                ResponseRule::Remove(name) => {
+                    headers.remove(name);
+                }
+            }
+        }
+        headers
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{HeaderRules, RequestContext, RulesConfig};
+    use ohttp::{
+        hpke::{Aead, Kdf},
+        SymmetricSuite,
+    };
+    use reqwest::header::HeaderMap;
+
+    const RULES: &str = r#"{
+        "request": [
+            { "action": "set", "name": "x-gateway", "value": "ohttp" },
+            { "action": "copy", "from": "x-relay-user", "to": "x-user" },
+            { "action": "copy", "from": "x-feature" },
+            { "action": "remove", "name": "cookie" },
+            { "action": "set_from", "name": "x-ohttp-key-id", "source": "key_id" },
+            { "action": "set_from", "name": "x-ohttp-suite", "source": "suite" },
+            { "action": "path_from", "from": "x-target-path" }
+        ],
+        "response": [
+            { "action": "remove", "name": "server" },
+            { "action": "set", "name": "x-served-by", "value": "gateway" }
+        ]
+    }"#;
+
+    fn rules() -> HeaderRules {
+        let config: RulesConfig = serde_json::from_str(RULES).unwrap();
+        HeaderRules::from_config(config).unwrap()
+    }
+
+    #[test]
+    fn request_rules() {
+        let mut outer = HeaderMap::new();
+        outer.insert("x-relay-user", "alice".parse().unwrap());
+        outer.insert("x-feature", "asr".parse().unwrap());
+        outer.insert("x-target-path", "/v1/audio".parse().unwrap());
+        let context = RequestContext {
+            outer: &outer,
+            key_id: 7,
+            suite: SymmetricSuite::new(Kdf::HkdfSha256, Aead::Aes128Gcm),
+            attestation_token: "",
+        };
+
+        let mut headers = HeaderMap::new();
+        headers.insert("cookie", "secret".parse().unwrap());
+        headers.insert("x-gateway", "client".parse().unwrap());
+        let path = rules().apply_request(&context, &mut headers).unwrap();
+
+        assert_eq!(path.unwrap(), "/v1/audio");
+        assert_eq!(headers.get("x-gateway").unwrap(), "ohttp");
+        assert_eq!(headers.get("x-user").unwrap(), "alice");
+        assert_eq!(headers.get("x-feature").unwrap(), "asr");
+        assert!(headers.get("x-relay-user").is_none());
+        assert!(headers.get("cookie").is_none());
+        assert_eq!(headers.get("x-ohttp-key-id").unwrap(), "7");
+        assert_eq!(
+            headers.get("x-ohttp-suite").unwrap(),
+            "HkdfSha256/Aes128Gcm"
+        );
+    }
+
+    #[test]
+    fn response_rules() {
+        let mut target = HeaderMap::new();
+        target.insert("server", "uvicorn".parse().unwrap());
+        target.insert("content-type", "text/plain".parse().unwrap());
+        target.insert("x-request-id", "1".parse().unwrap());
+        target.insert("set-cookie", "session=1".parse().unwrap());
+        target.insert("transfer-encoding", "chunked".parse().unwrap());
+        let headers = rules().response_headers(&target);
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers.get("x-served-by").unwrap(), "gateway");
+        assert_eq!(headers.get("x-request-id").unwrap(), "1");
+    }
+
+    #[test]
+    fn framing_is_fixed() {
+        let config: RulesConfig = serde_json::from_str(
+            r#"{ "response": [{ "action": "set", "name": "content-type", "value": "x" }] }"#,
+        )
+        .unwrap();
+        assert!(HeaderRules::from_config(config).is_err());
+    }
+}
@@ -0,0 +1,315 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use reqwest::{
+    header::HeaderMap, Body, Client, ClientBuilder, Method, RequestBuilder, Response, StatusCode,
+    Url,
+};
+use tokio::time::{sleep, timeout};
+use tracing::{info, warn};
+
+use crate::{err::GatewayError, routes::RoutingTable};
+
+type Res<T> = Result<T, Box<dyn std::error::Error>>;
+
+/// The delay before the first retry.  This doubles for each retry.
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Settings for the connections to targets.
+#[derive(Debug, Clone)]
+pub struct TargetSettings {
+    /// The longest time to wait for a connection.
+    pub connect_timeout: Duration,
+    /// The longest time to wait for response headers, or for the next
+    /// piece of the response content.
+    pub read_timeout: Duration,
+    /// The longest time for a whole request, unless the route sets one.
+    pub timeout: Option<Duration>,
+    /// The number of idle connections to keep for each target.
+    pub pool_max_idle: usize,
+    /// How many times idempotent requests are retried.
+    pub retries: u32,
+    /// The number of consecutive failures that stops requests to a target.
+    /// Zero disables the circuit breaker.
+    pub failure_threshold: u32,
+    /// How long requests to a failing target are stopped for.
+    pub cooldown: Duration,
+}
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    failures: u32,
+    open_until: Option<Instant>,
+    /// The request that is probing the target, if any.
+    probe: Option<u64>,
+    /// The number of probes so far, which tells them apart.
+    probes: u64,
+}
+
+/// Stops requests to a target after repeated failures, so that they fail
+/// quickly.  After a cooldown, a single request is let through to probe the
+/// target; if that succeeds, the breaker closes again.
+#[derive(Debug)]
+struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Permission to send a request now, if it can be sent.
+    fn allow(&self) -> Option<Permit<'_>> {
+        let mut state = self.state.lock().unwrap();
+        let probe = match state.open_until {
+            None => None,
+            Some(until) if Instant::now() >= until && state.probe.is_none() => {
+                state.probes = state.probes.wrapping_add(1);
+                state.probe = Some(state.probes);
+                state.probe
+            }
+            Some(_) => return None,
+        };
+        Some(Permit {
+            breaker: self,
+            probe,
+        })
+    }
+
+    fn success(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.failures = 0;
+        state.open_until = None;
+        state.probe = None;
+    }
+
+    fn failure(&self, target: &str) {
+        if self.threshold == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.failures = state.failures.saturating_add(1);
+        if state.probe.is_some() || state.failures >= self.threshold {
+            warn!("Stopping requests to {target} for {:?}", self.cooldown);
+            state.open_until = Some(Instant::now() + self.cooldown);
+            state.probe = None;
+        }
+    }
+}
+
+/// Permission from a `CircuitBreaker` to send a request.  If the request is
+/// a probe and it is dropped before its outcome is recorded, for instance
+/// because the client went away, another request can probe the target.
+struct Permit<'a> {
+    breaker: &'a CircuitBreaker,
+    probe: Option<u64>,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if let Some(probe) = self.probe {
+            let mut state = self.breaker.state.lock().unwrap();
+            if state.probe == Some(probe) {
+                state.probe = None;
+            }
+        }
+    }
+}
+
+/// A target, with its own connection pool and circuit breaker.
+struct Target {
+    client: Client,
+    breaker: CircuitBreaker,
+}
+
+/// The targets of all routes.
+pub struct Targets {
+    targets: HashMap<String, Target>,
+    settings: TargetSettings,
+}
+
+/// Whether a response status means that the target is unwell.
+/// A request that gets one of these can be retried.
+fn is_unavailable(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+impl Targets {
+    /// Create a client for each target in the routing table.
+    /// `builder` provides the TLS configuration for the clients.
+    ///
+    /// # Errors
+    /// If a client cannot be created.
+    pub fn new(
+        routes: &RoutingTable,
+        builder: impl Fn() -> Res<ClientBuilder>,
+        settings: TargetSettings,
+    ) -> Res<Self> {
+        let mut targets = HashMap::new();
+        for url in routes.targets() {
+            let origin = url.origin().ascii_serialization();
+            if targets.contains_key(&origin) {
+                continue;
+            }
+            let client = builder()?
+                .connect_timeout(settings.connect_timeout)
+                .pool_max_idle_per_host(settings.pool_max_idle)
+                .build()?;
+            let breaker = CircuitBreaker::new(settings.failure_threshold, settings.cooldown);
+            targets.insert(origin, Target { client, breaker });
+        }
+        Ok(Self { targets, settings })
+    }
+
+    /// The longest time to wait for the next piece of a response.
+    #[must_use]
+    pub fn read_timeout(&self) -> Duration {
+        self.settings.read_timeout
+    }
+
+    async fn send_once(&self, request: RequestBuilder) -> Result<Response, GatewayError> {
+        match timeout(self.settings.read_timeout, request.send()).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(e)) => Err(GatewayError::from_target(e)),
+            Err(_) => Err(GatewayError::TargetNoResponse),
+        }
+    }
+
+    /// Send a request to a target.  Idempotent requests with content that
+    /// is held in memory are retried if the target can't be reached or
+    /// reports that it is unavailable.  No part of a response has been
+    /// passed on at that point, so the client doesn't see the retries.
+    ///
+    /// # Errors
+    /// If the target is failing, or if the request fails.
+    pub async fn send(
+        &self,
+        method: Method,
+        url: Url,
+        headers: HeaderMap,
+        body: Body,
+        route_timeout: Option<Duration>,
+    ) -> Result<Response, GatewayError> {
+        let origin = url.origin().ascii_serialization();
+        let target = self
+            .targets
+            .get(&origin)
+            .ok_or_else(|| GatewayError::Internal(format!("no client for {origin}")))?;
+        // This is held until the outcome of the request is recorded.
+        let Some(mut permit) = target.breaker.allow() else {
+            return Err(GatewayError::TargetUnavailable(self.settings.cooldown));
+        };
+
+        let retries = if method.is_idempotent() {
+            self.settings.retries
+        } else {
+            0
+        };
+        let mut request = target
+            .client
+            .request(method, url)
+            .headers(headers)
+            .body(body);
+        if let Some(t) = route_timeout.or(self.settings.timeout) {
+            request = request.timeout(t);
+        }
+
+        let mut attempt = 0;
+        loop {
+            let next = if attempt < retries {
+                request.try_clone()
+            } else {
+                None
+            };
+            let result = self.send_once(request).await;
+            let failed = match &result {
+                Ok(response) => is_unavailable(response.status()),
+                Err(_) => true,
+            };
+            if !failed {
+                target.breaker.success();
+                drop(permit);
+                return result;
+            }
+            target.breaker.failure(&origin);
+            drop(permit);
+
+            let Some(r) = next else {
+                return result;
+            };
+            let Some(p) = target.breaker.allow() else {
+                return result;
+            };
+            permit = p;
+            let delay = RETRY_DELAY * 2_u32.pow(attempt);
+            info!("Retrying request to {origin} in {delay:?}");
+            sleep(delay).await;
+            request = r;
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CircuitBreaker;
+    use std::time::Duration;
+
+    #[test]
+    fn circuit_breaker() {
+        let breaker = CircuitBreaker::new(2, Duration::from_millis(0));
+        assert!(breaker.allow().is_some());
+        breaker.failure("t");
+        assert!(breaker.allow().is_some());
+        breaker.failure("t");
+
+        // Open, but the cooldown has passed, so one probe is allowed.
+        let probe = breaker.allow();
+        assert!(probe.is_some());
+        assert!(breaker.allow().is_none());
+        breaker.failure("t");
+        drop(probe);
+        let probe = breaker.allow();
+        assert!(probe.is_some());
+        breaker.success();
+        assert!(breaker.allow().is_some());
+        assert!(breaker.allow().is_some());
+    }
+
+    #[test]
+    fn circuit_breaker_dropped_probe() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(0));
+        breaker.failure("t");
+        let probe = breaker.allow();
+        assert!(probe.is_some());
+        assert!(breaker.allow().is_none());
+
+        // The probe went away without an outcome, so another can start.
+        drop(probe);
+        let probe = breaker.allow();
+        assert!(probe.is_some());
+        assert!(breaker.allow().is_none());
+    }
+
+    #[test]
+    fn circuit_breaker_disabled() {
+        let breaker = CircuitBreaker::new(0, Duration::from_secs(60));
+        for _ in 0..10 {
+            breaker.failure("t");
+        }
+        assert!(breaker.allow().is_some());
+    }
+}
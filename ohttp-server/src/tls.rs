@@ -0,0 +1,200 @@
+use std::{
+    fs::{self, File},
+    io::BufReader,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+
+use futures::{channel::mpsc, Stream};
+use reqwest::{Certificate as ReqwestCertificate, ClientBuilder, Identity};
+use rustls::{
+    server::{AllowAnyAuthenticatedClient, ClientHello, ResolvesServerCert},
+    sign::{self, CertifiedKey},
+    Certificate, PrivateKey, RootCertStore, ServerConfig,
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    time::{interval, Duration},
+};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use tracing::{error, info};
+
+use crate::err::ServerError;
+
+type Res<T> = Result<T, Box<dyn std::error::Error>>;
+
+/// How often the certificate and key files are checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+
+fn read_certs(path: &Path) -> Res<Vec<Certificate>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(path)?))?;
+    if certs.is_empty() {
+        return Err(Box::new(ServerError::Tls(format!(
+            "no certificates in {}",
+            path.display()
+        ))));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn read_key(path: &Path) -> Res<PrivateKey> {
+    for item in rustls_pemfile::read_all(&mut BufReader::new(File::open(path)?))? {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => {}
+        }
+    }
+    Err(Box::new(ServerError::Tls(format!(
+        "no private key in {}",
+        path.display()
+    ))))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// A server certificate that is loaded again when its files change.
+struct ReloadingCert {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ReloadingCert {
+    fn load(cert_path: &Path, key_path: &Path) -> Res<CertifiedKey> {
+        let certs = read_certs(cert_path)?;
+        let key = sign::any_supported_type(&read_key(key_path)?)?;
+        Ok(CertifiedKey::new(certs, key))
+    }
+
+    fn new(cert_path: PathBuf, key_path: PathBuf) -> Res<Self> {
+        let current = RwLock::new(Arc::new(Self::load(&cert_path, &key_path)?));
+        Ok(Self {
+            cert_path,
+            key_path,
+            current,
+        })
+    }
+
+    /// Periodically check the files, and load them again if they change.
+    /// If loading fails, the old certificate stays in use.
+    async fn watch(self: Arc<Self>) {
+        let mut last = (modified(&self.cert_path), modified(&self.key_path));
+        let mut timer = interval(RELOAD_INTERVAL);
+        loop {
+            timer.tick().await;
+            let now = (modified(&self.cert_path), modified(&self.key_path));
+            if now == last {
+                continue;
+            }
+            last = now;
+            match Self::load(&self.cert_path, &self.key_path) {
+                Ok(key) => {
+                    info!("Reloaded TLS certificate from {}", self.cert_path.display());
+                    *self.current.write().unwrap() = Arc::new(key);
+                }
+                Err(e) => error!("Failed to reload TLS certificate: {e}"),
+            }
+        }
+    }
+}
+
+impl ResolvesServerCert for ReloadingCert {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(Arc::clone(&self.current.read().unwrap()))
+    }
+}
+
+/// Listen for TLS connections.  The certificate is reloaded when its files
+/// change.  If `client_ca` is set, clients (normally relays) need to present
+/// a certificate that is signed by one of the CA certificates in that file.
+///
+/// # Errors
+/// If the listener cannot be bound or the certificates cannot be loaded.
+pub async fn incoming(
+    address: SocketAddr,
+    cert: PathBuf,
+    key: PathBuf,
+    client_ca: Option<&Path>,
+) -> Res<impl Stream<Item = Result<TlsStream<TcpStream>, std::io::Error>>> {
+    let resolver = Arc::new(ReloadingCert::new(cert, key)?);
+    tokio::spawn(Arc::clone(&resolver).watch());
+
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = if let Some(client_ca) = client_ca {
+        let mut roots = RootCertStore::empty();
+        for cert in read_certs(client_ca)? {
+            roots.add(&cert)?;
+        }
+        builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+    } else {
+        builder.with_no_client_auth()
+    };
+    let mut config = builder.with_cert_resolver(resolver);
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+
+    // Complete handshakes in separate tasks, so that a slow client does not
+    // hold up other connections.
+    let listener = TcpListener::bind(address).await?;
+    let (sender, receiver) = mpsc::unbounded();
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(s) => s,
+                Err(e) => {
+                    error!("Failed to accept connection: {e}");
+                    continue;
+                }
+            };
+            let acceptor = acceptor.clone();
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                match acceptor.accept(stream).await {
+                    Ok(stream) => _ = sender.unbounded_send(Ok(stream)),
+                    Err(e) => error!("TLS handshake with {peer} failed: {e}"),
+                }
+            });
+        }
+    });
+    Ok(receiver)
+}
+
+/// Configure a client for an upstream service (a target or the KMS).
+/// Certificates in `ca` are trusted in addition to the built-in roots, and
+/// `cert` and `key` are used to authenticate to the service.
+///
+/// # Errors
+/// If a file cannot be read or parsed.
+pub fn client_builder(
+    builder: ClientBuilder,
+    ca: Option<&Path>,
+    cert: Option<&Path>,
+    key: Option<&Path>,
+) -> Res<ClientBuilder> {
+    let mut builder = builder;
+    if let Some(ca) = ca {
+        for cert in ReqwestCertificate::from_pem_bundle(&fs::read(ca)?)? {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+    match (cert, key) {
+        (Some(cert), Some(key)) => {
+            let mut pem = fs::read(cert)?;
+            pem.extend_from_slice(&fs::read(key)?);
+            builder = builder.identity(Identity::from_pem(&pem)?);
+        }
+        (None, None) => {}
+        _ => {
+            return Err(Box::new(ServerError::Tls(
+                "a client certificate needs both a certificate and a key".into(),
+            )))
+        }
+    }
+    Ok(builder)
+}
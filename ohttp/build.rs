@@ -6,6 +6,17 @@
 
 #![deny(clippy::pedantic)]
 
+// Checking feature combinations here means that a bad combination stops the build
+// with just this error, rather than with a pile of errors from the library.
+#[cfg(any(
+    all(feature = "nss", feature = "rust-hpke"),
+    all(feature = "nss", feature = "openssl"),
+    all(feature = "openssl", feature = "rust-hpke"),
+))]
+compile_error!("only one of the `nss`, `openssl`, and `rust-hpke` features can be enabled");
+#[cfg(all(any(feature = "pq", feature = "pq-draft"), not(feature = "rust-hpke")))]
+compile_error!("the `pq` and `pq-draft` features need the `rust-hpke` feature");
+
 #[cfg(feature = "nss")]
 mod nss {
     use bindgen::Builder;
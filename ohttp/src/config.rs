@@ -11,13 +11,20 @@ use std::{
 
 #[cfg(feature = "nss")]
 use crate::nss::{
-    hpke::{generate_key_pair, Config as HpkeConfig, HpkeR},
+    hpke::{generate_key_pair, import_key_pair, Config as HpkeConfig, HpkeR},
     PrivateKey, PublicKey,
 };
 
 #[cfg(feature = "rust-hpke")]
 use crate::rh::hpke::{
-    derive_key_pair, generate_key_pair, Config as HpkeConfig, HpkeR, PrivateKey, PublicKey,
+    derive_key_pair, generate_key_pair, import_key_pair, Config as HpkeConfig, HpkeR, PrivateKey,
+    PublicKey,
+};
+
+#[cfg(feature = "openssl")]
+use crate::ossl::hpke::{
+    derive_key_pair, generate_key_pair, import_key_pair, Config as HpkeConfig, HpkeR, PrivateKey,
+    PublicKey,
 };
 
 /// A tuple of KDF and AEAD identifiers.
@@ -44,6 +51,45 @@ impl SymmetricSuite {
     }
 }
 
+/// The reason that an entry in a list of key configurations can't be used.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum Rejection {
+    #[error("the KEM ID {0:#06x} is not known")]
+    UnknownKem(u16),
+    #[error("the KEM {0:?} is not supported")]
+    UnsupportedKem(Kem),
+    #[error("the public key has {actual} of {expected} bytes")]
+    TruncatedPublicKey { expected: usize, actual: usize },
+    #[error("the public key is not valid")]
+    InvalidPublicKey,
+    #[error("the KDF and AEAD list is {0} bytes, which is not a multiple of 4")]
+    BadSuiteLength(usize),
+    #[error("the KDF ID {kdf:#06x} or AEAD ID {aead:#06x} is not known")]
+    UnknownSuite { kdf: u16, aead: u16 },
+    #[error("none of the KDF and AEAD pairs are supported")]
+    NoSupportedSuites,
+    #[error("{0} bytes follow the configuration")]
+    TrailingBytes(usize),
+    #[error("the configuration is truncated")]
+    Truncated,
+}
+
+/// An entry from a list of key configurations, with as much of it as could be
+/// decoded.  This is produced by `KeyConfig::decode_list_report`.
+#[derive(Debug)]
+pub struct KeyConfigEntry {
+    /// The key identifier, if the entry is long enough to include one.
+    pub key_id: Option<KeyId>,
+    /// The KEM identifier, if the entry is long enough to include one.
+    pub kem: Option<u16>,
+    /// The KDF and AEAD identifiers that the entry lists.
+    pub suites: Vec<(u16, u16)>,
+    /// The listed KDF and AEAD pairs that were removed because they are not supported.
+    pub stripped: Vec<SymmetricSuite>,
+    /// The configuration, or the reason that it can't be used.
+    pub config: Result<KeyConfig, Rejection>,
+}
+
 /// The key configuration of a server.  This can be used by both client and server.
 /// An important invariant of this structure is that it does not include
 /// any combination of KEM, KDF, and AEAD that is not supported.
@@ -79,9 +125,42 @@ impl KeyConfig {
         })
     }
 
-    /// Construct a configuration from an existing private key
+    /// Construct a configuration for the server side from an existing key pair,
+    /// using the serialized form of the private key and the public key.
+    /// This works with any backend, though the `nss` backend only supports X25519
+    /// and reports `Error::Unsupported` for P-384.
+    pub fn import(
+        key_id: u8,
+        kem: Kem,
+        sk: &[u8],
+        pk: &[u8],
+        mut symmetric: Vec<SymmetricSuite>,
+    ) -> Res<Self> {
+        Self::strip_unsupported(&mut symmetric, kem);
+        if symmetric.is_empty() {
+            return Err(Error::SymmetricKeyEmpty);
+        }
+        if pk.len() != kem.n_pk() {
+            return Err(Error::InvalidKeyType);
+        }
+        let (sk, pk) = import_key_pair(kem, sk, pk)?;
+        Ok(Self {
+            key_id,
+            kem,
+            symmetric,
+            sk: Some(sk),
+            pk,
+        })
+    }
+
+    /// Construct a configuration from an existing P-384 key pair from the `hpke` crate.
+    /// This is only available with `rust-hpke`; the `openssl` backend takes the encoded
+    /// keys through [`import()`], and the `nss` backend doesn't support P-384.
     /// # Panics
     /// If the configurations don't include a supported configuration.
+    ///
+    /// [`import()`]: Self::import
+    #[cfg(feature = "rust-hpke")]
     pub fn import_p384(
         key_id: u8,
         kem: Kem,
@@ -114,7 +193,7 @@ impl KeyConfig {
         mut symmetric: Vec<SymmetricSuite>,
         ikm: &[u8],
     ) -> Res<Self> {
-        #[cfg(feature = "rust-hpke")]
+        #[cfg(any(feature = "rust-hpke", feature = "openssl"))]
         {
             Self::strip_unsupported(&mut symmetric, kem);
             if symmetric.is_empty() {
@@ -129,12 +208,18 @@ impl KeyConfig {
                 pk,
             })
         }
-        #[cfg(not(feature = "rust-hpke"))]
+        #[cfg(feature = "nss")]
         {
             Err(Error::Unsupported)
         }
     }
 
+    /// The identifier of this key.
+    #[must_use]
+    pub fn key_id(&self) -> KeyId {
+        self.key_id
+    }
+
     /// Encode a list of key configurations.
     ///
     /// This produces the key configuration format that is used for
@@ -274,6 +359,111 @@ impl KeyConfig {
         Ok(configs)
     }
 
+    /// Decode a list of key configurations, reporting on every entry.
+    /// Unlike `decode_list`, this doesn't stop at the first bad entry and doesn't drop
+    /// unsupported entries, so that the reason for an empty list can be found.
+    /// An entry that runs past the end of the list ends the report.
+    #[must_use]
+    pub fn decode_list_report(encoded_list: &[u8]) -> Vec<KeyConfigEntry> {
+        let mut r = encoded_list;
+        let mut entries = Vec::new();
+        while !r.is_empty() {
+            let mut entry = KeyConfigEntry {
+                key_id: None,
+                kem: None,
+                suites: Vec::new(),
+                stripped: Vec::new(),
+                config: Err(Rejection::Truncated),
+            };
+            let Ok(len) = r.read_u16::<NetworkEndian>() else {
+                entries.push(entry);
+                break;
+            };
+            let len = usize::from(len);
+            let encoded = &r[..len.min(r.len())];
+            entry.config = Self::decode_entry(encoded, &mut entry);
+            if len > r.len() && entry.config.is_ok() {
+                entry.config = Err(Rejection::Truncated);
+            }
+            entries.push(entry);
+            if len > r.len() {
+                break;
+            }
+            r = &r[len..];
+        }
+        entries
+    }
+
+    /// Decode a key configuration for `decode_list_report`, filling in `entry` as fields
+    /// are decoded.
+    fn decode_entry(mut r: &[u8], entry: &mut KeyConfigEntry) -> Result<Self, Rejection> {
+        let key_id = r.read_u8().map_err(|_| Rejection::Truncated)?;
+        entry.key_id = Some(key_id);
+        let kem_id = r
+            .read_u16::<NetworkEndian>()
+            .map_err(|_| Rejection::Truncated)?;
+        entry.kem = Some(kem_id);
+
+        let kem = Kem::try_from(kem_id).map_err(|_| Rejection::UnknownKem(kem_id))?;
+        let kem_config = HpkeConfig::new(kem, Kdf::HkdfSha256, AeadId::Aes128Gcm);
+        if !kem_config.supported() {
+            return Err(Rejection::UnsupportedKem(kem));
+        }
+        let n_pk = kem_config.kem().n_pk();
+        if r.len() < n_pk {
+            return Err(Rejection::TruncatedPublicKey {
+                expected: n_pk,
+                actual: r.len(),
+            });
+        }
+        let (pk_buf, rest) = r.split_at(n_pk);
+        r = rest;
+
+        let sym_len = usize::from(
+            r.read_u16::<NetworkEndian>()
+                .map_err(|_| Rejection::Truncated)?,
+        );
+        if r.len() < sym_len {
+            return Err(Rejection::Truncated);
+        }
+        let (mut sym, rest) = r.split_at(sym_len);
+        if sym.is_empty() || (sym.len() % 4 != 0) {
+            return Err(Rejection::BadSuiteLength(sym.len()));
+        }
+        while !sym.is_empty() {
+            let kdf = sym.read_u16::<NetworkEndian>().unwrap();
+            let aead = sym.read_u16::<NetworkEndian>().unwrap();
+            entry.suites.push((kdf, aead));
+        }
+        if !rest.is_empty() {
+            return Err(Rejection::TrailingBytes(rest.len()));
+        }
+
+        let mut symmetric = Vec::with_capacity(entry.suites.len());
+        for &(kdf, aead) in &entry.suites {
+            let (Ok(kdf), Ok(aead)) = (Kdf::try_from(kdf), AeadId::try_from(aead)) else {
+                return Err(Rejection::UnknownSuite { kdf, aead });
+            };
+            symmetric.push(SymmetricSuite::new(kdf, aead));
+        }
+        entry.stripped = symmetric.clone();
+        Self::strip_unsupported(&mut symmetric, kem);
+        entry.stripped.retain(|s| !symmetric.contains(s));
+        if symmetric.is_empty() {
+            return Err(Rejection::NoSupportedSuites);
+        }
+
+        let pk = HpkeR::decode_public_key(kem_config.kem(), pk_buf)
+            .map_err(|_| Rejection::InvalidPublicKey)?;
+        Ok(Self {
+            key_id,
+            kem,
+            symmetric,
+            sk: None,
+            pk,
+        })
+    }
+
     /// Select creates a new configuration that contains the identified symmetric suite.
     ///
     /// # Errors
@@ -298,7 +488,7 @@ impl AsRef<Self> for KeyConfig {
 mod test {
     use crate::{
         hpke::{Aead, Kdf, Kem},
-        init, Error, KeyConfig, KeyId, SymmetricSuite,
+        init, Error, KeyConfig, KeyId, Rejection, SymmetricSuite,
     };
     use std::iter::zip;
 
@@ -399,4 +589,91 @@ mod test {
         x25519[36] = 1;
         assert!(matches!(KeyConfig::decode(&x25519), Err(Error::Format)));
     }
+
+    #[test]
+    fn decode_list_report() {
+        init();
+
+        let config = KeyConfig::new(KEY_ID, KEM, Vec::from(SYMMETRIC)).unwrap();
+        let good = config.encode().unwrap();
+        let mut unknown_kem = good.clone();
+        unknown_kem[1..3].copy_from_slice(&[0xff, 0xff]);
+        let mut unknown_suite = good.clone();
+        unknown_suite[39..41].copy_from_slice(&[0xff, 0xff]);
+        let mut trailing = good.clone();
+        trailing.push(0);
+        let truncated_pk = good[..20].to_vec();
+        let entries = [
+            &good,
+            &unknown_kem,
+            &unknown_suite,
+            &trailing,
+            &truncated_pk,
+        ];
+
+        let mut list = Vec::new();
+        for e in entries {
+            list.extend_from_slice(&u16::try_from(e.len()).unwrap().to_be_bytes());
+            list.extend_from_slice(e);
+        }
+        // An entry that runs past the end of the list.
+        list.extend_from_slice(&[0, 10, KEY_ID]);
+
+        let report = KeyConfig::decode_list_report(&list);
+        assert_eq!(report.len(), entries.len() + 1);
+        assert!(report.iter().all(|e| e.key_id == Some(KEY_ID)));
+
+        let decoded = report[0].config.as_ref().unwrap();
+        assert_eq!(
+            decoded.pk.key_data().unwrap(),
+            config.pk.key_data().unwrap()
+        );
+        assert_eq!(report[0].kem, Some(u16::from(KEM)));
+        assert_eq!(report[0].suites, [(0x0001, 0x0001), (0x0001, 0x0003)]);
+
+        assert_eq!(report[1].kem, Some(0xffff));
+        assert_eq!(
+            report[1].config.as_ref().unwrap_err(),
+            &Rejection::UnknownKem(0xffff)
+        );
+        assert!(matches!(
+            report[2].config,
+            Err(Rejection::UnknownSuite {
+                kdf: 1,
+                aead: 0xffff
+            })
+        ));
+        assert!(matches!(report[3].config, Err(Rejection::TrailingBytes(1))));
+        assert!(matches!(
+            report[4].config,
+            Err(Rejection::TruncatedPublicKey {
+                expected: 32,
+                actual: 17
+            })
+        ));
+        assert!(matches!(report[5].config, Err(Rejection::Truncated)));
+
+        // `decode_list` fails on the same list, without saying why.
+        assert!(KeyConfig::decode_list(&list).is_err());
+    }
+
+    #[test]
+    fn import() {
+        // skRm and pkRm from RFC 9180, Appendix A.1.1.
+        const SK: &str = "4612c550263fc8ad58375df3f557aac531d26850903e55a9f23f21d8534e8ac8";
+        const PK: &str = "3948cfe0ad1ddb695d780e59077195da6c56506b027329794ab02bca80815c4d";
+        init();
+
+        let sk = hex::decode(SK).unwrap();
+        let pk = hex::decode(PK).unwrap();
+        let config = KeyConfig::import(KEY_ID, KEM, &sk, &pk, Vec::from(SYMMETRIC)).unwrap();
+        let decoded = KeyConfig::decode(&config.encode().unwrap()).unwrap();
+        assert_eq!(decoded.pk.key_data().unwrap(), pk);
+        assert_eq!(config.sk.unwrap().key_data().unwrap(), sk);
+
+        assert!(matches!(
+            KeyConfig::import(KEY_ID, KEM, &sk, &pk[1..], Vec::from(SYMMETRIC)),
+            Err(Error::InvalidKeyType)
+        ));
+    }
 }
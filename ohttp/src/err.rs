@@ -7,15 +7,17 @@ pub enum Error {
     Aead(#[from] aead::Error),
     #[error("AEAD mode mismatch")]
     AeadMode,
+    #[error("a chunk exceeded the maximum size")]
+    ChunkTooLarge,
     #[cfg(feature = "nss")]
     #[error("a problem occurred during cryptographic processing: {0}")]
     Crypto(#[from] crate::nss::Error),
     #[error("an error was found in the format")]
     Format,
-    #[cfg(all(feature = "rust-hpke", not(feature = "pq")))]
+    #[cfg(all(feature = "rust-hpke", not(feature = "pq-draft")))]
     #[error("a problem occurred with HPKE: {0}")]
     Hpke(#[from] ::hpke::HpkeError),
-    #[cfg(all(feature = "rust-hpke", feature = "pq"))]
+    #[cfg(all(feature = "rust-hpke", feature = "pq-draft"))]
     #[error("a problem occurred with HPKE: {0}")]
     Hpke(#[from] ::hpke_pq::HpkeError),
     #[error("an internal error occurred")]
@@ -26,12 +28,19 @@ pub enum Error {
     InvalidKem,
     #[error("Invalid private key")]
     InvalidPrivateKey,
+    #[error("the pre-shared key or its identifier was invalid")]
+    InvalidPsk,
     #[error("io error: {0}")]
     Io(#[from] std::io::Error),
     #[error("the key ID was invalid")]
     KeyId,
     #[error("Returned a different key ID from the one requested : {0} {1}")]
     KeyIdMismatch(u8, u8),
+    #[cfg(feature = "openssl")]
+    #[error("a problem occurred in OpenSSL: {0}")]
+    OpenSsl(#[from] ::openssl::error::ErrorStack),
+    #[error("the input stream failed: {0}")]
+    Stream(String),
     #[error("Symmetric key is empty")]
     SymmetricKeyEmpty,
     #[error("the configuration contained too many symmetric suites")]
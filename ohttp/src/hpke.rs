@@ -1,3 +1,13 @@
+#[cfg(feature = "nss")]
+use crate::nss::hpke as imp;
+#[cfg(feature = "openssl")]
+use crate::ossl::hpke as imp;
+#[cfg(feature = "rust-hpke")]
+use crate::rh::hpke as imp;
+
+use crate::err::{Error, Res};
+use imp::Exporter;
+
 macro_rules! convert_enum {
     ($(#[$meta:meta])* $vis:vis enum $name:ident {
         $($(#[$vmeta:meta])* $vname:ident $(= $val:expr)?,)*
@@ -35,8 +45,16 @@ pub enum Kem {
 
     X25519Sha256 = 32,
 
-    #[cfg(feature = "pq")]
+    // The pre-standard Kyber hybrid, kept for compatibility.
+    #[cfg(feature = "pq-draft")]
     X25519Kyber768Draft00 = 48,
+
+    #[cfg(feature = "pq")]
+    MlKem768 = 0x0041,
+
+    // The X25519 and ML-KEM-768 hybrid from draft-connolly-cfrg-xwing-kem.
+    #[cfg(feature = "xwing")]
+    XWing = 0x647a,
 }
 }
 
@@ -48,8 +66,14 @@ impl Kem {
 
             Kem::X25519Sha256 => 32,
 
-            #[cfg(feature = "pq")]
+            #[cfg(feature = "pq-draft")]
             Kem::X25519Kyber768Draft00 => 1120,
+
+            #[cfg(feature = "pq")]
+            Kem::MlKem768 => 1088,
+
+            #[cfg(feature = "xwing")]
+            Kem::XWing => 1120,
         }
     }
 
@@ -60,8 +84,14 @@ impl Kem {
 
             Kem::X25519Sha256 => 32,
 
-            #[cfg(feature = "pq")]
+            #[cfg(feature = "pq-draft")]
             Kem::X25519Kyber768Draft00 => 1216,
+
+            #[cfg(feature = "pq")]
+            Kem::MlKem768 => 1184,
+
+            #[cfg(feature = "xwing")]
+            Kem::XWing => 1216,
         }
     }
 }
@@ -74,6 +104,18 @@ convert_enum! {
     }
 }
 
+impl Kdf {
+    /// The size of the output of the hash function for this KDF.
+    #[must_use]
+    pub fn n_h(self) -> usize {
+        match self {
+            Kdf::HkdfSha256 => 32,
+            Kdf::HkdfSha384 => 48,
+            Kdf::HkdfSha512 => 64,
+        }
+    }
+}
+
 convert_enum! {
     pub enum Aead {
         Aes128Gcm = 1,
@@ -107,3 +149,661 @@ impl Aead {
         16
     }
 }
+
+/// An HPKE configuration: the KEM, KDF, and AEAD to use together.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    kem: Kem,
+    kdf: Kdf,
+    aead: Aead,
+}
+
+impl Config {
+    #[must_use]
+    pub const fn new(kem: Kem, kdf: Kdf, aead: Aead) -> Self {
+        Self { kem, kdf, aead }
+    }
+
+    #[must_use]
+    pub fn kem(self) -> Kem {
+        self.kem
+    }
+
+    #[must_use]
+    pub fn kdf(self) -> Kdf {
+        self.kdf
+    }
+
+    #[must_use]
+    pub fn aead(self) -> Aead {
+        self.aead
+    }
+
+    /// Whether the selected backend supports this combination.
+    #[must_use]
+    pub fn supported(self) -> bool {
+        self.backend().supported()
+    }
+
+    fn backend(self) -> imp::Config {
+        imp::Config::new(self.kem, self.kdf, self.aead)
+    }
+}
+
+/// A public key for use with HPKE.
+#[derive(Clone, Debug)]
+pub struct PublicKey {
+    kem: Kem,
+    pub(crate) key: imp::PublicKey,
+}
+
+impl PublicKey {
+    /// Decode a serialized public key for the identified KEM.
+    pub fn decode(kem: Kem, k: &[u8]) -> Res<Self> {
+        if k.len() != kem.n_pk() {
+            return Err(Error::InvalidKeyType);
+        }
+        let key = imp::HpkeR::decode_public_key(kem, k)?;
+        Ok(Self { kem, key })
+    }
+
+    /// Serialize this public key.
+    pub fn encode(&self) -> Res<Vec<u8>> {
+        self.key.key_data()
+    }
+
+    #[must_use]
+    pub fn kem(&self) -> Kem {
+        self.kem
+    }
+}
+
+/// A private key for use with HPKE.
+#[derive(Clone)]
+pub struct PrivateKey {
+    kem: Kem,
+    pub(crate) key: imp::PrivateKey,
+}
+
+impl PrivateKey {
+    /// Serialize this private key, as `SerializePrivateKey` from RFC 9180 does.
+    /// This fails for keys that the backend will not export,
+    /// such as keys that were generated with the `nss` backend.
+    pub fn encode(&self) -> Res<Vec<u8>> {
+        self.key.key_data()
+    }
+
+    #[must_use]
+    pub fn kem(&self) -> Kem {
+        self.kem
+    }
+}
+
+impl std::fmt::Debug for PrivateKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "PrivateKey {:?}", self.kem)
+    }
+}
+
+/// A pre-shared key and its identifier, for the PSK modes of HPKE.
+#[derive(Clone)]
+pub struct Psk {
+    psk: Vec<u8>,
+    id: Vec<u8>,
+}
+
+impl Psk {
+    /// RFC 9180 requires that the key has at least 32 bytes of entropy,
+    /// so this rejects anything shorter.  The identifier can't be empty.
+    pub fn new(psk: &[u8], id: &[u8]) -> Res<Self> {
+        if psk.len() < 32 || id.is_empty() {
+            return Err(Error::InvalidPsk);
+        }
+        Ok(Self {
+            psk: psk.to_vec(),
+            id: id.to_vec(),
+        })
+    }
+
+    pub(crate) fn psk(&self) -> &[u8] {
+        &self.psk
+    }
+
+    pub(crate) fn id(&self) -> &[u8] {
+        &self.id
+    }
+}
+
+impl std::fmt::Debug for Psk {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Psk {}", hex::encode(&self.id))
+    }
+}
+
+/// The modes of HPKE, with the identifiers used in the key schedule.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Base = 0,
+    Psk = 1,
+    Auth = 2,
+    AuthPsk = 3,
+}
+
+impl Mode {
+    /// A short name for the mode.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Base => "base",
+            Self::Psk => "psk",
+            Self::Auth => "auth",
+            Self::AuthPsk => "auth-psk",
+        }
+    }
+}
+
+/// The mode that a sender uses, with the keys that authenticate it.
+/// The authenticated modes take the private and public key of the sender.
+#[derive(Clone, Debug)]
+pub enum SenderMode {
+    Base,
+    Psk(Psk),
+    Auth(PrivateKey, PublicKey),
+    AuthPsk(PrivateKey, PublicKey, Psk),
+}
+
+impl SenderMode {
+    #[must_use]
+    pub fn mode(&self) -> Mode {
+        match self {
+            Self::Base => Mode::Base,
+            Self::Psk(_) => Mode::Psk,
+            Self::Auth(..) => Mode::Auth,
+            Self::AuthPsk(..) => Mode::AuthPsk,
+        }
+    }
+}
+
+/// The mode that a receiver expects, with the keys it needs to authenticate
+/// the sender.  The authenticated modes take the public key of the sender.
+#[derive(Clone, Debug)]
+pub enum ReceiverMode {
+    Base,
+    Psk(Psk),
+    Auth(PublicKey),
+    AuthPsk(PublicKey, Psk),
+}
+
+impl ReceiverMode {
+    #[must_use]
+    pub fn mode(&self) -> Mode {
+        match self {
+            Self::Base => Mode::Base,
+            Self::Psk(_) => Mode::Psk,
+            Self::Auth(_) => Mode::Auth,
+            Self::AuthPsk(..) => Mode::AuthPsk,
+        }
+    }
+}
+
+/// Generate a key pair for the identified KEM.
+pub fn generate_key_pair(kem: Kem) -> Res<(PrivateKey, PublicKey)> {
+    let (sk, pk) = imp::generate_key_pair(kem)?;
+    Ok((PrivateKey { kem, key: sk }, PublicKey { kem, key: pk }))
+}
+
+/// Import a key pair for the identified KEM from a serialized private key
+/// and the matching serialized public key.
+pub fn import_key_pair(kem: Kem, sk: &[u8], pk: &[u8]) -> Res<(PrivateKey, PublicKey)> {
+    if pk.len() != kem.n_pk() {
+        return Err(Error::InvalidKeyType);
+    }
+    let (sk, pk) = imp::import_key_pair(kem, sk, pk)?;
+    Ok((PrivateKey { kem, key: sk }, PublicKey { kem, key: pk }))
+}
+
+/// Derive a key pair for the identified KEM from input keying material,
+/// using the `DeriveKeyPair` function of the KEM.
+/// This is not available with the `nss` backend.
+#[allow(unused)]
+pub fn derive_key_pair(kem: Kem, ikm: &[u8]) -> Res<(PrivateKey, PublicKey)> {
+    #[cfg(any(feature = "rust-hpke", feature = "openssl"))]
+    {
+        let (sk, pk) = imp::derive_key_pair(kem, ikm)?;
+        Ok((PrivateKey { kem, key: sk }, PublicKey { kem, key: pk }))
+    }
+    #[cfg(feature = "nss")]
+    {
+        Err(Error::Unsupported)
+    }
+}
+
+/// Encrypt one message to the holder of the private key for `pk`, using
+/// the base mode of HPKE.  This returns the encapsulated key and the ciphertext.
+pub fn seal(
+    config: Config,
+    pk: &PublicKey,
+    info: &[u8],
+    aad: &[u8],
+    pt: &[u8],
+) -> Res<(Vec<u8>, Vec<u8>)> {
+    let mut context = SenderContext::new(config, pk, info)?;
+    let ct = context.seal(aad, pt)?;
+    Ok((context.enc()?, ct))
+}
+
+/// Decrypt one message that was produced by `seal`.
+pub fn open(
+    config: Config,
+    pk: &PublicKey,
+    sk: &PrivateKey,
+    enc: &[u8],
+    info: &[u8],
+    aad: &[u8],
+    ct: &[u8],
+) -> Res<Vec<u8>> {
+    ReceiverContext::new(config, pk, sk, enc, info)?.open(aad, ct)
+}
+
+/// The sending side of an HPKE context, in base mode.
+/// Each call to `seal` uses the next nonce, so the receiver needs to
+/// open messages in the order that they were sealed.
+pub struct SenderContext {
+    hpke: imp::HpkeS,
+}
+
+impl SenderContext {
+    pub fn new(config: Config, pk: &PublicKey, info: &[u8]) -> Res<Self> {
+        Self::with_mode(config, pk, info, &SenderMode::Base)
+    }
+
+    /// Create a context that uses one of the other modes of HPKE.
+    /// The `nss` backend only supports the base and PSK modes.
+    pub fn with_mode(config: Config, pk: &PublicKey, info: &[u8], mode: &SenderMode) -> Res<Self> {
+        let mut pk = pk.key.clone();
+        let hpke = imp::HpkeS::with_mode(config.backend(), &mut pk, info, mode)?;
+        Ok(Self { hpke })
+    }
+
+    #[must_use]
+    pub fn config(&self) -> Config {
+        let c = self.hpke.config();
+        Config::new(c.kem(), c.kdf(), c.aead())
+    }
+
+    /// The encapsulated key, which the receiver needs to set up its context.
+    pub fn enc(&self) -> Res<Vec<u8>> {
+        self.hpke.enc()
+    }
+
+    pub fn seal(&mut self, aad: &[u8], pt: &[u8]) -> Res<Vec<u8>> {
+        self.hpke.seal(aad, pt)
+    }
+
+    /// Export a secret of `len` bytes that is bound to `context`.
+    pub fn export(&self, context: &[u8], len: usize) -> Res<Vec<u8>> {
+        Ok(self.hpke.export(context, len)?.key_data()?.to_vec())
+    }
+}
+
+/// The receiving side of an HPKE context, in base mode.
+pub struct ReceiverContext {
+    hpke: imp::HpkeR,
+}
+
+impl ReceiverContext {
+    pub fn new(
+        config: Config,
+        pk: &PublicKey,
+        sk: &PrivateKey,
+        enc: &[u8],
+        info: &[u8],
+    ) -> Res<Self> {
+        Self::with_mode(config, pk, sk, enc, info, &ReceiverMode::Base)
+    }
+
+    /// Create a context that uses one of the other modes of HPKE.
+    /// The `nss` backend only supports the base and PSK modes.
+    pub fn with_mode(
+        config: Config,
+        pk: &PublicKey,
+        sk: &PrivateKey,
+        enc: &[u8],
+        info: &[u8],
+        mode: &ReceiverMode,
+    ) -> Res<Self> {
+        let hpke = imp::HpkeR::with_mode(config.backend(), &pk.key, &sk.key, enc, info, mode)?;
+        Ok(Self { hpke })
+    }
+
+    #[must_use]
+    pub fn config(&self) -> Config {
+        let c = self.hpke.config();
+        Config::new(c.kem(), c.kdf(), c.aead())
+    }
+
+    pub fn open(&mut self, aad: &[u8], ct: &[u8]) -> Res<Vec<u8>> {
+        self.hpke.open(aad, ct)
+    }
+
+    /// Export a secret of `len` bytes that is bound to `context`.
+    pub fn export(&self, context: &[u8], len: usize) -> Res<Vec<u8>> {
+        Ok(self.hpke.export(context, len)?.key_data()?.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        generate_key_pair, import_key_pair, open, seal, Aead, Config, Kdf, Kem, Mode, Psk,
+        PublicKey, ReceiverContext, ReceiverMode, SenderContext, SenderMode,
+    };
+    use crate::{init, Error};
+
+    const CONFIG: Config = Config::new(Kem::X25519Sha256, Kdf::HkdfSha256, Aead::Aes128Gcm);
+    const INFO: &[u8] = b"info";
+    const AAD: &[u8] = b"aad";
+    const PT: &[u8] = b"plaintext";
+
+    #[test]
+    fn single_shot() {
+        init();
+        let (sk, pk) = generate_key_pair(CONFIG.kem()).unwrap();
+        let (enc, ct) = seal(CONFIG, &pk, INFO, AAD, PT).unwrap();
+        assert_eq!(enc.len(), CONFIG.kem().n_enc());
+        assert_eq!(ct.len(), PT.len() + CONFIG.aead().n_t());
+
+        let pt = open(CONFIG, &pk, &sk, &enc, INFO, AAD, &ct).unwrap();
+        assert_eq!(pt, PT);
+        assert!(open(CONFIG, &pk, &sk, &enc, INFO, b"other", &ct).is_err());
+    }
+
+    #[test]
+    fn context() {
+        init();
+        let (sk, pk) = generate_key_pair(CONFIG.kem()).unwrap();
+        let pk = PublicKey::decode(CONFIG.kem(), &pk.encode().unwrap()).unwrap();
+
+        let mut sender = SenderContext::new(CONFIG, &pk, INFO).unwrap();
+        let mut receiver =
+            ReceiverContext::new(CONFIG, &pk, &sk, &sender.enc().unwrap(), INFO).unwrap();
+        assert_eq!(receiver.config(), sender.config());
+
+        for i in 0..3_u8 {
+            let ct = sender.seal(AAD, &[i]).unwrap();
+            assert_eq!(receiver.open(AAD, &ct).unwrap(), [i]);
+        }
+
+        let secret = sender.export(b"context", 32).unwrap();
+        assert_eq!(secret.len(), 32);
+        assert_eq!(receiver.export(b"context", 32).unwrap(), secret);
+        assert_ne!(receiver.export(b"other", 32).unwrap(), secret);
+    }
+
+    #[test]
+    fn bad_public_key() {
+        init();
+        assert!(PublicKey::decode(Kem::X25519Sha256, &[0; 3]).is_err());
+    }
+
+    fn psk() -> Psk {
+        Psk::new(&[7; 32], b"psk id").unwrap()
+    }
+
+    #[test]
+    fn modes() {
+        init();
+        let (sk_r, pk_r) = generate_key_pair(CONFIG.kem()).unwrap();
+
+        #[cfg_attr(feature = "nss", allow(unused_mut))]
+        let mut modes = vec![
+            (SenderMode::Base, ReceiverMode::Base),
+            (SenderMode::Psk(psk()), ReceiverMode::Psk(psk())),
+        ];
+        // NSS does not implement the authenticated modes.
+        #[cfg(not(feature = "nss"))]
+        {
+            let (sk_s, pk_s) = generate_key_pair(CONFIG.kem()).unwrap();
+            modes.extend([
+                (
+                    SenderMode::Auth(sk_s.clone(), pk_s.clone()),
+                    ReceiverMode::Auth(pk_s.clone()),
+                ),
+                (
+                    SenderMode::AuthPsk(sk_s, pk_s.clone(), psk()),
+                    ReceiverMode::AuthPsk(pk_s, psk()),
+                ),
+            ]);
+        }
+        for (sender_mode, receiver_mode) in &modes {
+            assert_eq!(sender_mode.mode(), receiver_mode.mode());
+            let mut sender = SenderContext::with_mode(CONFIG, &pk_r, INFO, sender_mode).unwrap();
+            let ct = sender.seal(AAD, PT).unwrap();
+            let enc = sender.enc().unwrap();
+
+            let mut receiver =
+                ReceiverContext::with_mode(CONFIG, &pk_r, &sk_r, &enc, INFO, receiver_mode)
+                    .unwrap();
+            assert_eq!(receiver.open(AAD, &ct).unwrap(), PT);
+
+            // A receiver that expects a different mode can't open the message.
+            for (_, other) in modes
+                .iter()
+                .filter(|(_, m)| m.mode() != receiver_mode.mode())
+            {
+                let mut receiver =
+                    ReceiverContext::with_mode(CONFIG, &pk_r, &sk_r, &enc, INFO, other).unwrap();
+                assert!(receiver.open(AAD, &ct).is_err());
+            }
+        }
+    }
+
+    #[cfg(feature = "nss")]
+    #[test]
+    fn auth_unsupported() {
+        init();
+        let (sk_r, pk_r) = generate_key_pair(CONFIG.kem()).unwrap();
+        let (sk_s, pk_s) = generate_key_pair(CONFIG.kem()).unwrap();
+        let mode = SenderMode::Auth(sk_s, pk_s.clone());
+        assert!(matches!(
+            SenderContext::with_mode(CONFIG, &pk_r, INFO, &mode),
+            Err(Error::Unsupported)
+        ));
+        let mode = ReceiverMode::Auth(pk_s);
+        let enc = SenderContext::new(CONFIG, &pk_r, INFO)
+            .unwrap()
+            .enc()
+            .unwrap();
+        assert!(matches!(
+            ReceiverContext::with_mode(CONFIG, &pk_r, &sk_r, &enc, INFO, &mode),
+            Err(Error::Unsupported)
+        ));
+    }
+
+    #[test]
+    fn import() {
+        // skRm and pkRm from RFC 9180, Appendix A.1.1.
+        const SK: &str = "4612c550263fc8ad58375df3f557aac531d26850903e55a9f23f21d8534e8ac8";
+        const PK: &str = "3948cfe0ad1ddb695d780e59077195da6c56506b027329794ab02bca80815c4d";
+        init();
+        let (sk, pk) = import_key_pair(
+            CONFIG.kem(),
+            &hex::decode(SK).unwrap(),
+            &hex::decode(PK).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(hex::encode(sk.encode().unwrap()), SK);
+        assert_eq!(hex::encode(pk.encode().unwrap()), PK);
+
+        let (enc, ct) = seal(CONFIG, &pk, INFO, AAD, PT).unwrap();
+        assert_eq!(open(CONFIG, &pk, &sk, &enc, INFO, AAD, &ct).unwrap(), PT);
+
+        assert!(matches!(
+            import_key_pair(CONFIG.kem(), &hex::decode(SK).unwrap(), &[0; 3]),
+            Err(Error::InvalidKeyType)
+        ));
+    }
+
+    #[test]
+    fn wrong_psk() {
+        init();
+        let (sk, pk) = generate_key_pair(CONFIG.kem()).unwrap();
+        let mut sender =
+            SenderContext::with_mode(CONFIG, &pk, INFO, &SenderMode::Psk(psk())).unwrap();
+        let ct = sender.seal(AAD, PT).unwrap();
+
+        let other = ReceiverMode::Psk(Psk::new(&[8; 32], b"psk id").unwrap());
+        let mut receiver =
+            ReceiverContext::with_mode(CONFIG, &pk, &sk, &sender.enc().unwrap(), INFO, &other)
+                .unwrap();
+        assert!(receiver.open(AAD, &ct).is_err());
+    }
+
+    #[test]
+    fn bad_psk() {
+        assert!(matches!(Psk::new(&[0; 31], b"id"), Err(Error::InvalidPsk)));
+        assert!(matches!(Psk::new(&[0; 32], b""), Err(Error::InvalidPsk)));
+    }
+
+    /// A vector for one mode from Appendix A.1 of RFC 9180, which uses
+    /// DHKEM(X25519, HKDF-SHA256), HKDF-SHA256, and AES-128-GCM.
+    /// Only the first two encryptions and the export with the context
+    /// "TestContext" are included.
+    struct ModeVector {
+        mode: Mode,
+        ikm_r: &'static str,
+        sk_r: &'static str,
+        pk_r: &'static str,
+        /// The IKM, private key, and public key of the sender, in the
+        /// authenticated modes.
+        sender: Option<[&'static str; 3]>,
+        enc: &'static str,
+        ct: [&'static str; 2],
+        exported: &'static str,
+    }
+
+    const VECTOR_INFO: &str = "4f6465206f6e2061204772656369616e2055726e";
+    const VECTOR_PSK: &str = "0247fd33b913760fa1fa51e1892d9f307fbe65eb171e8132c2af18555a738b82";
+    const VECTOR_PSK_ID: &str = "456e6e796e20447572696e206172616e204d6f726961";
+    const VECTOR_PT: &str = "4265617574792069732074727574682c20747275746820626561757479";
+    const VECTORS: &[ModeVector] = &[
+        // A.1.2, PSK mode.
+        ModeVector {
+            mode: Mode::Psk,
+            ikm_r: "d4a09d09f575fef425905d2ab396c1449141463f698f8efdb7accfaff8995098",
+            sk_r: "c5eb01eb457fe6c6f57577c5413b931550a162c71a03ac8d196babbd4e5ce0fd",
+            pk_r: "9fed7e8c17387560e92cc6462a68049657246a09bfa8ade7aefe589672016366",
+            sender: None,
+            enc: "0ad0950d9fb9588e59690b74f1237ecdf1d775cd60be2eca57af5a4b0471c91b",
+            ct: [
+                "e52c6fed7f758d0cf7145689f21bc1be6ec9ea097fef4e959440012f4feb73fb\
+                 611b946199e681f4cfc34db8ea",
+                "49f3b19b28a9ea9f43e8c71204c00d4a490ee7f61387b6719db765e948123b45\
+                 b61633ef059ba22cd62437c8ba",
+            ],
+            exported: "8aff52b45a1be3a734bc7a41e20b4e055ad4c4d22104b0c20285a7c4302401cd",
+        },
+        // A.1.3, Auth mode.
+        ModeVector {
+            mode: Mode::Auth,
+            ikm_r: "f1d4a30a4cef8d6d4e3b016e6fd3799ea057db4f345472ed302a67ce1c20cdec",
+            sk_r: "fdea67cf831f1ca98d8e27b1f6abeb5b7745e9d35348b80fa407ff6958f9137e",
+            pk_r: "1632d5c2f71c2b38d0a8fcc359355200caa8b1ffdf28618080466c909cb69b2e",
+            sender: Some([
+                "94b020ce91d73fca4649006c7e7329a67b40c55e9e93cc907d282bbbff386f58",
+                "dc4a146313cce60a278a5323d321f051c5707e9c45ba21a3479fecdf76fc69dd",
+                "8b0c70873dc5aecb7f9ee4e62406a397b350e57012be45cf53b7105ae731790b",
+            ]),
+            enc: "23fb952571a14a25e3d678140cd0e5eb47a0961bb18afcf85896e5453c312e76",
+            ct: [
+                "5fd92cc9d46dbf8943e72a07e42f363ed5f721212cd90bcfd072bfd9f44e06b8\
+                 0fd17824947496e21b680c141b",
+                "d3736bb256c19bfa93d79e8f80b7971262cb7c887e35c26370cfed62254369a1\
+                 b52e3d505b79dd699f002bc8ed",
+            ],
+            exported: "5a0131813abc9a522cad678eb6bafaabc43389934adb8097d23c5ff68059eb64",
+        },
+        // A.1.4, AuthPSK mode.
+        ModeVector {
+            mode: Mode::AuthPsk,
+            ikm_r: "4b16221f3b269a88e207270b5e1de28cb01f847841b344b8314d6a622fe5ee90",
+            sk_r: "cb29a95649dc5656c2d054c1aa0d3df0493155e9d5da6d7e344ed8b6a64a9423",
+            pk_r: "1d11a3cd247ae48e901939659bd4d79b6b959e1f3e7d66663fbc9412dd4e0976",
+            sender: Some([
+                "62f77dcf5df0dd7eac54eac9f654f426d4161ec850cc65c54f8b65d2e0b4e345",
+                "fc1c87d2f3832adb178b431fce2ac77c7ca2fd680f3406c77b5ecdf818b119f4",
+                "2bfb2eb18fcad1af0e4f99142a1c474ae74e21b9425fc5c589382c69b50cc57e",
+            ]),
+            enc: "820818d3c23993492cc5623ab437a48a0a7ca3e9639c140fe1e33811eb844b7c",
+            ct: [
+                "a84c64df1e11d8fd11450039d4fe64ff0c8a99fca0bd72c2d4c3e0400bc14a40\
+                 f27e45e141a24001697737533e",
+                "4d19303b848f424fc3c3beca249b2c6de0a34083b8e909b6aa4c3688505c05ff\
+                 e0c8f57a0a4c5ab9da127435d9",
+            ],
+            exported: "a30c20370c026bbea4dca51cb63761695132d342bae33a6a11527d3e7679436d",
+        },
+    ];
+
+    #[cfg(any(feature = "rust-hpke", feature = "openssl"))]
+    #[test]
+    fn derive_vector_keys() {
+        use super::derive_key_pair;
+
+        init();
+        for v in VECTORS {
+            let sender = v.sender.map(|[ikm, sk, pk]| (ikm, sk, pk));
+            for (ikm, sk, pk) in std::iter::once((v.ikm_r, v.sk_r, v.pk_r)).chain(sender) {
+                let (sk_d, pk_d) =
+                    derive_key_pair(CONFIG.kem(), &hex::decode(ikm).unwrap()).unwrap();
+                assert_eq!(hex::encode(sk_d.encode().unwrap()), sk);
+                assert_eq!(hex::encode(pk_d.encode().unwrap()), pk);
+            }
+        }
+    }
+
+    #[test]
+    fn mode_vectors() {
+        init();
+        let info = hex::decode(VECTOR_INFO).unwrap();
+        let psk = Psk::new(
+            &hex::decode(VECTOR_PSK).unwrap(),
+            &hex::decode(VECTOR_PSK_ID).unwrap(),
+        )
+        .unwrap();
+
+        for v in VECTORS {
+            // NSS does not implement the authenticated modes.
+            if cfg!(feature = "nss") && v.mode != Mode::Psk {
+                continue;
+            }
+            let (sk_r, pk_r) = import_key_pair(
+                CONFIG.kem(),
+                &hex::decode(v.sk_r).unwrap(),
+                &hex::decode(v.pk_r).unwrap(),
+            )
+            .unwrap();
+            let pk_s = v.sender.map(|[_, _, pk]| {
+                PublicKey::decode(CONFIG.kem(), &hex::decode(pk).unwrap()).unwrap()
+            });
+            let mode = match (v.mode, pk_s) {
+                (Mode::Psk, None) => ReceiverMode::Psk(psk.clone()),
+                (Mode::Auth, Some(pk_s)) => ReceiverMode::Auth(pk_s),
+                (Mode::AuthPsk, Some(pk_s)) => ReceiverMode::AuthPsk(pk_s, psk.clone()),
+                _ => unreachable!(),
+            };
+            let enc = hex::decode(v.enc).unwrap();
+            let mut receiver =
+                ReceiverContext::with_mode(CONFIG, &pk_r, &sk_r, &enc, &info, &mode).unwrap();
+            for (i, ct) in v.ct.iter().enumerate() {
+                let aad = format!("Count-{i}");
+                let pt = receiver
+                    .open(aad.as_bytes(), &hex::decode(ct).unwrap())
+                    .unwrap();
+                assert_eq!(hex::encode(pt), VECTOR_PT);
+            }
+            let exported = receiver.export(b"TestContext", 32).unwrap();
+            assert_eq!(hex::encode(exported), v.exported);
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! The labeled HKDF and the key schedule from RFC 9180, for the parts of the
+//! backends that implement HPKE themselves: the OpenSSL backend and the
+//! post-quantum KEMs in the rust-hpke backend.
+
+use crate::{
+    err::{Error, Res},
+    hpke::{Aead as AeadId, Kdf, Kem, Mode as HpkeMode, Psk},
+};
+use std::convert::TryFrom;
+
+#[cfg(feature = "openssl")]
+use crate::ossl::{
+    aead::{Aead, Mode, NONCE_LEN},
+    hkdf::Hkdf,
+    SymKey,
+};
+#[cfg(feature = "rust-hpke")]
+use crate::rh::{
+    aead::{Aead, Mode, NONCE_LEN},
+    hkdf::Hkdf,
+    SymKey,
+};
+
+/// `LabeledExtract` and `LabeledExpand` from Section 4 of RFC 9180.
+pub struct LabeledHkdf {
+    kdf: Kdf,
+    hkdf: Hkdf,
+    suite_id: Vec<u8>,
+}
+
+impl LabeledHkdf {
+    const VERSION: &'static [u8] = b"HPKE-v1";
+
+    /// For use inside a KEM, which uses `kdf` internally.
+    pub fn kem(kem: Kem, kdf: Kdf) -> Self {
+        Self {
+            kdf,
+            hkdf: Hkdf::new(kdf),
+            suite_id: [&b"KEM"[..], &u16::from(kem).to_be_bytes()].concat(),
+        }
+    }
+
+    /// For use in the key schedule.
+    pub fn hpke(kem: Kem, kdf: Kdf, aead: AeadId) -> Self {
+        let mut suite_id = b"HPKE".to_vec();
+        for id in [u16::from(kem), u16::from(kdf), u16::from(aead)] {
+            suite_id.extend_from_slice(&id.to_be_bytes());
+        }
+        Self {
+            kdf,
+            hkdf: Hkdf::new(kdf),
+            suite_id,
+        }
+    }
+
+    pub fn kdf(&self) -> Kdf {
+        self.kdf
+    }
+
+    pub fn extract(&self, salt: &[u8], label: &[u8], ikm: &[u8]) -> Res<SymKey> {
+        let labeled_ikm = [Self::VERSION, &self.suite_id, label, ikm].concat();
+        self.hkdf.extract(salt, &Hkdf::import_ikm(&labeled_ikm)?)
+    }
+
+    pub fn expand(&self, prk: &SymKey, label: &[u8], info: &[u8], len: usize) -> Res<Vec<u8>> {
+        let len_bytes = u16::try_from(len)
+            .map_err(|_| Error::Internal)?
+            .to_be_bytes();
+        let labeled_info = [&len_bytes[..], Self::VERSION, &self.suite_id, label, info].concat();
+        self.hkdf.expand_data(prk, &labeled_info, len)
+    }
+}
+
+/// The key schedule from Section 5.1 of RFC 9180, and what it produces.
+pub struct KeySchedule {
+    kdf: LabeledHkdf,
+    key: SymKey,
+    base_nonce: [u8; NONCE_LEN],
+    exporter_secret: SymKey,
+}
+
+impl KeySchedule {
+    pub fn new(
+        kem: Kem,
+        kdf: Kdf,
+        aead: AeadId,
+        mode: HpkeMode,
+        shared_secret: &[u8],
+        info: &[u8],
+        psk: Option<&Psk>,
+    ) -> Res<Self> {
+        let labeled = LabeledHkdf::hpke(kem, kdf, aead);
+        let (psk, psk_id) = psk.map_or((&[][..], &[][..]), |p| (p.psk(), p.id()));
+        let psk_id_hash = labeled.extract(&[], b"psk_id_hash", psk_id)?;
+        let info_hash = labeled.extract(&[], b"info_hash", info)?;
+        let context = [&[mode as u8][..], psk_id_hash.as_ref(), info_hash.as_ref()].concat();
+
+        let secret = labeled.extract(shared_secret, b"secret", psk)?;
+        let key = SymKey::from(labeled.expand(&secret, b"key", &context, aead.n_k())?);
+        let base_nonce = labeled.expand(&secret, b"base_nonce", &context, aead.n_n())?;
+        let base_nonce =
+            <[u8; NONCE_LEN]>::try_from(&base_nonce[..]).map_err(|_| Error::Internal)?;
+        let exporter_secret = SymKey::from(labeled.expand(&secret, b"exp", &context, kdf.n_h())?);
+        Ok(Self {
+            kdf: labeled,
+            key,
+            base_nonce,
+            exporter_secret,
+        })
+    }
+
+    pub fn aead(&self, mode: Mode, aead: AeadId) -> Res<Aead> {
+        Aead::new(mode, aead, &self.key, self.base_nonce)
+    }
+
+    /// The secret export interface from Section 5.3 of RFC 9180.
+    pub fn export(&self, info: &[u8], len: usize) -> Res<SymKey> {
+        Ok(SymKey::from(self.kdf.expand(
+            &self.exporter_secret,
+            b"sec",
+            info,
+            len,
+        )?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{KeySchedule, Mode};
+    use crate::{
+        hpke::{Aead, Kdf, Kem, Mode as HpkeMode},
+        init,
+    };
+
+    /// The key schedule doesn't depend on the KEM, so it can be checked
+    /// against Appendix A.1.1 of RFC 9180, starting from the shared secret.
+    #[test]
+    fn key_schedule() {
+        const SHARED_SECRET: &str =
+            "fe0e18c9f024ce43799ae393c7e8fe8fce9d218875e8227b0187c04e7d2ea1fc";
+        const INFO: &str = "4f6465206f6e2061204772656369616e2055726e";
+        const CT: &str = "f938558b5d72f1a23810b4be2ab4f84331acc02fc97babc53a52ae8218a355a9\
+                          6d8770ac83d07bea87e13c512a";
+        const EXPORTED: &str = "3853fe2b4035195a573ffc53856e77058e15d9ea064de3e59f4961d0095250ee";
+
+        init();
+        let ks = KeySchedule::new(
+            Kem::X25519Sha256,
+            Kdf::HkdfSha256,
+            Aead::Aes128Gcm,
+            HpkeMode::Base,
+            &hex::decode(SHARED_SECRET).unwrap(),
+            &hex::decode(INFO).unwrap(),
+            None,
+        )
+        .unwrap();
+        let mut aead = ks.aead(Mode::Encrypt, Aead::Aes128Gcm).unwrap();
+        let ct = aead
+            .seal(b"Count-0", b"Beauty is truth, truth beauty")
+            .unwrap();
+        assert_eq!(hex::encode(ct), CT);
+        let exported = ks.export(&[], 32).unwrap();
+        assert_eq!(hex::encode(exported.as_ref()), EXPORTED);
+    }
+}
@@ -7,34 +7,42 @@
 mod config;
 mod err;
 pub mod hpke;
+#[cfg(any(feature = "openssl", all(feature = "rust-hpke", feature = "pq")))]
+mod key_schedule;
 #[cfg(feature = "nss")]
 mod nss;
+#[cfg(feature = "openssl")]
+mod ossl;
 #[cfg(feature = "rust-hpke")]
 mod rand;
 #[cfg(feature = "rust-hpke")]
 mod rh;
 
-use async_stream::stream;
+use async_stream::{stream, try_stream};
 use futures::{stream::Stream, StreamExt};
 use futures_util::stream::once;
 
 pub use crate::{
-    config::{KeyConfig, SymmetricSuite},
+    config::{KeyConfig, KeyConfigEntry, Rejection, SymmetricSuite},
     err::Error,
 };
 
 use crate::{
     err::Res,
-    hpke::{Aead as AeadId, Kdf, Kem},
+    hpke::{Aead as AeadId, Kdf, Kem, Mode as HpkeMode, ReceiverMode, SenderMode},
 };
 use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use std::{
+    borrow::Cow,
     cmp::max,
     convert::TryFrom,
     io::{BufReader, Read},
     mem::size_of,
+    pin::Pin,
+    time::Duration,
 };
-use tracing::{info, trace};
+use tracing::trace;
 
 #[cfg(feature = "nss")]
 use crate::nss::random;
@@ -43,6 +51,7 @@ use crate::nss::{
     aead::{Aead, Mode, NONCE_LEN},
     hkdf::{Hkdf, KeyMechanism},
     hpke::{Config as HpkeConfig, Exporter, HpkeR, HpkeS},
+    SymKey,
 };
 
 #[cfg(feature = "rust-hpke")]
@@ -52,13 +61,20 @@ use crate::rh::{
     aead::{Aead, Mode, NONCE_LEN},
     hkdf::{Hkdf, KeyMechanism},
     hpke::{Config as HpkeConfig, Exporter, HpkeR, HpkeS},
+    SymKey,
+};
+
+#[cfg(feature = "openssl")]
+use crate::ossl::{
+    aead::{Aead, Mode, NONCE_LEN},
+    hkdf::{Hkdf, KeyMechanism},
+    hpke::{Config as HpkeConfig, Exporter, HpkeR, HpkeS},
+    random, SymKey,
 };
 
 /// The request header is a `KeyId` and 2 each for KEM, KDF, and AEAD identifiers
 const REQUEST_HEADER_LEN: usize = size_of::<KeyId>() + 6;
 const INFO_REQUEST: &[u8] = b"message/bhttp request";
-/// The info used for HPKE export is `INFO_REQUEST`, a zero byte, and the header.
-const INFO_LEN: usize = INFO_REQUEST.len() + 1 + REQUEST_HEADER_LEN;
 const LABEL_RESPONSE: &[u8] = b"message/bhttp response";
 const INFO_KEY: &[u8] = b"key";
 const INFO_NONCE: &[u8] = b"nonce";
@@ -66,15 +82,96 @@ const INFO_NONCE: &[u8] = b"nonce";
 /// The type of a key identifier.
 pub type KeyId = u8;
 
+/// The media type of encapsulated messages, as the pair of labels that bind
+/// requests and responses to it (RFC 9458, Section 4.6).  The request label
+/// is part of the HPKE info and the response label is used to export the
+/// secret for the response, so both sides need to agree on them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaType {
+    request: Cow<'static, [u8]>,
+    response: Cow<'static, [u8]>,
+}
+
+impl MediaType {
+    /// Binary HTTP messages, `message/bhttp`, which is the default.
+    pub const BHTTP: Self = Self {
+        request: Cow::Borrowed(INFO_REQUEST),
+        response: Cow::Borrowed(LABEL_RESPONSE),
+    };
+
+    /// Use the labels that RFC 9458 uses for other media types:
+    /// the media type followed by " request" or " response".
+    #[must_use]
+    pub fn new(media_type: &str) -> Self {
+        Self::with_labels(
+            format!("{media_type} request").into_bytes(),
+            format!("{media_type} response").into_bytes(),
+        )
+    }
+
+    /// Use the given request and response labels.
+    #[must_use]
+    pub fn with_labels(request: Vec<u8>, response: Vec<u8>) -> Self {
+        Self {
+            request: Cow::Owned(request),
+            response: Cow::Owned(response),
+        }
+    }
+
+    /// The label that is included in the HPKE info for requests.
+    #[must_use]
+    pub fn request_label(&self) -> &[u8] {
+        &self.request
+    }
+
+    /// The label that is used to export the secret for responses.
+    #[must_use]
+    pub fn response_label(&self) -> &[u8] {
+        &self.response
+    }
+
+    /// The labels for the non-standard variant that uses one of the other
+    /// HPKE modes.  The name of the mode is added to both labels, so that a
+    /// message can't be mistaken for one that uses a different mode.
+    fn with_mode(self, mode: HpkeMode) -> Self {
+        if mode == HpkeMode::Base {
+            return self;
+        }
+        let label = |l: &[u8]| [l, b" ", mode.name().as_bytes()].concat();
+        Self::with_labels(label(&self.request), label(&self.response))
+    }
+}
+
+impl Default for MediaType {
+    fn default() -> Self {
+        Self::BHTTP
+    }
+}
+
+/// A stream of encapsulated or decapsulated chunks.
+pub type ChunkStream = Pin<Box<dyn Stream<Item = Res<Bytes>> + Send + 'static>>;
+
+/// Options for `ServerResponse::encapsulate_stream_with`.
+#[derive(Debug, Clone, Default)]
+pub struct StreamOptions {
+    /// Only end chunks where a `text/event-stream` event ends, so that each
+    /// chunk holds whole events.
+    pub event_stream: bool,
+    /// Send an empty chunk when no content arrives for this long, so that
+    /// idle connections are not closed.
+    pub keepalive: Option<Duration>,
+}
+
 pub fn init() {
     #[cfg(feature = "nss")]
     nss::init();
 }
 
 /// Construct the info parameter we use to initialize an `HpkeS` instance.
-fn build_info(key_id: KeyId, config: HpkeConfig) -> Res<Vec<u8>> {
-    let mut info = Vec::with_capacity(INFO_LEN);
-    info.extend_from_slice(INFO_REQUEST);
+/// This is the request label, a zero byte, and the header.
+fn build_info(label: &[u8], key_id: KeyId, config: HpkeConfig) -> Res<Vec<u8>> {
+    let mut info = Vec::with_capacity(label.len() + 1 + REQUEST_HEADER_LEN);
+    info.extend_from_slice(label);
     info.push(0);
     info.write_u8(key_id)?;
     info.write_u16::<NetworkEndian>(u16::from(config.kem()))?;
@@ -90,25 +187,50 @@ fn build_info(key_id: KeyId, config: HpkeConfig) -> Res<Vec<u8>> {
 pub struct ClientRequest {
     hpke: HpkeS,
     header: Vec<u8>,
+    media_type: MediaType,
 }
 
 #[cfg(feature = "client")]
 impl ClientRequest {
     /// Construct a `ClientRequest` from a specific `KeyConfig` instance.
     pub fn from_config(config: &mut KeyConfig) -> Res<Self> {
+        Self::from_config_with_media_type(config, MediaType::BHTTP)
+    }
+
+    /// Construct a `ClientRequest` for messages of a media type other than
+    /// binary HTTP.  The server needs to use the same media type.
+    pub fn from_config_with_media_type(config: &mut KeyConfig, media_type: MediaType) -> Res<Self> {
+        Self::from_config_with_mode(config, media_type, &SenderMode::Base)
+    }
+
+    /// Construct a `ClientRequest` that uses one of the other HPKE modes,
+    /// which authenticate the client with a pre-shared key or its own key pair.
+    /// This is not part of RFC 9458; it only works with a server that uses
+    /// `Server::with_mode` and the same media type.
+    pub fn from_config_with_mode(
+        config: &mut KeyConfig,
+        media_type: MediaType,
+        mode: &SenderMode,
+    ) -> Res<Self> {
+        let media_type = media_type.with_mode(mode.mode());
         // TODO(mt) choose the best config, not just the first.
         let selected = config.select(config.symmetric[0])?;
 
         // Build the info, which contains the message header.
-        let info = build_info(config.key_id, selected)?;
-        let hpke = HpkeS::new(selected, &mut config.pk, &info)?;
+        let label = media_type.request_label();
+        let info = build_info(label, config.key_id, selected)?;
+        let hpke = HpkeS::with_mode(selected, &mut config.pk, &info, mode)?;
 
-        let header = Vec::from(&info[INFO_REQUEST.len() + 1..]);
+        let header = Vec::from(&info[label.len() + 1..]);
         let header_len = header.len();
         if header_len != REQUEST_HEADER_LEN {
             return Err(Error::UnequalLength(header_len, REQUEST_HEADER_LEN));
         }
-        Ok(Self { hpke, header })
+        Ok(Self {
+            hpke,
+            header,
+            media_type,
+        })
     }
 
     /// Reads an encoded configuration and constructs a single use client sender.
@@ -150,7 +272,83 @@ impl ClientRequest {
         if expected_len != enc_request_len {
             return Err(Error::UnequalLength(expected_len, enc_request_len));
         }
-        Ok((enc_request, ClientResponse::new(self.hpke, enc)))
+        let client_response = ClientResponse::new(&self.hpke, &self.media_type, enc)?;
+        Ok((enc_request, client_response))
+    }
+
+    /// Encapsulate a request as a stream of chunks, so that sending can start
+    /// before all of the request is available.  This consumes this object.
+    /// The framing is the same as for `ServerResponse::encapsulate_stream`,
+    /// with the header and encapsulated key in place of the response nonce.
+    pub fn encapsulate_stream<S, B, E>(self, input: S) -> Res<(ChunkStream, ClientResponse)>
+    where
+        S: Stream<Item = Result<B, E>> + Send + 'static,
+        B: AsRef<[u8]> + Send + 'static,
+        E: std::fmt::Debug + Send,
+    {
+        let enc = self.hpke.enc()?;
+        let client_response = ClientResponse::new(&self.hpke, &self.media_type, enc.clone())?;
+
+        let mut header = self.header;
+        header.extend_from_slice(&enc);
+        let header_stream = once(async { Ok(Bytes::from(header)) });
+
+        let tag_len = self.hpke.config().aead().n_t();
+        let mut hpke = self.hpke;
+        let mut seal = move |aad: &[u8], buf: &mut BytesMut| hpke_seal(&mut hpke, aad, buf);
+        let mut input = Box::pin(input);
+        let output_stream = try_stream! {
+            let stream_error = |e| Error::Stream(format!("{e:?}"));
+            let first = input.next().await.transpose().map_err(stream_error)?;
+            let mut current = first;
+
+            while let Some(next) = input.next().await.transpose().map_err(stream_error)? {
+                let chunk = current.as_ref().map_or(&[][..], AsRef::as_ref);
+                let enc_request = encapsulate_chunk(&mut seal, tag_len, false, chunk)?;
+                trace!("Encapsulated request chunk ({})", enc_request.len());
+                yield enc_request;
+                current = Some(next);
+            }
+
+            let chunk = current.as_ref().map_or(&[][..], AsRef::as_ref);
+            let enc_request = encapsulate_chunk(&mut seal, tag_len, true, chunk)?;
+            trace!("Encapsulated final request chunk ({})", enc_request.len());
+            yield enc_request;
+        };
+
+        Ok((
+            Box::pin(header_stream.chain(output_stream)),
+            client_response,
+        ))
+    }
+
+    /// Encapsulate a request as a stream of chunks, sending each chunk as soon
+    /// as it arrives, like `ServerResponse::encapsulate_stream_with`.  This
+    /// suits content that is produced over time, such as live audio, and
+    /// allows the response to arrive while the request is still being sent.
+    pub fn encapsulate_stream_with<S, B, E>(
+        self,
+        input: S,
+        options: StreamOptions,
+    ) -> Res<(ChunkStream, ClientResponse)>
+    where
+        S: Stream<Item = Result<B, E>> + Send + 'static,
+        B: AsRef<[u8]> + Send + 'static,
+        E: std::fmt::Debug + Send + 'static,
+    {
+        let enc = self.hpke.enc()?;
+        let client_response = ClientResponse::new(&self.hpke, &self.media_type, enc.clone())?;
+
+        let mut header = self.header;
+        header.extend_from_slice(&enc);
+        let header_stream = once(async { Ok(Bytes::from(header)) });
+
+        let tag_len = self.hpke.config().aead().n_t();
+        let mut hpke = self.hpke;
+        let chunks = encapsulate_chunks(input, options, tag_len, move |aad, buf| {
+            hpke_seal(&mut hpke, aad, buf)
+        });
+        Ok((Box::pin(header_stream.chain(chunks)), client_response))
     }
 }
 
@@ -161,6 +359,8 @@ impl ClientRequest {
 #[derive(Debug, Clone)]
 pub struct Server {
     config: KeyConfig,
+    media_type: MediaType,
+    mode: ReceiverMode,
 }
 
 #[cfg(feature = "server")]
@@ -168,10 +368,25 @@ impl Server {
     /// Create a new server configuration.
     /// If the configuration doesn't include a private key.
     pub fn new(config: KeyConfig) -> Res<Self> {
+        Self::with_media_type(config, MediaType::BHTTP)
+    }
+
+    /// Create a server for messages of a media type other than binary HTTP.
+    pub fn with_media_type(config: KeyConfig, media_type: MediaType) -> Res<Self> {
+        Self::with_mode(config, media_type, ReceiverMode::Base)
+    }
+
+    /// Create a server that only accepts requests that use the given HPKE mode,
+    /// as produced by `ClientRequest::from_config_with_mode`.
+    pub fn with_mode(config: KeyConfig, media_type: MediaType, mode: ReceiverMode) -> Res<Self> {
         if config.sk.is_none() {
             return Err(Error::InvalidPrivateKey);
         }
-        Ok(Self { config })
+        Ok(Self {
+            config,
+            media_type: media_type.with_mode(mode.mode()),
+            mode,
+        })
     }
 
     /// Get the configuration that this server uses.
@@ -180,10 +395,14 @@ impl Server {
         &self.config
     }
 
-    /// Remove encapsulation on a message.
-    /// Not as a consequence of this code, but Rust won't know that for sure.
+    /// The length of the header and encapsulated key at the start of a request.
+    fn request_prefix_len(&self) -> usize {
+        REQUEST_HEADER_LEN + self.config.kem.n_enc()
+    }
+
+    /// Read the header and encapsulated key of a request and set up HPKE.
     #[allow(clippy::similar_names)] // for kem_id and key_id
-    pub fn decapsulate(&self, enc_request: &[u8]) -> Res<(Vec<u8>, ServerResponse)> {
+    fn decapsulate_header(&self, enc_request: &[u8]) -> Res<(HpkeR, Vec<u8>)> {
         if enc_request.len() < REQUEST_HEADER_LEN {
             return Err(Error::Truncated);
         }
@@ -201,6 +420,7 @@ impl Server {
         let sym = SymmetricSuite::new(kdf_id, aead_id);
 
         let info = build_info(
+            self.media_type.request_label(),
             key_id,
             HpkeConfig::new(self.config.kem, sym.kdf(), sym.aead()),
         )?;
@@ -208,39 +428,340 @@ impl Server {
         let cfg = self.config.select(sym)?;
         let mut enc = vec![0; cfg.kem().n_enc()];
         r.read_exact(&mut enc)?;
-        let mut hpke = HpkeR::new(
+        let hpke = HpkeR::with_mode(
             cfg,
             &self.config.pk,
             self.config.sk.as_ref().unwrap(),
             &enc,
             &info,
+            &self.mode,
         )?;
+        Ok((hpke, enc))
+    }
+
+    /// Remove encapsulation on a message.
+    pub fn decapsulate(&self, enc_request: &[u8]) -> Res<(Vec<u8>, ServerResponse)> {
+        let (mut hpke, enc) = self.decapsulate_header(enc_request)?;
+        let ct = &enc_request[self.request_prefix_len()..];
+        let request = hpke.open(&[], ct)?;
+        Ok((
+            request,
+            ServerResponse::new(self.config.key_id, &hpke, &self.media_type, enc)?,
+        ))
+    }
+
+    /// Remove encapsulation on a chunked request, as produced by
+    /// `ClientRequest::encapsulate_stream`.  This waits for the header and
+    /// encapsulated key, then produces decrypted chunks as they arrive.
+    /// No more than `max_chunk_len` bytes are buffered for any one chunk.
+    pub async fn decapsulate_stream<S, B>(
+        &self,
+        mut input: S,
+        max_chunk_len: usize,
+    ) -> Res<(ChunkStream, ServerResponse)>
+    where
+        S: Stream<Item = Res<B>> + Send + Unpin + 'static,
+        B: AsRef<[u8]> + Send + 'static,
+    {
+        let prefix_len = self.request_prefix_len();
+        let mut buffer = BytesMut::new();
+        while buffer.len() < prefix_len {
+            match input.next().await {
+                Some(chunk) => buffer.extend_from_slice(chunk?.as_ref()),
+                None => return Err(Error::Truncated),
+            }
+        }
+        let (mut hpke, enc) = self.decapsulate_header(&buffer)?;
+        let server_response =
+            ServerResponse::new(self.config.key_id, &hpke, &self.media_type, enc)?;
+        buffer.advance(prefix_len);
+
+        let output_stream = try_stream! {
+            let mut done = false;
+            loop {
+                while let Some((is_final, ct)) = next_chunk(&mut buffer, max_chunk_len)? {
+                    if done {
+                        // Nothing can follow the final chunk.
+                        Err(Error::Format)?;
+                    }
+                    trace!("Decapsulating request chunk ({})", ct.len());
+                    done = is_final;
+                    let aad: &[u8] = if is_final { b"final" } else { &[] };
+                    yield Bytes::from(hpke.open(aad, &ct)?);
+                }
+                match input.next().await {
+                    Some(chunk) => buffer.extend_from_slice(chunk?.as_ref()),
+                    None if done && buffer.is_empty() => break,
+                    None => Err(Error::Truncated)?,
+                }
+            }
+        };
+
+        Ok((Box::pin(output_stream), server_response))
+    }
+}
+
+/// The most bytes that the variable length encoding of a `usize` takes.
+const MAX_VARIANT_LEN: usize = 10;
+
+/// The most of a `text/event-stream` event that is held back, waiting for the
+/// event to end.  Longer events are split across chunks.
+const MAX_PENDING_EVENT_LEN: usize = 64 * 1024;
+
+/// The most bytes that `ClientResponse::decapsulate_stream` buffers for any
+/// one chunk of a response, unless `ClientResponse::with_max_chunk_len` says
+/// otherwise.
+pub const DEFAULT_MAX_RESPONSE_CHUNK_LEN: usize = 16 * 1024 * 1024;
+
+// Variable length encoding of an integer
+fn variant_encode(mut val: usize, bytes: &mut impl BufMut) {
+    loop {
+        #[allow(clippy::cast_possible_truncation)]
+        let mut byte = (val & 0x7F) as u8; // Take the last 7 bits
+        val >>= 7; // Shift right by 7 bits
+        if val != 0 {
+            byte |= 0x80; // Set the MSB if there's more to encode
+        }
+        bytes.put_u8(byte);
+        if val == 0 {
+            break;
+        }
+    }
+}
+
+/// Decode a variable length integer from the start of `bytes`,
+/// returning the value and the number of bytes that it used.
+/// This returns `None` if more bytes are needed.
+fn variant_decode_prefix(bytes: &[u8]) -> Res<Option<(u64, usize)>> {
+    let mut value: u64 = 0;
+    for (i, &byte) in bytes.iter().enumerate() {
+        // The tenth byte only has room for the top bit of a `u64`.
+        if i >= MAX_VARIANT_LEN || (i == MAX_VARIANT_LEN - 1 && byte > 1) {
+            return Err(Error::Format);
+        }
+        value |= u64::from(byte & 0x7F) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(Some((value, i + 1)));
+        }
+    }
+    Ok(None)
+}
+
+/// Split and combine the chunks of `input` so that every chunk has `size`
+/// bytes, except for the last, which has whatever is left.  The sizes of
+/// encapsulated chunks then reveal nothing about how the content was
+/// produced, such as the size of each token from a model.
+///
+/// # Panics
+/// If `size` is zero.
+pub fn rechunk<S, B, E>(input: S, size: usize) -> impl Stream<Item = Result<Bytes, E>> + Send
+where
+    S: Stream<Item = Result<B, E>> + Send + 'static,
+    B: AsRef<[u8]> + Send + 'static,
+    E: Send,
+{
+    assert!(size > 0, "chunks need at least one byte");
+    let mut input = Box::pin(input);
+    stream! {
+        let mut pending = BytesMut::new();
+        while let Some(next) = input.next().await {
+            let content = match next {
+                Ok(content) => content,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+            pending.extend_from_slice(content.as_ref());
+            while pending.len() >= size {
+                yield Ok(pending.split_to(size).freeze());
+            }
+        }
+        if !pending.is_empty() {
+            yield Ok(pending.freeze());
+        }
+    }
+}
+
+/// Find the end of the last complete event in `text/event-stream` content,
+/// which is the end of the last blank line.  Lines end with CRLF, LF, or CR.
+/// A CR at the end of `buf` might be the start of a CRLF, so it isn't treated
+/// as the end of a line until more content arrives.
+fn event_stream_boundary(buf: &[u8]) -> Option<usize> {
+    let mut boundary = None;
+    let mut line_start = 0;
+    let mut i = 0;
+    while i < buf.len() {
+        let end = match buf[i] {
+            b'\n' => i + 1,
+            b'\r' if i + 1 == buf.len() => break,
+            b'\r' if buf[i + 1] == b'\n' => i + 2,
+            b'\r' => i + 1,
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        if i == line_start {
+            boundary = Some(end);
+        }
+        line_start = end;
+        i = end;
+    }
+    boundary
+}
+
+/// Encapsulate one chunk: the final chunk indicator, for the final chunk,
+/// then the length and the AEAD-protected chunk.  This uses one buffer,
+/// which `chunk` is copied into and `seal` then protects in place,
+/// adding `tag_len` bytes.
+fn encapsulate_chunk<F>(seal: &mut F, tag_len: usize, is_final: bool, chunk: &[u8]) -> Res<Bytes>
+where
+    F: FnMut(&[u8], &mut BytesMut) -> Res<()>,
+{
+    let ct_len = chunk.len() + tag_len;
+    let mut enc = BytesMut::with_capacity(2 * MAX_VARIANT_LEN + ct_len);
+    let aad: &[u8] = if is_final {
+        // Final Chunk Indicator (i) = 0, AEAD-Protected Final Chunk (..)
+        variant_encode(0, &mut enc);
+        b"final"
+    } else {
+        // Non-Final Chunk: Length (i) = 1.., AEAD-Protected Chunk (..)
+        &[]
+    };
+    variant_encode(ct_len, &mut enc);
+
+    let mut ct = enc.split_off(enc.len());
+    ct.extend_from_slice(chunk);
+    seal(aad, &mut ct)?;
+    if ct.len() != ct_len {
+        return Err(Error::UnequalLength(ct.len(), ct_len));
+    }
+    enc.unsplit(ct);
+    Ok(enc.freeze())
+}
+
+/// Seal with HPKE, which can't work in place, for `encapsulate_chunk`.
+#[cfg(feature = "client")]
+fn hpke_seal(hpke: &mut HpkeS, aad: &[u8], buf: &mut BytesMut) -> Res<()> {
+    let ct = hpke.seal(aad, buf)?;
+    buf.clear();
+    buf.extend_from_slice(&ct);
+    Ok(())
+}
+
+/// Encapsulate each chunk of `input` as soon as it arrives, using `seal` to
+/// protect it, then end with a separate final chunk.  This is the framing
+/// from `ServerResponse::encapsulate_stream`, without holding chunks back.
+/// Keepalive chunks are empty, but they are authenticated like any other.
+fn encapsulate_chunks<S, B, E, F>(
+    input: S,
+    options: StreamOptions,
+    tag_len: usize,
+    mut seal: F,
+) -> impl Stream<Item = Res<Bytes>> + Send
+where
+    S: Stream<Item = Result<B, E>> + Send + 'static,
+    B: AsRef<[u8]> + Send + 'static,
+    E: std::fmt::Debug + Send + 'static,
+    F: FnMut(&[u8], &mut BytesMut) -> Res<()> + Send + 'static,
+{
+    let mut seal_chunk =
+        move |is_final, chunk: &[u8]| encapsulate_chunk(&mut seal, tag_len, is_final, chunk);
+
+    let mut input = Box::pin(input);
+    try_stream! {
+        let mut pending = BytesMut::new();
+        loop {
+            let next = if let Some(idle) = options.keepalive {
+                if let Ok(next) = tokio::time::timeout(idle, input.next()).await {
+                    next
+                } else {
+                    trace!("Encapsulated keepalive chunk");
+                    yield seal_chunk(false, &[])?;
+                    continue;
+                }
+            } else {
+                input.next().await
+            };
+            let Some(next) = next else { break };
+            let content = next.map_err(|e| Error::Stream(format!("{e:?}")))?;
+
+            let event;
+            let chunk = if options.event_stream {
+                pending.extend_from_slice(content.as_ref());
+                let end = match event_stream_boundary(&pending) {
+                    Some(end) => end,
+                    None if pending.len() >= MAX_PENDING_EVENT_LEN => pending.len(),
+                    None => continue,
+                };
+                event = pending.split_to(end);
+                &event[..]
+            } else {
+                content.as_ref()
+            };
+            if !chunk.is_empty() {
+                let enc = seal_chunk(false, chunk)?;
+                trace!("Encapsulated chunk ({})", enc.len());
+                yield enc;
+            }
+        }
 
-        let mut ct = Vec::new();
-        r.read_to_end(&mut ct)?;
+        let enc = seal_chunk(true, &pending)?;
+        trace!("Encapsulated final chunk ({})", enc.len());
+        yield enc;
+    }
+}
 
-        let request = hpke.open(&[], &ct)?;
-        Ok((request, ServerResponse::new(&hpke, enc)?))
+/// Remove the next complete chunk from the start of `buffer`.
+/// This returns whether the chunk is the final chunk and its ciphertext,
+/// or `None` if `buffer` does not yet hold a complete chunk.
+fn next_chunk(buffer: &mut BytesMut, max_chunk_len: usize) -> Res<Option<(bool, BytesMut)>> {
+    let Some((mut len, mut offset)) = variant_decode_prefix(buffer)? else {
+        return Ok(None);
+    };
+    // Final Chunk Indicator (i) = 0, followed by the length of the final chunk.
+    let is_final = len == 0;
+    if is_final {
+        let Some((final_len, final_offset)) = variant_decode_prefix(&buffer[offset..])? else {
+            return Ok(None);
+        };
+        len = final_len;
+        offset += final_offset;
     }
+    let len = usize::try_from(len).map_err(|_| Error::ChunkTooLarge)?;
+    if len > max_chunk_len {
+        return Err(Error::ChunkTooLarge);
+    }
+    let end = offset.checked_add(len).ok_or(Error::ChunkTooLarge)?;
+    if buffer.len() < end {
+        return Ok(None);
+    }
+    buffer.advance(offset);
+    Ok(Some((is_final, buffer.split_to(len))))
 }
 
 fn entropy(config: HpkeConfig) -> usize {
     max(config.aead().n_n(), config.aead().n_k())
 }
 
+/// Export the secret that the response is protected with.
+fn export_secret(cfg: HpkeConfig, exp: &impl Exporter, media_type: &MediaType) -> Res<SymKey> {
+    exp.export(media_type.response_label(), entropy(cfg))
+}
+
 fn make_aead(
     mode: Mode,
     cfg: HpkeConfig,
-    exp: &impl Exporter,
+    secret: &SymKey,
     enc: Vec<u8>,
     response_nonce: &[u8],
 ) -> Res<Aead> {
-    let secret = exp.export(LABEL_RESPONSE, entropy(cfg))?;
     let mut salt = enc;
     salt.extend_from_slice(response_nonce);
 
     let hkdf = Hkdf::new(cfg.kdf());
-    let prk = hkdf.extract(&salt, &secret)?;
+    let prk = hkdf.extract(&salt, secret)?;
 
     let key = hkdf.expand_key(&prk, INFO_KEY, KeyMechanism::Aead(cfg.aead()))?;
     let iv = hkdf.expand_data(&prk, INFO_NONCE, cfg.aead().n_n())?;
@@ -253,37 +774,38 @@ fn make_aead(
 /// The only way to obtain one of these is through `Server::decapsulate()`.
 #[cfg(feature = "server")]
 pub struct ServerResponse {
+    key_id: KeyId,
+    suite: SymmetricSuite,
     response_nonce: Vec<u8>,
     aead: Aead,
 }
 
 #[cfg(feature = "server")]
 impl ServerResponse {
-    fn new(hpke: &HpkeR, enc: Vec<u8>) -> Res<Self> {
-        let response_nonce = random(entropy(hpke.config()));
-        let aead = make_aead(Mode::Encrypt, hpke.config(), hpke, enc, &response_nonce)?;
+    fn new(key_id: KeyId, hpke: &HpkeR, media_type: &MediaType, enc: Vec<u8>) -> Res<Self> {
+        let cfg = hpke.config();
+        let response_nonce = random(entropy(cfg));
+        let secret = export_secret(cfg, hpke, media_type)?;
+        let aead = make_aead(Mode::Encrypt, cfg, &secret, enc, &response_nonce)?;
         Ok(Self {
+            key_id,
+            suite: SymmetricSuite::new(cfg.kdf(), cfg.aead()),
             response_nonce,
             aead,
         })
     }
 
-    // Variable length encoding of an integer
-    fn variant_encode(&mut self, mut val: usize) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        loop {
-            #[allow(clippy::cast_possible_truncation)]
-            let mut byte = (val & 0x7F) as u8; // Take the last 7 bits
-            val >>= 7; // Shift right by 7 bits
-            if val != 0 {
-                byte |= 0x80; // Set the MSB if there's more to encode
-            }
-            bytes.push(byte);
-            if val == 0 {
-                break;
-            }
-        }
-        bytes
+    /// The identifier of the key that the request was encapsulated with.
+    #[must_use]
+    pub fn key_id(&self) -> KeyId {
+        self.key_id
+    }
+
+    /// The symmetric algorithms that the client chose for the request.
+    /// The response is protected with the same algorithms.
+    #[must_use]
+    pub fn suite(&self) -> SymmetricSuite {
+        self.suite
     }
 
     /// Consume this object by encapsulating a response.
@@ -311,73 +833,90 @@ impl ServerResponse {
     //   Length (i) = 1..,
     //   AEAD-Protected Chunk (..),
     // }
-    pub fn encapsulate_stream<S, E>(
-        mut self,
-        input: S,
-    ) -> std::pin::Pin<Box<dyn Stream<Item = Res<Vec<u8>>> + Send + 'static>>
+    pub fn encapsulate_stream<S, B, E>(mut self, input: S) -> ChunkStream
     where
-        S: Stream<Item = Result<Vec<u8>, E>> + Send + 'static,
+        S: Stream<Item = Result<B, E>> + Send + 'static,
+        B: AsRef<[u8]> + Send + 'static,
         E: std::fmt::Debug + Send,
     {
         // Response Nonce (Nk)
-        let response_nonce = Ok(self.response_nonce.clone());
-        info!(
-            "Response nonce {}({})",
-            hex::encode(self.response_nonce.clone()),
-            self.response_nonce.len()
-        );
+        let response_nonce = Ok(Bytes::from(self.response_nonce));
         let nonce_stream = once(async { response_nonce });
 
+        let tag_len = self.suite.aead().n_t();
+        let mut seal = move |aad: &[u8], buf: &mut BytesMut| self.aead.seal_in_place(aad, buf);
         let mut input = Box::pin(input);
         let output_stream = stream! {
-            let current = input.next().await;
-            let Some(current) = current else { return };
-            let Ok(mut current) = current else { return };
+            let mut current = match input.next().await {
+                Some(Ok(current)) => current,
+                Some(Err(e)) => {
+                    yield Err(Error::Stream(format!("{e:?}")));
+                    return;
+                }
+                None => {
+                    // An empty response is still ended by a final chunk.
+                    yield encapsulate_chunk(&mut seal, tag_len, true, &[]);
+                    return;
+                }
+            };
 
             loop {
-                //info!("Processing chunk {}", std::str::from_utf8(&current).unwrap());
-                if let Some(next) = input.next().await {
-                    let mut enc_response = Vec::new();
-
-                    // Non-Final Response Chunk (..),
-                    let aad = "";
-                    let mut ct = self.aead.seal(aad.as_bytes(), &current).unwrap();
-                    let mut enc_length = self.variant_encode(ct.len());
-                    // Length (i) = 1..,
-                    enc_response.append(&mut enc_length);
-
-                    // AEAD-Protected Chunk (..),
-                    enc_response.append(&mut ct);
-
-                    info!("Encapsulated chunk ({},{})", ct.len(), enc_response.len());
-                    trace!("{}", hex::encode(&enc_response));
+                let next = input.next().await;
+                let is_final = next.is_none();
+                match encapsulate_chunk(&mut seal, tag_len, is_final, current.as_ref()) {
+                    Ok(enc_response) => {
+                        trace!("Encapsulated chunk ({}, final: {is_final})", enc_response.len());
+                        yield Ok(enc_response);
+                    }
+                    Err(e) => {
+                        yield Err(e);
+                        return;
+                    }
+                }
 
-                    yield Ok(enc_response);
-                    current = next.unwrap();
-                } else {
-                    let mut enc_response = Vec::new();
-
-                    // Final Response Chunk Indicator (i) = 0,
-                    let mut final_chunk_indicator = self.variant_encode(0);
-                    enc_response.append(&mut final_chunk_indicator);
-
-                    // AEAD-Protected Final Response Chunk (..),
-                    let aad = "final";
-                    let mut ct = self.aead.seal(aad.as_bytes(), &current).unwrap();
-                    let mut enc_length = self.variant_encode(ct.len());
-                    enc_response.append(&mut enc_length);
-                    enc_response.append(&mut ct);
-
-                    info!("Encapsulated final chunk ({},{})", ct.len(), enc_response.len());
-                    trace!("{}", hex::encode(&enc_response));
-                    yield Ok(enc_response);
-                    return;
+                match next {
+                    Some(Ok(next)) => current = next,
+                    // Stop with the error and without a final chunk, so that
+                    // the client sees that the response was truncated.
+                    Some(Err(e)) => {
+                        yield Err(Error::Stream(format!("{e:?}")));
+                        return;
+                    }
+                    None => return,
                 }
             }
         };
 
-        let stream = nonce_stream.chain(output_stream);
-        Box::pin(stream)
+        Box::pin(nonce_stream.chain(output_stream))
+    }
+
+    /// Consume this object by encapsulating a stream, sending each chunk as
+    /// soon as it arrives.  Unlike `encapsulate_stream`, this doesn't hold a
+    /// chunk back to find out whether it is the last one; the end of the
+    /// response is marked by a separate final chunk, which is empty unless
+    /// part of an event is left over.
+    ///
+    /// If `input` fails, the stream ends with that error and without a final
+    /// chunk, so that the client can tell that the response was truncated.
+    pub fn encapsulate_stream_with<S, B, E>(
+        mut self,
+        input: S,
+        options: StreamOptions,
+    ) -> ChunkStream
+    where
+        S: Stream<Item = Result<B, E>> + Send + 'static,
+        B: AsRef<[u8]> + Send + 'static,
+        E: std::fmt::Debug + Send + 'static,
+    {
+        // Response Nonce (Nk)
+        let response_nonce = Ok(Bytes::from(self.response_nonce));
+        let nonce_stream = once(async { response_nonce });
+
+        let tag_len = self.suite.aead().n_t();
+        let chunks = encapsulate_chunks(input, options, tag_len, move |aad, buf| {
+            self.aead.seal_in_place(aad, buf)
+        });
+        Box::pin(nonce_stream.chain(chunks))
     }
 }
 
@@ -392,10 +931,10 @@ impl std::fmt::Debug for ServerResponse {
 /// The only way to obtain one of these is through `ClientRequest::encapsulate()`.
 #[cfg(feature = "client")]
 pub struct ClientResponse {
-    hpke: HpkeS,
+    config: HpkeConfig,
+    secret: SymKey,
     enc: Vec<u8>,
-    seq: u64,
-    aead: Option<Aead>,
+    max_chunk_len: usize,
 }
 
 #[cfg(feature = "client")]
@@ -403,126 +942,93 @@ impl ClientResponse {
     /// Private method for constructing one of these.
     /// Doesn't do anything because we don't have the nonce yet, so
     /// the work that can be done is limited.
-    fn new(hpke: HpkeS, enc: Vec<u8>) -> Self {
-        let seq = 0;
-        let aead = None;
-        Self {
-            hpke,
+    fn new(hpke: &HpkeS, media_type: &MediaType, enc: Vec<u8>) -> Res<Self> {
+        let config = hpke.config();
+        let secret = export_secret(config, hpke, media_type)?;
+        Ok(Self {
+            config,
+            secret,
             enc,
-            seq,
-            aead,
-        }
+            max_chunk_len: DEFAULT_MAX_RESPONSE_CHUNK_LEN,
+        })
+    }
+
+    /// Set the most bytes that `decapsulate_stream` buffers for any one
+    /// chunk.  A longer chunk ends the response with `Error::ChunkTooLarge`.
+    #[must_use]
+    pub fn with_max_chunk_len(mut self, max_chunk_len: usize) -> Self {
+        self.max_chunk_len = max_chunk_len;
+        self
     }
 
     /// Consume this object by decapsulating a response.
     pub fn decapsulate(self, enc_response: &[u8]) -> Res<Vec<u8>> {
-        let mid = entropy(self.hpke.config());
+        let mid = entropy(self.config);
         if mid >= enc_response.len() {
             return Err(Error::Truncated);
         }
         let (response_nonce, ct) = enc_response.split_at(mid);
         let mut aead = make_aead(
             Mode::Decrypt,
-            self.hpke.config(),
-            &self.hpke,
+            self.config,
+            &self.secret,
             self.enc,
             response_nonce,
         )?;
         aead.open(&[], 0, ct) // 0 is the sequence number
     }
 
-    fn set_response_nonce(&mut self, enc_response: &[u8]) -> Res<()> {
-        let mid = entropy(self.hpke.config());
-        if mid != enc_response.len() {
-            return Err(Error::Truncated);
-        }
-        let aead = make_aead(
-            Mode::Decrypt,
-            self.hpke.config(),
-            &self.hpke,
-            self.enc.clone(),
-            enc_response,
-        )?;
-        self.aead = Some(aead);
-        Ok(())
-    }
-
-    fn variant_decode(&mut self, bytes: &[u8]) -> Result<(u64, usize), String> {
-        let mut value: u64 = 0;
-        let mut shift = 0;
-        let mut bytes_read = 0;
-
-        for &byte in bytes {
-            let byte_value = (byte & 0x7F) as u64;
-            value |= byte_value << shift;
-            bytes_read += 1;
-            if byte & 0x80 == 0 {
-                // Continuation bit is not set, end of the VLQ-encoded integer
-                return Ok((value, bytes_read));
-            }
-            shift += 7;
-            if shift >= 64 {
-                return Err("VLQ-encoded integer is too large".to_string());
-            }
-        }
-        Err("Incomplete VLQ-encoded integer".to_string())
-    }
-
-    pub async fn decapsulate_stream<S>(
-        mut self,
-        mut stream: S,
-    ) -> std::pin::Pin<Box<dyn Stream<Item = Res<Vec<u8>>> + Send + 'static>>
+    /// Remove encapsulation on a chunked response, as produced by
+    /// `ServerResponse::encapsulate_stream`.  This waits for the response
+    /// nonce, then produces decrypted chunks as they arrive.  Errors from
+    /// `stream` are passed on, and the output ends with an error if `stream`
+    /// ends before the final chunk or continues after it.
+    pub async fn decapsulate_stream<S, B>(self, mut stream: S) -> ChunkStream
     where
-        S: Stream<Item = Res<Vec<u8>>> + Send + 'static + Unpin,
+        S: Stream<Item = Res<B>> + Send + 'static + Unpin,
+        B: AsRef<[u8]> + Send + 'static,
     {
-        let mut nonce_received = false;
-        let mut aad = "";
-        let nonce_size = entropy(self.hpke.config());
-        let mut buffer: Vec<u8> = Vec::new();
-        let output_stream = stream! {
-            while let Some(next) = stream.next().await {
-                let mut enc_response = next.unwrap();
-                info!("Received chunk: ({})", enc_response.len());
-                trace!("{}", hex::encode(&enc_response));
-                buffer.append(&mut enc_response);
-                info!("Buffer size {}", buffer.len());
-
-                // Response Nonce (Nk)
-                if !nonce_received && buffer.len() >= nonce_size {
-                    nonce_received = true;
-                    let nonce: Vec<_> = buffer.drain(0..nonce_size).collect();
-                    info!("Setting response nonce: {}({})", hex::encode(&nonce), nonce.len());
-                    self.set_response_nonce(&nonce).unwrap();
+        let nonce_len = entropy(self.config);
+        let max_chunk_len = self.max_chunk_len;
+        let output_stream = try_stream! {
+            // Response Nonce (Nk)
+            let mut buffer = BytesMut::new();
+            while buffer.len() < nonce_len {
+                match stream.next().await {
+                    Some(chunk) => buffer.extend_from_slice(chunk?.as_ref()),
+                    None => Err(Error::Truncated)?,
                 }
+            }
+            let nonce = buffer.split_to(nonce_len);
+            let mut aead =
+                make_aead(Mode::Decrypt, self.config, &self.secret, self.enc, &nonce)?;
 
-                while nonce_received && !buffer.is_empty() {
-                    let (mut len, mut bytes_read) = self.variant_decode(&buffer).unwrap();
-                    info!("Buffer state: {}, {}({})", buffer.len(), len, bytes_read);
-
-                    // Final Response Chunk Indicator (i) = 0,
-                    if len == 0 {
-                        buffer.drain(0..bytes_read);
-                        info!("Processing final chunk");
-                        aad = "final";
-                        let (length, bytes) = self.variant_decode(&buffer).unwrap();
-                        info!("Buffer state: {}({})", length, bytes_read);
-                        len = length;
-                        bytes_read = bytes;
+            let mut seq = 0;
+            let mut done = false;
+            loop {
+                while let Some((is_final, mut chunk)) = next_chunk(&mut buffer, max_chunk_len)? {
+                    if done {
+                        // Nothing can follow the final chunk.
+                        Err(Error::Format)?;
                     }
-
-                    // Decapsulate chunk if received
-                    let len = usize::try_from(len).unwrap();
-                    if buffer.len() >= len {
-                        buffer.drain(0..bytes_read);
-                        let ct: Vec<_> = buffer.drain(0..len).collect();
-                        info!("Decapsulating chunk ({})", len);
-                        trace!("{}", hex::encode(&ct));
-                        self.seq += 1;
-                        yield self.aead.as_mut().unwrap().open(aad.as_bytes(), self.seq - 1, &ct);
-                    } else {
-                        break;
+                    trace!("Decapsulating response chunk ({})", chunk.len());
+                    done = is_final;
+                    let aad: &[u8] = if is_final { b"final" } else { &[] };
+                    aead.open_in_place(aad, seq, &mut chunk)?;
+                    seq += 1;
+                    // Keepalive chunks and empty final chunks carry nothing.
+                    if !chunk.is_empty() {
+                        yield chunk.freeze();
                     }
                 }
+                if done && !buffer.is_empty() {
+                    Err(Error::Format)?;
+                }
+                match stream.next().await {
+                    Some(chunk) => buffer.extend_from_slice(chunk?.as_ref()),
+                    None if done => break,
+                    None => Err(Error::Truncated)?,
+                }
             }
         };
 
@@ -535,12 +1041,15 @@ mod test {
     use crate::{
         config::SymmetricSuite,
         err::Res,
-        hpke::{Aead, Kdf, Kem},
-        ClientRequest, Error, KeyConfig, KeyId, Server,
+        event_stream_boundary,
+        hpke::{Aead, Kdf, Kem, Psk, ReceiverMode, SenderMode},
+        rechunk, variant_decode_prefix, ClientRequest, ClientResponse, Error, KeyConfig, KeyId,
+        MediaType, Server, ServerResponse, StreamOptions,
     };
 
-    use futures::StreamExt;
-    use std::{fmt::Debug, io::ErrorKind};
+    use bytes::Bytes;
+    use futures::{channel::mpsc, StreamExt};
+    use std::{fmt::Debug, io::ErrorKind, time::Duration};
     use tracing::trace;
 
     use async_stream::stream;
@@ -577,6 +1086,8 @@ mod test {
 
         let (request, server_response) = server.decapsulate(&enc_request).unwrap();
         assert_eq!(&request[..], REQUEST);
+        assert_eq!(server_response.key_id(), KEY_ID);
+        assert_eq!(server_response.suite(), SYMMETRIC[0]);
 
         let enc_response = server_response.encapsulate(RESPONSE).unwrap();
         trace!("Encapsulated Response: {}", hex::encode(&enc_response));
@@ -615,6 +1126,135 @@ mod test {
         assert_eq!(&response2[..], RESPONSE);
     }
 
+    #[test]
+    fn custom_media_type() {
+        init();
+
+        let media_type = MediaType::new("application/json");
+        assert_eq!(media_type.request_label(), b"application/json request");
+        assert_eq!(media_type.response_label(), b"application/json response");
+
+        let server_config = KeyConfig::new(KEY_ID, KEM, Vec::from(SYMMETRIC)).unwrap();
+        let server = Server::with_media_type(server_config, media_type.clone()).unwrap();
+        let mut config = KeyConfig::decode(&server.config().encode().unwrap()).unwrap();
+
+        let client = ClientRequest::from_config_with_media_type(&mut config, media_type).unwrap();
+        let (enc_request, client_response) = client.encapsulate(REQUEST).unwrap();
+
+        let (request, server_response) = server.decapsulate(&enc_request).unwrap();
+        assert_eq!(&request[..], REQUEST);
+
+        let enc_response = server_response.encapsulate(RESPONSE).unwrap();
+        let response = client_response.decapsulate(&enc_response).unwrap();
+        assert_eq!(&response[..], RESPONSE);
+    }
+
+    #[test]
+    fn media_type_mismatch() {
+        init();
+
+        let server_config = KeyConfig::new(KEY_ID, KEM, Vec::from(SYMMETRIC)).unwrap();
+        let server = Server::new(server_config).unwrap();
+        let mut config = KeyConfig::decode(&server.config().encode().unwrap()).unwrap();
+
+        let media_type = MediaType::new("application/json");
+        let client = ClientRequest::from_config_with_media_type(&mut config, media_type).unwrap();
+        let (enc_request, _) = client.encapsulate(REQUEST).unwrap();
+        assert!(server.decapsulate(&enc_request).is_err());
+    }
+
+    #[test]
+    fn authenticated_modes() {
+        init();
+
+        let psk = Psk::new(&[3; 32], b"device").unwrap();
+        #[cfg_attr(feature = "nss", allow(unused_mut))]
+        let mut modes = vec![(SenderMode::Psk(psk.clone()), ReceiverMode::Psk(psk.clone()))];
+        // NSS does not implement the authenticated modes.
+        #[cfg(not(feature = "nss"))]
+        {
+            let (sk_s, pk_s) = crate::hpke::generate_key_pair(KEM).unwrap();
+            modes.extend([
+                (
+                    SenderMode::Auth(sk_s.clone(), pk_s.clone()),
+                    ReceiverMode::Auth(pk_s.clone()),
+                ),
+                (
+                    SenderMode::AuthPsk(sk_s, pk_s.clone(), psk.clone()),
+                    ReceiverMode::AuthPsk(pk_s, psk),
+                ),
+            ]);
+        }
+        for (sender_mode, receiver_mode) in modes {
+            let server_config = KeyConfig::new(KEY_ID, KEM, Vec::from(SYMMETRIC)).unwrap();
+            let server = Server::with_mode(server_config, MediaType::BHTTP, receiver_mode).unwrap();
+            let mut config = KeyConfig::decode(&server.config().encode().unwrap()).unwrap();
+
+            let client =
+                ClientRequest::from_config_with_mode(&mut config, MediaType::BHTTP, &sender_mode)
+                    .unwrap();
+            let (enc_request, client_response) = client.encapsulate(REQUEST).unwrap();
+            let (request, server_response) = server.decapsulate(&enc_request).unwrap();
+            assert_eq!(&request[..], REQUEST);
+
+            let enc_response = server_response.encapsulate(RESPONSE).unwrap();
+            let response = client_response.decapsulate(&enc_response).unwrap();
+            assert_eq!(&response[..], RESPONSE);
+
+            // A standard request isn't accepted.
+            let client = ClientRequest::from_config(&mut config).unwrap();
+            let (enc_request, _) = client.encapsulate(REQUEST).unwrap();
+            assert!(server.decapsulate(&enc_request).is_err());
+        }
+    }
+
+    #[test]
+    fn psk_mismatch() {
+        init();
+
+        let server_config = KeyConfig::new(KEY_ID, KEM, Vec::from(SYMMETRIC)).unwrap();
+        let psk = Psk::new(&[3; 32], b"device").unwrap();
+        let server =
+            Server::with_mode(server_config, MediaType::BHTTP, ReceiverMode::Psk(psk)).unwrap();
+        let mut config = KeyConfig::decode(&server.config().encode().unwrap()).unwrap();
+
+        let other = SenderMode::Psk(Psk::new(&[4; 32], b"device").unwrap());
+        let client =
+            ClientRequest::from_config_with_mode(&mut config, MediaType::BHTTP, &other).unwrap();
+        let (enc_request, _) = client.encapsulate(REQUEST).unwrap();
+        assert!(server.decapsulate(&enc_request).is_err());
+    }
+
+    #[cfg(all(feature = "pq", feature = "rust-hpke"))]
+    #[test]
+    fn post_quantum() {
+        init();
+
+        let kems = [
+            Kem::MlKem768,
+            #[cfg(feature = "xwing")]
+            Kem::XWing,
+        ];
+        for kem in kems {
+            let server_config = KeyConfig::new(KEY_ID, kem, Vec::from(SYMMETRIC)).unwrap();
+            let server = Server::new(server_config).unwrap();
+            let encoded_config = server.config().encode().unwrap();
+            assert_eq!(
+                encoded_config.len(),
+                3 + kem.n_pk() + 2 + 4 * SYMMETRIC.len()
+            );
+
+            let client = ClientRequest::from_encoded_config(&encoded_config).unwrap();
+            let (enc_request, client_response) = client.encapsulate(REQUEST).unwrap();
+            let (request, server_response) = server.decapsulate(&enc_request).unwrap();
+            assert_eq!(&request[..], REQUEST);
+
+            let enc_response = server_response.encapsulate(RESPONSE).unwrap();
+            let response = client_response.decapsulate(&enc_response).unwrap();
+            assert_eq!(&response[..], RESPONSE);
+        }
+    }
+
     fn assert_truncated<T: Debug>(res: Res<T>) {
         match res.unwrap_err() {
             Error::Truncated => {}
@@ -622,6 +1262,8 @@ mod test {
             Error::Aead(_) => {}
             #[cfg(feature = "nss")]
             Error::Crypto(_) => {}
+            #[cfg(feature = "openssl")]
+            Error::OpenSsl(_) => {}
             Error::Io(e) => assert_eq!(e.kind(), ErrorKind::UnexpectedEof),
             e => panic!("unexpected error type: {e:?}"),
         }
@@ -688,7 +1330,7 @@ mod test {
         response_truncated(7);
     }
 
-    #[cfg(feature = "rust-hpke")]
+    #[cfg(any(feature = "rust-hpke", feature = "openssl"))]
     #[test]
     fn derive_key_pair() {
         const IKM: &[u8] = &[
@@ -825,12 +1467,11 @@ mod test {
         let merged_response = enc_response.chunks(2).map(|chunk| {
             if chunk.len() == 2 {
                 println!("Found too elements");
-                let mut first = chunk[0].as_ref().unwrap().clone();
+                let first = chunk[0].as_ref().unwrap();
                 let second = chunk[1].as_ref().unwrap();
-                first.append(&mut second.clone());
-                Ok::<Vec<u8>, Error>(first.clone())
+                Ok::<Vec<u8>, Error>([&first[..], &second[..]].concat())
             } else {
-                Ok::<Vec<u8>, Error>(chunk[0].as_ref().unwrap().clone())
+                Ok::<Vec<u8>, Error>(chunk[0].as_ref().unwrap().to_vec())
             }
         });
 
@@ -870,12 +1511,11 @@ mod test {
 
         let merged_response = enc_response.chunks(2).map(|chunk| {
             if chunk.len() == 2 {
-                let mut first = chunk[0].as_ref().unwrap().clone();
+                let first = chunk[0].as_ref().unwrap();
                 let second = chunk[1].as_ref().unwrap();
-                first.append(&mut second.clone());
-                Ok::<Vec<u8>, Error>(first.clone())
+                Ok::<Vec<u8>, Error>([&first[..], &second[..]].concat())
             } else {
-                Ok::<Vec<u8>, Error>(chunk[0].as_ref().unwrap().clone())
+                Ok::<Vec<u8>, Error>(chunk[0].as_ref().unwrap().to_vec())
             }
         });
 
@@ -923,7 +1563,7 @@ mod test {
                     .collect();
                 futures_util::stream::iter(chunks)
             } else {
-                let vec = vec![Ok::<Vec<u8>, Error>(c)];
+                let vec = vec![Ok::<Vec<u8>, Error>(c.to_vec())];
                 futures_util::stream::iter(vec)
             }
         });
@@ -934,4 +1574,418 @@ mod test {
         let next = response.next().await;
         assert!(next.is_some_and(|x| x.is_ok_and(|x| x.eq_ignore_ascii_case(RESPONSE))));
     }
+
+    #[tokio::test]
+    async fn request_stream() {
+        init();
+
+        let server_config = KeyConfig::new(KEY_ID, KEM, Vec::from(SYMMETRIC)).unwrap();
+        let server = Server::new(server_config).unwrap();
+        let encoded_config = server.config().encode().unwrap();
+
+        let client = ClientRequest::from_encoded_config(&encoded_config).unwrap();
+        let (first, second) = REQUEST.split_at(10);
+        let stream = stream! {
+            yield Ok::<Vec<u8>, Error>(first.to_vec());
+            yield Ok::<Vec<u8>, Error>(second.to_vec());
+        };
+        let (enc_request, client_response) = client.encapsulate_stream(stream).unwrap();
+
+        // Deliver the encapsulated request a few bytes at a time.
+        let fragmented_request = enc_request.flat_map(|chunk| {
+            let chunks: Vec<_> = chunk.unwrap().chunks(5).map(|c| Ok(c.to_vec())).collect();
+            futures_util::stream::iter(chunks)
+        });
+        let (request, server_response) = server
+            .decapsulate_stream(Box::pin(fragmented_request), 1024)
+            .await
+            .unwrap();
+        let request: Vec<_> = request.map(Result::unwrap).collect().await;
+        assert_eq!(request, [first, second]);
+
+        let enc_response = server_response.encapsulate(RESPONSE).unwrap();
+        let response = client_response.decapsulate(&enc_response).unwrap();
+        assert_eq!(&response[..], RESPONSE);
+    }
+
+    #[tokio::test]
+    async fn duplex_stream() {
+        init();
+
+        let server_config = KeyConfig::new(KEY_ID, KEM, Vec::from(SYMMETRIC)).unwrap();
+        let server = Server::new(server_config).unwrap();
+        let encoded_config = server.config().encode().unwrap();
+        let client = ClientRequest::from_encoded_config(&encoded_config).unwrap();
+
+        let (request_tx, request_rx) = mpsc::unbounded::<Result<Vec<u8>, Error>>();
+        let (enc_request, client_response) = client
+            .encapsulate_stream_with(request_rx, StreamOptions::default())
+            .unwrap();
+        request_tx.unbounded_send(Ok(b"frame 1".to_vec())).unwrap();
+        let (mut request, server_response) =
+            server.decapsulate_stream(enc_request, 1024).await.unwrap();
+        assert_eq!(request.next().await.unwrap().unwrap(), &b"frame 1"[..]);
+
+        // The response starts while the request is still open.
+        let (response_tx, response_rx) = mpsc::unbounded::<Result<Vec<u8>, Error>>();
+        let enc_response =
+            server_response.encapsulate_stream_with(response_rx, StreamOptions::default());
+        let mut response = client_response.decapsulate_stream(enc_response).await;
+        response_tx.unbounded_send(Ok(b"text 1".to_vec())).unwrap();
+        assert_eq!(response.next().await.unwrap().unwrap(), &b"text 1"[..]);
+
+        request_tx.unbounded_send(Ok(b"frame 2".to_vec())).unwrap();
+        drop(request_tx);
+        assert_eq!(request.next().await.unwrap().unwrap(), &b"frame 2"[..]);
+        // The final chunk is empty.
+        assert!(request.next().await.unwrap().unwrap().is_empty());
+        assert!(request.next().await.is_none());
+
+        response_tx.unbounded_send(Ok(b"text 2".to_vec())).unwrap();
+        drop(response_tx);
+        assert_eq!(response.next().await.unwrap().unwrap(), &b"text 2"[..]);
+        assert!(response.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn request_stream_truncated() {
+        init();
+
+        let server_config = KeyConfig::new(KEY_ID, KEM, Vec::from(SYMMETRIC)).unwrap();
+        let server = Server::new(server_config).unwrap();
+        let encoded_config = server.config().encode().unwrap();
+
+        let client = ClientRequest::from_encoded_config(&encoded_config).unwrap();
+        let stream = stream! { yield Ok::<Vec<u8>, Error>(REQUEST.to_vec()); };
+        let (enc_request, _) = client.encapsulate_stream(stream).unwrap();
+        let mut enc_request: Vec<u8> = enc_request.map(|c| c.unwrap().to_vec()).concat().await;
+        enc_request.pop();
+
+        let (mut request, _) = server
+            .decapsulate_stream(Box::pin(stream! { yield Ok(enc_request); }), 1024)
+            .await
+            .unwrap();
+        assert!(matches!(request.next().await, Some(Err(Error::Truncated))));
+    }
+
+    #[tokio::test]
+    async fn request_stream_chunk_too_large() {
+        init();
+
+        let server_config = KeyConfig::new(KEY_ID, KEM, Vec::from(SYMMETRIC)).unwrap();
+        let server = Server::new(server_config).unwrap();
+        let encoded_config = server.config().encode().unwrap();
+
+        let client = ClientRequest::from_encoded_config(&encoded_config).unwrap();
+        let stream = stream! { yield Ok::<Vec<u8>, Error>(REQUEST.to_vec()); };
+        let (enc_request, _) = client.encapsulate_stream(stream).unwrap();
+
+        let (mut request, _) = server
+            .decapsulate_stream(enc_request, REQUEST.len())
+            .await
+            .unwrap();
+        assert!(matches!(
+            request.next().await,
+            Some(Err(Error::ChunkTooLarge))
+        ));
+    }
+
+    fn response_pair() -> (ServerResponse, ClientResponse) {
+        init();
+        let server_config = KeyConfig::new(KEY_ID, KEM, Vec::from(SYMMETRIC)).unwrap();
+        let server = Server::new(server_config).unwrap();
+        let encoded_config = server.config().encode().unwrap();
+        let client = ClientRequest::from_encoded_config(&encoded_config).unwrap();
+        let (enc_request, client_response) = client.encapsulate(REQUEST).unwrap();
+        let (_, server_response) = server.decapsulate(&enc_request).unwrap();
+        (server_response, client_response)
+    }
+
+    #[test]
+    fn event_stream_boundaries() {
+        assert_eq!(event_stream_boundary(b"data: a"), None);
+        assert_eq!(event_stream_boundary(b"data: a\n"), None);
+        assert_eq!(event_stream_boundary(b"data: a\n\ndata: b"), Some(9));
+        assert_eq!(event_stream_boundary(b"data: a\n\ndata: b\n\n"), Some(18));
+        assert_eq!(event_stream_boundary(b"data: a\r\n\r\n"), Some(11));
+        assert_eq!(event_stream_boundary(b"data: a\r\rx"), Some(9));
+        // The last CR might be followed by LF.
+        assert_eq!(event_stream_boundary(b"data: a\r\n\r"), None);
+    }
+
+    #[tokio::test]
+    async fn response_stream_flushed() {
+        let (server_response, client_response) = response_pair();
+        let stream = stream! {
+            yield Ok::<Vec<u8>, Error>(RESPONSE.to_vec());
+            yield Ok::<Vec<u8>, Error>(RESPONSE.to_vec());
+        };
+        let enc_response =
+            server_response.encapsulate_stream_with(stream, StreamOptions::default());
+
+        // The nonce, a chunk for each input chunk, and an empty final chunk.
+        let enc_response: Vec<_> = enc_response.map(Result::unwrap).collect().await;
+        assert_eq!(enc_response.len(), 4);
+        assert_eq!(&enc_response[3][..1], &[0]);
+
+        let response = client_response
+            .decapsulate_stream(futures_util::stream::iter(enc_response.into_iter().map(Ok)))
+            .await;
+        let response: Vec<_> = response.map(Result::unwrap).collect().await;
+        assert_eq!(response, [RESPONSE, RESPONSE]);
+    }
+
+    /// Encapsulate a two chunk response, as separate pieces.
+    async fn encapsulated_response() -> (Vec<Bytes>, ClientResponse) {
+        let (server_response, client_response) = response_pair();
+        let stream = stream! {
+            yield Ok::<Vec<u8>, Error>(RESPONSE.to_vec());
+            yield Ok::<Vec<u8>, Error>(RESPONSE.to_vec());
+        };
+        let enc_response =
+            server_response.encapsulate_stream_with(stream, StreamOptions::default());
+        let enc_response = enc_response.map(Result::unwrap).collect().await;
+        (enc_response, client_response)
+    }
+
+    #[tokio::test]
+    async fn response_stream_truncated() {
+        let (mut enc_response, client_response) = encapsulated_response().await;
+        assert_eq!(enc_response.pop().unwrap()[0], 0);
+
+        let response = client_response
+            .decapsulate_stream(futures_util::stream::iter(enc_response.into_iter().map(Ok)))
+            .await;
+        let response: Vec<_> = response.collect().await;
+        assert_eq!(response.len(), 3);
+        assert!(response[..2]
+            .iter()
+            .all(|r| r.as_ref().unwrap() == RESPONSE));
+        assert!(matches!(response[2], Err(Error::Truncated)));
+    }
+
+    #[tokio::test]
+    async fn response_stream_truncated_chunk() {
+        let (mut enc_response, client_response) = encapsulated_response().await;
+        enc_response.pop();
+        let last = enc_response.pop().unwrap();
+        enc_response.push(last.slice(..last.len() - 1));
+
+        let response = client_response
+            .decapsulate_stream(futures_util::stream::iter(enc_response.into_iter().map(Ok)))
+            .await;
+        let response: Vec<_> = response.collect().await;
+        assert_eq!(response.len(), 2);
+        assert!(matches!(response[1], Err(Error::Truncated)));
+    }
+
+    #[tokio::test]
+    async fn response_stream_trailing_data() {
+        let (mut enc_response, client_response) = encapsulated_response().await;
+        enc_response.push(Bytes::from_static(b"\x01"));
+
+        let response = client_response
+            .decapsulate_stream(futures_util::stream::iter(enc_response.into_iter().map(Ok)))
+            .await;
+        let response: Vec<_> = response.collect().await;
+        assert!(matches!(response.last(), Some(Err(Error::Format))));
+    }
+
+    /// A length of `u64::MAX` from the relay can't be buffered, and doesn't
+    /// overflow, even without a limit.
+    #[tokio::test]
+    async fn response_stream_huge_chunk() {
+        const HUGE: &[u8] = &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        for max_chunk_len in [None, Some(usize::MAX)] {
+            let (mut enc_response, mut client_response) = encapsulated_response().await;
+            enc_response.truncate(1); // Only the nonce.
+            enc_response.push(Bytes::from_static(HUGE));
+            if let Some(max_chunk_len) = max_chunk_len {
+                client_response = client_response.with_max_chunk_len(max_chunk_len);
+            }
+
+            let response = client_response
+                .decapsulate_stream(futures_util::stream::iter(enc_response.into_iter().map(Ok)))
+                .await;
+            let response: Vec<_> = response.collect().await;
+            assert_eq!(response.len(), 1);
+            assert!(matches!(response[0], Err(Error::ChunkTooLarge)));
+        }
+    }
+
+    #[test]
+    fn variant_too_long() {
+        let max = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        assert_eq!(
+            variant_decode_prefix(&max).unwrap(),
+            Some((u64::MAX, max.len()))
+        );
+        // More than 64 bits.
+        let mut over = max;
+        over[9] = 0x02;
+        assert!(matches!(variant_decode_prefix(&over), Err(Error::Format)));
+        // More than ten bytes.
+        let mut long = max;
+        long[9] = 0x80;
+        assert!(matches!(variant_decode_prefix(&long), Err(Error::Format)));
+    }
+
+    #[tokio::test]
+    async fn response_stream_input_error() {
+        let (mut enc_response, client_response) = encapsulated_response().await;
+        enc_response.truncate(2);
+        let input = futures_util::stream::iter(enc_response.into_iter().map(Ok)).chain(
+            futures_util::stream::iter([Err(Error::Stream("lost".into()))]),
+        );
+
+        let response = client_response.decapsulate_stream(input).await;
+        let response: Vec<_> = response.collect().await;
+        assert_eq!(response.len(), 2);
+        assert!(matches!(&response[1], Err(Error::Stream(s)) if s == "lost"));
+    }
+
+    #[tokio::test]
+    async fn response_stream_events() {
+        let (server_response, client_response) = response_pair();
+        let stream = stream! {
+            yield Ok::<Vec<u8>, Error>(b"data: a\n".to_vec());
+            yield Ok::<Vec<u8>, Error>(b"\ndata: b\n\nda".to_vec());
+            yield Ok::<Vec<u8>, Error>(b"ta: c".to_vec());
+        };
+        let options = StreamOptions {
+            event_stream: true,
+            keepalive: None,
+        };
+        let enc_response = server_response.encapsulate_stream_with(stream, options);
+        let response = client_response.decapsulate_stream(enc_response).await;
+        let response: Vec<_> = response.map(Result::unwrap).collect().await;
+        assert_eq!(response, [&b"data: a\n\ndata: b\n\n"[..], &b"data: c"[..]]);
+    }
+
+    #[tokio::test]
+    async fn response_stream_keepalive() {
+        let (server_response, client_response) = response_pair();
+        let stream = stream! {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            yield Ok::<Vec<u8>, Error>(RESPONSE.to_vec());
+        };
+        let options = StreamOptions {
+            event_stream: false,
+            keepalive: Some(Duration::from_millis(10)),
+        };
+        let enc_response: Vec<_> = server_response
+            .encapsulate_stream_with(stream, options)
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        // The nonce, at least one keepalive, the content, and the final chunk.
+        assert!(enc_response.len() > 3);
+
+        // Keepalive chunks are authenticated, but not passed on.
+        let response = client_response
+            .decapsulate_stream(futures_util::stream::iter(enc_response.into_iter().map(Ok)))
+            .await;
+        let response: Vec<_> = response.map(Result::unwrap).collect().await;
+        assert_eq!(response, [RESPONSE]);
+    }
+
+    #[tokio::test]
+    async fn response_stream_rechunked() {
+        let (server_response, client_response) = response_pair();
+        let stream = stream! {
+            yield Ok::<Vec<u8>, Error>(b"a".to_vec());
+            yield Ok::<Vec<u8>, Error>(b"bcdefgh".to_vec());
+            yield Ok::<Vec<u8>, Error>(b"ij".to_vec());
+        };
+        let enc_response: Vec<_> = server_response
+            .encapsulate_stream_with(rechunk(stream, 4), StreamOptions::default())
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        // Both full chunks are the same size.
+        assert_eq!(enc_response.len(), 5);
+        assert_eq!(enc_response[1].len(), enc_response[2].len());
+
+        let response = client_response
+            .decapsulate_stream(futures_util::stream::iter(enc_response.into_iter().map(Ok)))
+            .await;
+        let response: Vec<_> = response.map(Result::unwrap).collect().await;
+        assert_eq!(response, [&b"abcd"[..], &b"efgh"[..], &b"ij"[..]]);
+    }
+
+    #[tokio::test]
+    async fn response_stream_empty() {
+        let (server_response, client_response) = response_pair();
+        let stream = futures_util::stream::empty::<Result<Vec<u8>, Error>>();
+        let enc_response: Vec<_> = server_response
+            .encapsulate_stream(stream)
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        // The nonce and an empty final chunk.
+        assert_eq!(enc_response.len(), 2);
+        assert_eq!(&enc_response[1][..1], &[0]);
+
+        let response = client_response
+            .decapsulate_stream(futures_util::stream::iter(enc_response.into_iter().map(Ok)))
+            .await;
+        let response: Vec<_> = response.collect().await;
+        assert!(response.is_empty());
+    }
+
+    #[tokio::test]
+    async fn response_stream_held_error() {
+        for ok_chunks in 0..2 {
+            let (server_response, _) = response_pair();
+            let stream = stream! {
+                for _ in 0..ok_chunks {
+                    yield Ok::<Vec<u8>, Error>(RESPONSE.to_vec());
+                }
+                yield Err(Error::Truncated);
+            };
+            let enc_response: Vec<_> = server_response.encapsulate_stream(stream).collect().await;
+            // The nonce, any chunks before the error, then the error.
+            assert_eq!(enc_response.len(), 2 + ok_chunks);
+            assert!(matches!(enc_response.last(), Some(Err(Error::Stream(_)))));
+        }
+    }
+
+    #[tokio::test]
+    async fn response_stream_long_event() {
+        let (server_response, client_response) = response_pair();
+        let long = vec![b'x'; super::MAX_PENDING_EVENT_LEN];
+        let input = long.clone();
+        let stream = stream! {
+            yield Ok::<Vec<u8>, Error>(b"data: ".to_vec());
+            yield Ok::<Vec<u8>, Error>(input);
+            yield Ok::<Vec<u8>, Error>(b"\n\n".to_vec());
+        };
+        let options = StreamOptions {
+            event_stream: true,
+            keepalive: None,
+        };
+        let enc_response = server_response.encapsulate_stream_with(stream, options);
+        let response = client_response.decapsulate_stream(enc_response).await;
+        let response: Vec<_> = response.map(Result::unwrap).collect().await;
+        // The event that doesn't end in time is sent without waiting.
+        assert_eq!(response.len(), 2);
+        assert_eq!(&response[0][6..], &long[..]);
+        assert_eq!(&response[1][..], b"\n\n");
+    }
+
+    #[tokio::test]
+    async fn response_stream_error() {
+        let (server_response, _) = response_pair();
+        let stream = stream! {
+            yield Ok::<Vec<u8>, Error>(RESPONSE.to_vec());
+            yield Err(Error::Truncated);
+        };
+        let enc_response: Vec<_> = server_response
+            .encapsulate_stream_with(stream, StreamOptions::default())
+            .collect()
+            .await;
+        // No final chunk is sent after the error.
+        assert_eq!(enc_response.len(), 3);
+        assert!(enc_response[2].is_err());
+    }
 }
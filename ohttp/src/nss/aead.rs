@@ -13,6 +13,7 @@ use crate::{
     err::{Error, Res},
     hpke::Aead as AeadId,
 };
+use bytes::BytesMut;
 use std::{
     convert::{TryFrom, TryInto},
     mem,
@@ -124,17 +125,26 @@ impl Aead {
     }
 
     pub fn seal(&mut self, aad: &[u8], pt: &[u8]) -> Res<Vec<u8>> {
+        let mut buf = BytesMut::with_capacity(pt.len() + TAG_LEN);
+        buf.extend_from_slice(pt);
+        self.seal_in_place(aad, &mut buf)?;
+        Ok(Vec::from(buf))
+    }
+
+    /// Encrypt the content of `buf` in place and add the tag.
+    /// This only allocates if `buf` doesn't have space for the tag.
+    pub fn seal_in_place(&mut self, aad: &[u8], buf: &mut BytesMut) -> Res<()> {
         if self.mode != Mode::Encrypt {
             return Err(Error::AeadMode);
         }
         // A copy for the nonce generator to write into.  But we don't use the value.
         let mut nonce = self.nonce_base;
-        // Ciphertext with enough space for the tag.
-        // Even though we give the operation a separate buffer for the tag,
-        // reserve the capacity on allocation.
-        let mut ct = vec![0; pt.len() + TAG_LEN];
+        let pt_len = buf.len();
+        buf.resize(pt_len + TAG_LEN, 0);
+        let (data, tag) = buf.split_at_mut(pt_len);
         let mut ct_len: c_int = 0;
-        let mut tag = vec![0; TAG_LEN];
+        // NSS is happy to encrypt in place, so `data` is both input and output.
+        let data = data.as_mut_ptr();
         secstatus_to_res(unsafe {
             PK11_AEADOp(
                 *self.ctx,
@@ -144,27 +154,35 @@ impl Aead {
                 c_int_len(nonce.len()),
                 aad.as_ptr(),
                 c_int_len(aad.len()),
-                ct.as_mut_ptr(),
+                data,
                 &mut ct_len,
-                c_int_len(ct.len()), // signed :(
+                c_int_len(pt_len), // signed :(
                 tag.as_mut_ptr(),
-                c_int_len(tag.len()),
-                pt.as_ptr(),
-                c_int_len(pt.len()),
+                c_int_len(TAG_LEN),
+                data,
+                c_int_len(pt_len),
             )
         })?;
-        ct.truncate(usize::try_from(ct_len).unwrap());
-
-        ct_len = ct.len();
-        let pt_len = pt.len();
+        let ct_len = usize::try_from(ct_len).unwrap();
         if ct_len != pt_len {
             return Err(Error::UnequalLength(ct_len, pt_len));
         }
-        ct.append(&mut tag);
-        Ok(ct)
+        Ok(())
     }
 
     pub fn open(&mut self, aad: &[u8], seq: SequenceNumber, ct: &[u8]) -> Res<Vec<u8>> {
+        let mut buf = BytesMut::from(ct);
+        self.open_in_place(aad, seq, &mut buf)?;
+        Ok(Vec::from(buf))
+    }
+
+    /// Decrypt the content of `buf` in place and remove the tag.
+    pub fn open_in_place(
+        &mut self,
+        aad: &[u8],
+        seq: SequenceNumber,
+        buf: &mut BytesMut,
+    ) -> Res<()> {
         if self.mode != Mode::Decrypt {
             return Err(Error::AeadMode);
         }
@@ -172,9 +190,14 @@ impl Aead {
         for (i, n) in nonce.iter_mut().rev().take(COUNTER_LEN).enumerate() {
             *n ^= u8::try_from((seq >> (8 * i)) & 0xff).unwrap();
         }
-        let mut pt = vec![0; ct.len()]; // NSS needs more space than it uses for plaintext.
+        let pt_expected = buf.len().checked_sub(TAG_LEN).ok_or(Error::Truncated)?;
         let mut pt_len: c_int = 0;
-        let pt_expected = ct.len().checked_sub(TAG_LEN).ok_or(Error::Truncated)?;
+        // NSS needs more space than it uses for plaintext, so it gets the
+        // whole buffer, with the tag copied out of the way first.
+        let mut tag = [0; TAG_LEN];
+        tag.copy_from_slice(&buf[pt_expected..]);
+        let out_len = buf.len();
+        let data = buf.as_mut_ptr();
         secstatus_to_res(unsafe {
             PK11_AEADOp(
                 *self.ctx,
@@ -184,12 +207,12 @@ impl Aead {
                 c_int_len(nonce.len()),
                 aad.as_ptr(),
                 c_int_len(aad.len()),
-                pt.as_mut_ptr(),
+                data,
                 &mut pt_len,
-                c_int_len(pt.len()),                    // signed :(
-                ct.as_ptr().add(pt_expected) as *mut _, // const cast :(
+                c_int_len(out_len), // signed :(
+                tag.as_mut_ptr(),
                 c_int_len(TAG_LEN),
-                ct.as_ptr(),
+                data,
                 c_int_len(pt_expected),
             )
         })?;
@@ -197,8 +220,8 @@ impl Aead {
         if len != pt_expected {
             return Err(Error::UnequalLength(len, pt_expected));
         }
-        pt.truncate(len);
-        Ok(pt)
+        buf.truncate(len);
+        Ok(())
     }
 }
 
@@ -208,6 +231,7 @@ mod test {
         super::{super::hpke::Aead as AeadId, init},
         Aead, Mode, SequenceNumber, NONCE_LEN,
     };
+    use bytes::BytesMut;
 
     /// Check that the first invocation of encryption matches expected values.
     /// Also check decryption of the same.
@@ -229,6 +253,15 @@ mod test {
         let mut dec = Aead::new(Mode::Decrypt, algorithm, &k, *nonce).unwrap();
         let plaintext = dec.open(aad, 0, ct).unwrap();
         assert_eq!(&plaintext[..], pt);
+
+        // The same, but in place.
+        let mut buf = BytesMut::from(pt);
+        let mut enc = Aead::new(Mode::Encrypt, algorithm, &k, *nonce).unwrap();
+        enc.seal_in_place(aad, &mut buf).unwrap();
+        assert_eq!(&buf[..], ct);
+        let mut dec = Aead::new(Mode::Decrypt, algorithm, &k, *nonce).unwrap();
+        dec.open_in_place(aad, 0, &mut buf).unwrap();
+        assert_eq!(&buf[..], pt);
     }
 
     fn decrypt(
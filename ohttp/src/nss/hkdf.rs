@@ -3,8 +3,9 @@ use super::{
     p11::{
         sys::{
             self, CKA_DERIVE, CKF_HKDF_SALT_DATA, CKF_HKDF_SALT_NULL, CKM_AES_GCM,
-            CKM_CHACHA20_POLY1305, CKM_HKDF_DATA, CKM_HKDF_DERIVE, CKM_SHA256, CK_BBOOL,
-            CK_HKDF_PARAMS, CK_INVALID_HANDLE, CK_MECHANISM_TYPE, CK_OBJECT_HANDLE, CK_ULONG,
+            CKM_CHACHA20_POLY1305, CKM_HKDF_DATA, CKM_HKDF_DERIVE, CKM_SHA256, CKM_SHA384,
+            CKM_SHA512, CK_BBOOL, CK_HKDF_PARAMS, CK_INVALID_HANDLE, CK_MECHANISM_TYPE,
+            CK_OBJECT_HANDLE, CK_ULONG,
         },
         ParamItem, SymKey,
     },
@@ -46,7 +47,6 @@ impl Hkdf {
         Self { kdf }
     }
 
-    #[cfg(test)]
     pub fn import_ikm(ikm: &[u8]) -> Res<SymKey> {
         let slot = super::p11::Slot::internal()?;
         let ptr = unsafe {
@@ -65,7 +65,8 @@ impl Hkdf {
     fn mech(&self) -> CK_MECHANISM_TYPE {
         CK_MECHANISM_TYPE::from(match self.kdf {
             Kdf::HkdfSha256 => CKM_SHA256,
-            _ => unimplemented!(),
+            Kdf::HkdfSha384 => CKM_SHA384,
+            Kdf::HkdfSha512 => CKM_SHA512,
         })
     }
 
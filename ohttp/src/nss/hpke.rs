@@ -1,7 +1,8 @@
 use super::{
-    super::hpke::{Aead, Kdf, Kem},
+    super::hpke::{Aead, Kdf, Kem, Psk, ReceiverMode, SenderMode},
     err::{sec::SEC_ERROR_INVALID_ARGS, secstatus_to_res, Error},
-    p11::{sys, Item, PrivateKey, PublicKey, Slot, SymKey},
+    hkdf::Hkdf,
+    p11::{sys, Item, Slot, SymKey},
 };
 use crate::err::Res;
 use std::{
@@ -10,8 +11,9 @@ use std::{
     os::raw::c_uint,
     ptr::{addr_of_mut, null, null_mut},
 };
-use tracing::{log_enabled, trace};
+use tracing::{enabled, trace, Level};
 
+pub use super::p11::{PrivateKey, PublicKey};
 pub use sys::{HpkeAeadId as AeadId, HpkeKdfId as KdfId, HpkeKemId as KemId};
 
 /// Configuration for `Hpke`.
@@ -72,14 +74,19 @@ unsafe fn destroy_hpke_context(cx: *mut sys::HpkeContext) {
 scoped_ptr!(HpkeContext, sys::HpkeContext, destroy_hpke_context);
 
 impl HpkeContext {
-    fn new(config: Config) -> Res<Self> {
+    /// Create a context, which uses the PSK mode if `psk` is set.
+    fn new(config: Config, psk: Option<&Psk>) -> Res<Self> {
+        let psk_key = psk.map(|psk| Hkdf::import_ikm(psk.psk())).transpose()?;
+        let psk_id = psk.map(|psk| Item::wrap(psk.id()));
         let ptr = unsafe {
             sys::PK11_HPKE_NewContext(
                 KemId::Type::from(u16::from(config.kem)),
                 KdfId::Type::from(u16::from(config.kdf)),
                 AeadId::Type::from(u16::from(config.aead)),
-                null_mut(),
-                null(),
+                psk_key.as_ref().map_or(null_mut(), |k| **k),
+                psk_id
+                    .as_ref()
+                    .map_or(null(), |id| id as *const sys::SECItem),
             )
         };
         Self::from_ptr(ptr)
@@ -111,10 +118,30 @@ pub struct HpkeS {
 
 impl HpkeS {
     /// Create a new context that uses the KEM mode for sending.
+    #[cfg(test)]
     #[allow(clippy::similar_names)]
     pub fn new(config: Config, pk_r: &mut PublicKey, info: &[u8]) -> Res<Self> {
+        Self::with_mode(config, pk_r, info, &SenderMode::Base)
+    }
+
+    /// Create a new context for sending that uses the given HPKE mode.
+    /// NSS doesn't implement the authenticated modes.
+    #[allow(clippy::similar_names)]
+    pub fn with_mode(
+        config: Config,
+        pk_r: &mut PublicKey,
+        info: &[u8],
+        mode: &SenderMode,
+    ) -> Res<Self> {
+        let psk = match mode {
+            SenderMode::Base => None,
+            SenderMode::Psk(psk) => Some(psk),
+            SenderMode::Auth(..) | SenderMode::AuthPsk(..) => {
+                return Err(crate::Error::Unsupported);
+            }
+        };
         let (sk_e, pk_e) = generate_key_pair(config.kem)?;
-        let context = HpkeContext::new(config)?;
+        let context = HpkeContext::new(config, psk)?;
         secstatus_to_res(unsafe {
             sys::PK11_HPKE_SetupS(*context, *pk_e, *sk_e, **pk_r, &Item::wrap(info))
         })?;
@@ -141,7 +168,7 @@ impl HpkeS {
             sys::PK11_HPKE_Seal(*self.context, &Item::wrap(aad), &Item::wrap(pt), &mut out)
         })?;
         let v = Item::from_ptr(out)?;
-        Ok(unsafe { v.into_vec() })
+        unsafe { v.into_vec() }
     }
 }
 
@@ -166,6 +193,7 @@ pub struct HpkeR {
 
 impl HpkeR {
     /// Create a new context that uses the KEM mode for sending.
+    #[cfg(test)]
     #[allow(clippy::similar_names)]
     pub fn new(
         config: Config,
@@ -174,7 +202,28 @@ impl HpkeR {
         enc: &[u8],
         info: &[u8],
     ) -> Res<Self> {
-        let context = HpkeContext::new(config)?;
+        Self::with_mode(config, pk_r, sk_r, enc, info, &ReceiverMode::Base)
+    }
+
+    /// Create a new context for receiving that uses the given HPKE mode.
+    /// NSS doesn't implement the authenticated modes.
+    #[allow(clippy::similar_names)]
+    pub fn with_mode(
+        config: Config,
+        pk_r: &PublicKey,
+        sk_r: &PrivateKey,
+        enc: &[u8],
+        info: &[u8],
+        mode: &ReceiverMode,
+    ) -> Res<Self> {
+        let psk = match mode {
+            ReceiverMode::Base => None,
+            ReceiverMode::Psk(psk) => Some(psk),
+            ReceiverMode::Auth(_) | ReceiverMode::AuthPsk(..) => {
+                return Err(crate::Error::Unsupported);
+            }
+        };
+        let context = HpkeContext::new(config, psk)?;
         secstatus_to_res(unsafe {
             sys::PK11_HPKE_SetupR(
                 *context,
@@ -193,10 +242,13 @@ impl HpkeR {
 
     pub fn decode_public_key(kem: Kem, k: &[u8]) -> Res<PublicKey> {
         // NSS uses a context for this, but we don't want that, but a dummy one works fine.
-        let context = HpkeContext::new(Config {
-            kem,
-            ..Config::default()
-        })?;
+        let context = HpkeContext::new(
+            Config {
+                kem,
+                ..Config::default()
+            },
+            None,
+        )?;
         let mut ptr: *mut sys::SECKEYPublicKey = null_mut();
         secstatus_to_res(unsafe {
             sys::PK11_HPKE_Deserialize(
@@ -215,7 +267,7 @@ impl HpkeR {
             sys::PK11_HPKE_Open(*self.context, &Item::wrap(aad), &Item::wrap(ct), &mut out)
         })?;
         let v = Item::from_ptr(out)?;
-        Ok(unsafe { v.into_vec() })
+        unsafe { v.into_vec() }
     }
 }
 
@@ -235,7 +287,7 @@ impl Deref for HpkeR {
 /// Generate a key pair for the identified KEM.
 pub fn generate_key_pair(kem: Kem) -> Res<(PrivateKey, PublicKey)> {
     if kem != Kem::X25519Sha256 {
-        return Err(Error::InvalidKem);
+        return Err(crate::Error::InvalidKem);
     }
     let slot = Slot::internal()?;
 
@@ -252,7 +304,7 @@ pub fn generate_key_pair(kem: Kem) -> Res<(PrivateKey, PublicKey)> {
     let mut wrapped = Item::wrap(&params);
 
     // Try to make an insensitive key so that we can read the key data for tracing.
-    let insensitive_secret_ptr = if log_enabled!(tracing::Level::Trace) {
+    let insensitive_secret_ptr = if enabled!(Level::TRACE) {
         unsafe {
             sys::PK11_GenerateKeyPairWithOpFlags(
                 *slot,
@@ -268,8 +320,8 @@ pub fn generate_key_pair(kem: Kem) -> Res<(PrivateKey, PublicKey)> {
     } else {
         null_mut()
     };
-    if insensitive_secret_ptr.is_null() == public_ptr.is_null() {
-        return Error::unexpected;
+    if insensitive_secret_ptr.is_null() != public_ptr.is_null() {
+        return Err(Error::internal().into());
     }
 
     let secret_ptr = if insensitive_secret_ptr.is_null() {
@@ -288,8 +340,8 @@ pub fn generate_key_pair(kem: Kem) -> Res<(PrivateKey, PublicKey)> {
     } else {
         insensitive_secret_ptr
     };
-    if secret_ptr.is_null() == public_ptr.is_null() {
-        return Error::unexpected;
+    if secret_ptr.is_null() != public_ptr.is_null() {
+        return Err(Error::internal().into());
     }
 
     let sk = PrivateKey::from_ptr(secret_ptr)?;
@@ -298,10 +350,28 @@ pub fn generate_key_pair(kem: Kem) -> Res<(PrivateKey, PublicKey)> {
     Ok((sk, pk))
 }
 
+/// Import a key pair from its encoded form.  As with generation, only X25519 is supported.
+/// The HPKE implementation in NSS only has DHKEM(X25519, HKDF-SHA256), so a P-384 key
+/// could be imported into PK11, but `PK11_HPKE_SetupR` would not accept it.
+pub fn import_key_pair(kem: Kem, sk: &[u8], pk: &[u8]) -> Res<(PrivateKey, PublicKey)> {
+    match kem {
+        Kem::X25519Sha256 => {}
+        Kem::P384Sha384 => return Err(crate::Error::Unsupported),
+        #[allow(unreachable_patterns)]
+        _ => return Err(crate::Error::InvalidKem),
+    }
+    let sk = PrivateKey::import_x25519(sk, pk)?;
+    let pk = HpkeR::decode_public_key(kem, pk)?;
+    Ok((sk, pk))
+}
+
 #[cfg(test)]
 mod test {
-    use super::{generate_key_pair, Config, HpkeContext, HpkeR, HpkeS};
-    use crate::{hpke::Aead, init};
+    use super::{generate_key_pair, import_key_pair, Config, HpkeContext, HpkeR, HpkeS};
+    use crate::{
+        hpke::{Aead, Kem},
+        init,
+    };
 
     const INFO: &[u8] = b"info";
     const AAD: &[u8] = b"aad";
@@ -349,6 +419,34 @@ mod test {
         seal_open(Aead::ChaCha20Poly1305);
     }
 
+    #[allow(clippy::similar_names)] // for sk_x and pk_x
+    #[test]
+    fn import() {
+        // skRm and pkRm from RFC 9180, Appendix A.1.1.
+        const SK: &str = "4612c550263fc8ad58375df3f557aac531d26850903e55a9f23f21d8534e8ac8";
+        const PK: &str = "3948cfe0ad1ddb695d780e59077195da6c56506b027329794ab02bca80815c4d";
+        init();
+        let cfg = Config::default();
+        let (sk_r, mut pk_r) = import_key_pair(
+            cfg.kem(),
+            &hex::decode(SK).unwrap(),
+            &hex::decode(PK).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(hex::encode(pk_r.key_data().unwrap()), PK);
+
+        let mut hpke_s = HpkeS::new(cfg, &mut pk_r, INFO).unwrap();
+        let enc = hpke_s.enc().unwrap();
+        let ct = hpke_s.seal(AAD, PT).unwrap();
+        let mut hpke_r = HpkeR::new(cfg, &pk_r, &sk_r, &enc, INFO).unwrap();
+        assert_eq!(&hpke_r.open(AAD, &ct).unwrap()[..], PT);
+
+        assert!(matches!(
+            import_key_pair(Kem::P384Sha384, &[1; 48], &[4; 97]),
+            Err(crate::Error::Unsupported)
+        ));
+    }
+
     #[test]
     fn send_hpkecontext() {
         use std::{sync::mpsc, thread};
@@ -356,7 +454,7 @@ mod test {
         let (tx, rx) = mpsc::channel();
 
         thread::spawn(move || {
-            let context = HpkeContext::new(Config::default());
+            let context = HpkeContext::new(Config::default(), None);
             tx.send(context).unwrap();
         });
 
@@ -53,7 +53,6 @@ macro_rules! scoped_ptr {
 
         impl std::ops::Deref for $scoped {
             type Target = *mut $target;
-            #[must_use]
             fn deref(&self) -> &*mut $target {
                 &self.ptr
             }
@@ -75,7 +74,50 @@ macro_rules! scoped_ptr {
 
 scoped_ptr!(PrivateKey, SECKEYPrivateKey, SECKEY_DestroyPrivateKey);
 
+/// The start of a PKCS#8 encoding of an X25519 private key, as NSS understands it.
+/// This is `PrivateKeyInfo` with the `id-ecPublicKey` algorithm and the NSS OID
+/// for Curve25519, up to the start of the private key octets.
+const X25519_PKCS8_PREFIX: &[u8] = &[
+    0x30, 0x67, 0x02, 0x01, 0x00, 0x30, 0x14, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01,
+    0x06, 0x09, 0x2b, 0x06, 0x01, 0x04, 0x01, 0xda, 0x47, 0x0f, 0x01, 0x04, 0x4c, 0x30, 0x4a, 0x02,
+    0x01, 0x01, 0x04, 0x20,
+];
+/// The bytes between the private key and the public key in that encoding.
+const X25519_PKCS8_PUBLIC: &[u8] = &[0xa1, 0x23, 0x03, 0x21, 0x00];
+
 impl PrivateKey {
+    /// Import an X25519 private key, along with its public key.
+    pub fn import_x25519(sk: &[u8], pk: &[u8]) -> Res<Self> {
+        if sk.len() != 32 || pk.len() != 32 {
+            return Err(crate::Error::InvalidKeyType);
+        }
+        let mut der =
+            Vec::with_capacity(X25519_PKCS8_PREFIX.len() + X25519_PKCS8_PUBLIC.len() + 64);
+        der.extend_from_slice(X25519_PKCS8_PREFIX);
+        der.extend_from_slice(sk);
+        der.extend_from_slice(X25519_PKCS8_PUBLIC);
+        der.extend_from_slice(pk);
+
+        let slot = Slot::internal()?;
+        let mut der_item = Item::wrap(&der);
+        let mut ptr = null_mut();
+        secstatus_to_res(unsafe {
+            sys::PK11_ImportDERPrivateKeyInfoAndReturnKey(
+                *slot,
+                &mut der_item,
+                null_mut(),
+                null_mut(),
+                PRBool::from(false),
+                PRBool::from(false),
+                sys::KU_ALL,
+                &mut ptr,
+                null_mut(),
+            )
+        })?;
+        Self::from_ptr(ptr)
+    }
+
+    /// Export the private key.  This only works for keys that are not marked as sensitive.
     pub fn key_data(&self) -> Res<Vec<u8>> {
         let mut key_item = SECItem {
             type_: SECItemType::siBuffer,
@@ -105,24 +147,23 @@ impl PrivateKey {
 }
 unsafe impl Send for PrivateKey {}
 
+impl PrivateKey {
+    /// Copy the key.  Unlike `clone()`, this reports a failure in NSS.
+    pub fn try_clone(&self) -> Res<Self> {
+        Self::from_ptr(unsafe { sys::SECKEY_CopyPrivateKey(self.ptr) })
+    }
+}
+
 impl Clone for PrivateKey {
-    #[must_use]
     fn clone(&self) -> Self {
-        let ptr = unsafe { sys::SECKEY_CopyPrivateKey(self.ptr) };
-        if ptr.is_null() {
-            return Error::unexpected;
-        }
-        Self { ptr }
+        // Copying a key only fails if NSS can't allocate memory.
+        self.try_clone().expect("NSS failed to copy a private key")
     }
 }
 
 impl std::fmt::Debug for PrivateKey {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        if let Ok(b) = self.key_data() {
-            write!(f, "PrivateKey {}", hex::encode(b))
-        } else {
-            write!(f, "Opaque PrivateKey")
-        }
+        f.write_str("PrivateKey")
     }
 }
 
@@ -148,14 +189,17 @@ impl PublicKey {
 
 unsafe impl Send for PublicKey {}
 
+impl PublicKey {
+    /// Copy the key.  Unlike `clone()`, this reports a failure in NSS.
+    pub fn try_clone(&self) -> Res<Self> {
+        Self::from_ptr(unsafe { sys::SECKEY_CopyPublicKey(self.ptr) })
+    }
+}
+
 impl Clone for PublicKey {
-    #[must_use]
     fn clone(&self) -> Self {
-        let ptr = unsafe { sys::SECKEY_CopyPublicKey(self.ptr) };
-        if ptr.is_null() {
-            return Error::unexpected;
-        }
-        Self { ptr }
+        // Copying a key only fails if NSS can't allocate memory.
+        self.try_clone().expect("NSS failed to copy a public key")
     }
 }
 
@@ -199,23 +243,17 @@ impl SymKey {
 }
 
 impl Clone for SymKey {
-    #[must_use]
     fn clone(&self) -> Self {
+        // This only increments the reference count, so it can't fail.
         let ptr = unsafe { PK11_ReferenceSymKey(self.ptr) };
-        if ptr.is_null() {
-            return Error::unexpected;
-        }
+        debug_assert_eq!(ptr, self.ptr);
         Self { ptr }
     }
 }
 
 impl std::fmt::Debug for SymKey {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        if let Ok(b) = self.key_data() {
-            write!(f, "SymKey {}", hex::encode(b))
-        } else {
-            write!(f, "Opaque SymKey")
-        }
+        f.write_str("SymKey")
     }
 }
 
@@ -275,24 +313,40 @@ impl Item {
     /// This dereferences the pointer held by the item and makes a copy of the
     /// content that is referenced there.
     ///
+    /// # Errors
+    /// If the item doesn't hold a plain buffer.
+    ///
     /// # Safety
     /// This dereferences two pointers.  It doesn't get much less safe.
-    pub(crate) unsafe fn into_vec(self) -> Vec<u8> {
-        let b = self.ptr.as_ref().unwrap();
+    pub(crate) unsafe fn into_vec(self) -> Res<Vec<u8>> {
+        let b = self.ptr.as_ref().ok_or_else(Error::internal)?;
         // Sanity check the type, as some types don't count bytes in `Item::len`.
         if b.type_ != SECItemType::siBuffer {
-            return Error::unexpected;
+            return Err(Error::internal().into());
         }
-        let slc = std::slice::from_raw_parts(b.data, usize::try_from(b.len).unwrap());
-        Vec::from(slc)
+        let slc = std::slice::from_raw_parts(b.data, usize::try_from(b.len)?);
+        Ok(Vec::from(slc))
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::random;
+    use super::{random, PrivateKey};
     use crate::init;
 
+    #[test]
+    fn import_x25519() {
+        // skRm and pkRm from RFC 9180, Appendix A.1.1.
+        const SK: &str = "4612c550263fc8ad58375df3f557aac531d26850903e55a9f23f21d8534e8ac8";
+        const PK: &str = "3948cfe0ad1ddb695d780e59077195da6c56506b027329794ab02bca80815c4d";
+        init();
+        let sk = hex::decode(SK).unwrap();
+        let pk = hex::decode(PK).unwrap();
+        let key = PrivateKey::import_x25519(&sk, &pk).unwrap();
+        assert_eq!(key.key_data().unwrap(), sk);
+        assert!(PrivateKey::import_x25519(&sk[1..], &pk).is_err());
+    }
+
     #[test]
     fn randomness() {
         init();
@@ -0,0 +1,595 @@
+//! HPKE (RFC 9180) built from the primitives in OpenSSL.
+//!
+//! This does not meet the need for a validated HPKE module.  OpenSSL 3.2 and
+//! later have their own HPKE API (`OSSL_HPKE_CTX`), but it can't be used
+//! here: `OSSL_HPKE_seal` and `OSSL_HPKE_open` reject empty plaintexts, which
+//! chunked Oblivious HTTP uses for final chunks and keepalives, and identifiers
+//! and labels are limited to 66 bytes.  It wouldn't be validated either: that
+//! API is part of libcrypto rather than the FIPS provider, and the FIPS
+//! provider doesn't offer DHKEM.
+//!
+//! So DHKEM and the key schedule are implemented here and in `key_schedule`,
+//! and only the key exchange, HKDF, and the AEAD come from OpenSSL.  With a
+//! FIPS provider, those come from the validated module when P-384 and AES-GCM
+//! are used, but the way they are put together is not validated.
+
+use super::{
+    aead::{Aead, Mode},
+    SymKey,
+};
+use crate::{
+    hpke::{Aead as AeadId, Kdf, Kem, ReceiverMode, SenderMode},
+    key_schedule::{KeySchedule, LabeledHkdf},
+    Error, Res,
+};
+use openssl::{
+    bn::{BigNum, BigNumContext},
+    derive::Deriver,
+    ec::{EcGroup, EcKey, EcPoint, PointConversionForm},
+    nid::Nid,
+    pkey::{HasPublic, Id, PKey, PKeyRef, Private, Public},
+};
+use std::ops::Deref;
+use tracing::trace;
+
+/// Configuration for `Hpke`.
+#[derive(Clone, Copy)]
+pub struct Config {
+    kem: Kem,
+    kdf: Kdf,
+    aead: AeadId,
+}
+
+impl Config {
+    pub fn new(kem: Kem, kdf: Kdf, aead: AeadId) -> Self {
+        Self { kem, kdf, aead }
+    }
+
+    pub fn kem(self) -> Kem {
+        self.kem
+    }
+
+    pub fn kdf(self) -> Kdf {
+        self.kdf
+    }
+
+    pub fn aead(self) -> AeadId {
+        self.aead
+    }
+
+    pub fn supported(self) -> bool {
+        kem_kdf(self.kem).is_ok()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            kem: Kem::X25519Sha256,
+            kdf: Kdf::HkdfSha256,
+            aead: AeadId::Aes128Gcm,
+        }
+    }
+}
+
+/// The KDF that a KEM uses internally.
+fn kem_kdf(kem: Kem) -> Res<Kdf> {
+    match kem {
+        Kem::X25519Sha256 => Ok(Kdf::HkdfSha256),
+        Kem::P384Sha384 => Ok(Kdf::HkdfSha384),
+        #[allow(unreachable_patterns)]
+        _ => Err(Error::InvalidKem),
+    }
+}
+
+fn p384() -> Res<EcGroup> {
+    Ok(EcGroup::from_curve_name(Nid::SECP384R1)?)
+}
+
+/// `SerializePublicKey` from RFC 9180.
+fn serialize_public<T: HasPublic>(kem: Kem, key: &PKeyRef<T>) -> Res<Vec<u8>> {
+    if kem == Kem::P384Sha384 {
+        let ec = key.ec_key()?;
+        let mut ctx = BigNumContext::new()?;
+        Ok(ec
+            .public_key()
+            .to_bytes(ec.group(), PointConversionForm::UNCOMPRESSED, &mut ctx)?)
+    } else {
+        Ok(key.raw_public_key()?)
+    }
+}
+
+/// `DeserializePublicKey` from RFC 9180, which also checks that the key is valid.
+fn deserialize_public(kem: Kem, k: &[u8]) -> Res<PKey<Public>> {
+    match kem {
+        Kem::X25519Sha256 => Ok(PKey::public_key_from_raw_bytes(k, Id::X25519)?),
+        Kem::P384Sha384 => {
+            let group = p384()?;
+            let mut ctx = BigNumContext::new()?;
+            let point = EcPoint::from_bytes(&group, k, &mut ctx)?;
+            let ec = EcKey::from_public_key(&group, &point)?;
+            ec.check_key()?;
+            Ok(PKey::from_ec_key(ec)?)
+        }
+        #[allow(unreachable_patterns)]
+        _ => Err(Error::Unsupported),
+    }
+}
+
+fn dh(sk: &PKeyRef<Private>, pk: &PKeyRef<Public>) -> Res<Vec<u8>> {
+    let mut deriver = Deriver::new(sk)?;
+    deriver.set_peer(pk)?;
+    Ok(deriver.derive_to_vec()?)
+}
+
+/// HKDF with the labels that HPKE adds to each step.
+/// A public key, which is kept with its serialized form.
+#[derive(Clone)]
+pub struct PublicKey {
+    key: PKey<Public>,
+    encoded: Vec<u8>,
+}
+
+impl PublicKey {
+    #[allow(clippy::unnecessary_wraps)]
+    pub fn key_data(&self) -> Res<Vec<u8>> {
+        Ok(self.encoded.clone())
+    }
+}
+
+impl std::fmt::Debug for PublicKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "PublicKey {}", hex::encode(&self.encoded))
+    }
+}
+
+#[derive(Clone)]
+pub struct PrivateKey {
+    kem: Kem,
+    key: PKey<Private>,
+}
+
+impl PrivateKey {
+    pub fn key_data(&self) -> Res<Vec<u8>> {
+        if self.kem == Kem::P384Sha384 {
+            Ok(self.key.ec_key()?.private_key().to_vec_padded(48)?)
+        } else {
+            Ok(self.key.raw_private_key()?)
+        }
+    }
+
+    fn public_key(&self) -> Res<PublicKey> {
+        let encoded = serialize_public(self.kem, &self.key)?;
+        let key = deserialize_public(self.kem, &encoded)?;
+        Ok(PublicKey { key, encoded })
+    }
+}
+
+/// This doesn't show the key, as keys end up in logs.
+impl std::fmt::Debug for PrivateKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "PrivateKey {:?}", self.kem)
+    }
+}
+
+/// `ExtractAndExpand` from DHKEM.
+fn extract_and_expand(kem: Kem, dh: &[u8], kem_context: &[u8]) -> Res<Vec<u8>> {
+    let kdf = LabeledHkdf::kem(kem, kem_kdf(kem)?);
+    let eae_prk = kdf.extract(&[], b"eae_prk", dh)?;
+    kdf.expand(&eae_prk, b"shared_secret", kem_context, kdf.kdf().n_h())
+}
+
+/// `Encap` from DHKEM, or `AuthEncap` if the sender has a key.
+/// This returns the shared secret and the encapsulated key.
+fn encap(kem: Kem, pk_r: &PublicKey, sender: Option<&PrivateKey>) -> Res<(Vec<u8>, Vec<u8>)> {
+    let (sk_e, _) = generate_key_pair(kem)?;
+    let enc = serialize_public(kem, &sk_e.key)?;
+    let mut dh_out = dh(&sk_e.key, &pk_r.key)?;
+    let mut kem_context = [&enc[..], &pk_r.encoded].concat();
+    if let Some(sk_s) = sender {
+        dh_out.extend_from_slice(&dh(&sk_s.key, &pk_r.key)?);
+        kem_context.extend_from_slice(&serialize_public(kem, &sk_s.key)?);
+    }
+    Ok((extract_and_expand(kem, &dh_out, &kem_context)?, enc))
+}
+
+/// `Decap` from DHKEM, or `AuthDecap` if there is a key for the sender.
+fn decap(kem: Kem, enc: &[u8], sk_r: &PrivateKey, pk_s: Option<&PublicKey>) -> Res<Vec<u8>> {
+    let pk_e = deserialize_public(kem, enc)?;
+    let mut dh_out = dh(&sk_r.key, &pk_e)?;
+    let mut kem_context = [enc, &serialize_public(kem, &sk_r.key)?].concat();
+    if let Some(pk_s) = pk_s {
+        dh_out.extend_from_slice(&dh(&sk_r.key, &pk_s.key)?);
+        kem_context.extend_from_slice(&pk_s.encoded);
+    }
+    extract_and_expand(kem, &dh_out, &kem_context)
+}
+
+pub trait Exporter {
+    fn export(&self, info: &[u8], len: usize) -> Res<SymKey>;
+}
+
+impl Exporter for KeySchedule {
+    fn export(&self, info: &[u8], len: usize) -> Res<SymKey> {
+        KeySchedule::export(self, info, len)
+    }
+}
+
+fn check_kem(config: Config, kem: Kem) -> Res<()> {
+    if kem == config.kem {
+        Ok(())
+    } else {
+        Err(Error::InvalidKeyType)
+    }
+}
+
+#[allow(clippy::module_name_repetitions)]
+pub struct HpkeS {
+    ks: KeySchedule,
+    aead: Aead,
+    enc: Vec<u8>,
+    config: Config,
+}
+
+impl HpkeS {
+    /// Create a new context that uses the KEM mode for sending.
+    #[cfg(test)]
+    pub fn new(config: Config, pk_r: &mut PublicKey, info: &[u8]) -> Res<Self> {
+        Self::with_mode(config, pk_r, info, &SenderMode::Base)
+    }
+
+    /// Create a new context for sending that uses the given HPKE mode.
+    pub fn with_mode(
+        config: Config,
+        pk_r: &mut PublicKey,
+        info: &[u8],
+        mode: &SenderMode,
+    ) -> Res<Self> {
+        let (sender, psk) = match mode {
+            SenderMode::Base => (None, None),
+            SenderMode::Psk(psk) => (None, Some(psk)),
+            SenderMode::Auth(sk_s, _) => (Some(sk_s), None),
+            SenderMode::AuthPsk(sk_s, _, psk) => (Some(sk_s), Some(psk)),
+        };
+        if let Some(sk_s) = sender {
+            check_kem(config, sk_s.kem())?;
+        }
+        let (shared_secret, enc) = encap(config.kem, pk_r, sender.map(|sk_s| &sk_s.key))?;
+        let ks = KeySchedule::new(
+            config.kem,
+            config.kdf,
+            config.aead,
+            mode.mode(),
+            &shared_secret,
+            info,
+            psk,
+        )?;
+        let aead = ks.aead(Mode::Encrypt, config.aead)?;
+        Ok(Self {
+            ks,
+            aead,
+            enc,
+            config,
+        })
+    }
+
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    /// Get the encapsulated KEM secret.
+    #[allow(clippy::unnecessary_wraps)]
+    pub fn enc(&self) -> Res<Vec<u8>> {
+        Ok(self.enc.clone())
+    }
+
+    pub fn seal(&mut self, aad: &[u8], pt: &[u8]) -> Res<Vec<u8>> {
+        self.aead.seal(aad, pt)
+    }
+}
+
+impl Exporter for HpkeS {
+    fn export(&self, info: &[u8], len: usize) -> Res<SymKey> {
+        self.ks.export(info, len)
+    }
+}
+
+impl Deref for HpkeS {
+    type Target = Config;
+    fn deref(&self) -> &Self::Target {
+        &self.config
+    }
+}
+
+#[allow(clippy::module_name_repetitions)]
+pub struct HpkeR {
+    ks: KeySchedule,
+    aead: Aead,
+    seq: u64,
+    config: Config,
+}
+
+impl HpkeR {
+    /// Create a new context that uses the KEM mode for sending.
+    #[cfg(test)]
+    #[allow(clippy::similar_names)]
+    pub fn new(
+        config: Config,
+        pk_r: &PublicKey,
+        sk_r: &PrivateKey,
+        enc: &[u8],
+        info: &[u8],
+    ) -> Res<Self> {
+        Self::with_mode(config, pk_r, sk_r, enc, info, &ReceiverMode::Base)
+    }
+
+    /// Create a new context for receiving that uses the given HPKE mode.
+    #[allow(clippy::similar_names)]
+    pub fn with_mode(
+        config: Config,
+        _pk_r: &PublicKey,
+        sk_r: &PrivateKey,
+        enc: &[u8],
+        info: &[u8],
+        mode: &ReceiverMode,
+    ) -> Res<Self> {
+        check_kem(config, sk_r.kem)?;
+        let (pk_s, psk) = match mode {
+            ReceiverMode::Base => (None, None),
+            ReceiverMode::Psk(psk) => (None, Some(psk)),
+            ReceiverMode::Auth(pk_s) => (Some(pk_s), None),
+            ReceiverMode::AuthPsk(pk_s, psk) => (Some(pk_s), Some(psk)),
+        };
+        if let Some(pk_s) = pk_s {
+            check_kem(config, pk_s.kem())?;
+        }
+        let shared_secret = decap(config.kem, enc, sk_r, pk_s.map(|pk_s| &pk_s.key))?;
+        let ks = KeySchedule::new(
+            config.kem,
+            config.kdf,
+            config.aead,
+            mode.mode(),
+            &shared_secret,
+            info,
+            psk,
+        )?;
+        let aead = ks.aead(Mode::Decrypt, config.aead)?;
+        Ok(Self {
+            ks,
+            aead,
+            seq: 0,
+            config,
+        })
+    }
+
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    /// Check that the key is valid for the KEM before keeping it.
+    pub fn decode_public_key(kem: Kem, k: &[u8]) -> Res<PublicKey> {
+        let key = deserialize_public(kem, k)?;
+        Ok(PublicKey {
+            key,
+            encoded: k.to_vec(),
+        })
+    }
+
+    pub fn open(&mut self, aad: &[u8], ct: &[u8]) -> Res<Vec<u8>> {
+        let pt = self.aead.open(aad, self.seq, ct)?;
+        self.seq += 1;
+        Ok(pt)
+    }
+}
+
+impl Exporter for HpkeR {
+    fn export(&self, info: &[u8], len: usize) -> Res<SymKey> {
+        self.ks.export(info, len)
+    }
+}
+
+impl Deref for HpkeR {
+    type Target = Config;
+    fn deref(&self) -> &Self::Target {
+        &self.config
+    }
+}
+
+/// Generate a key pair for the identified KEM.
+pub fn generate_key_pair(kem: Kem) -> Res<(PrivateKey, PublicKey)> {
+    let key = match kem {
+        Kem::X25519Sha256 => PKey::generate_x25519()?,
+        Kem::P384Sha384 => {
+            let group = p384()?;
+            PKey::from_ec_key(EcKey::generate(&group)?)?
+        }
+        #[allow(unreachable_patterns)]
+        _ => return Err(Error::InvalidKem),
+    };
+    let sk = PrivateKey { kem, key };
+    let pk = sk.public_key()?;
+    trace!("Generated key pair: sk={:?} pk={:?}", sk, pk);
+    Ok((sk, pk))
+}
+
+/// `DeriveKeyPair` from Section 7.1.3 of RFC 9180.
+pub fn derive_key_pair(kem: Kem, ikm: &[u8]) -> Res<(PrivateKey, PublicKey)> {
+    let kdf = LabeledHkdf::kem(kem, kem_kdf(kem)?);
+    let dkp_prk = kdf.extract(&[], b"dkp_prk", ikm)?;
+    let key = if kem == Kem::P384Sha384 {
+        let group = p384()?;
+        let mut ctx = BigNumContext::new()?;
+        let mut order = BigNum::new()?;
+        group.order(&mut order, &mut ctx)?;
+        let mut found = None;
+        for counter in 0..=u8::MAX {
+            let bytes = kdf.expand(&dkp_prk, b"candidate", &[counter], 48)?;
+            let sk = BigNum::from_slice(&bytes)?;
+            if sk.num_bits() > 0 && sk < order {
+                found = Some(sk);
+                break;
+            }
+        }
+        let sk = found.ok_or(Error::InvalidPrivateKey)?;
+        let mut public = EcPoint::new(&group)?;
+        public.mul_generator2(&group, &sk, &mut ctx)?;
+        PKey::from_ec_key(EcKey::from_private_components(&group, &sk, &public)?)?
+    } else {
+        let sk = kdf.expand(&dkp_prk, b"sk", &[], 32)?;
+        PKey::private_key_from_raw_bytes(&sk, Id::X25519)?
+    };
+    let sk = PrivateKey { kem, key };
+    let pk = sk.public_key()?;
+    trace!("Derived key pair: sk={:?} pk={:?}", sk, pk);
+    Ok((sk, pk))
+}
+
+/// Import a key pair, checking that the public key matches the private key.
+pub fn import_key_pair(kem: Kem, sk: &[u8], pk: &[u8]) -> Res<(PrivateKey, PublicKey)> {
+    let key = match kem {
+        Kem::X25519Sha256 => PKey::private_key_from_raw_bytes(sk, Id::X25519)?,
+        Kem::P384Sha384 => {
+            if sk.len() != 48 {
+                return Err(Error::InvalidPrivateKey);
+            }
+            let group = p384()?;
+            let mut ctx = BigNumContext::new()?;
+            let sk = BigNum::from_slice(sk)?;
+            let mut public = EcPoint::new(&group)?;
+            public.mul_generator2(&group, &sk, &mut ctx)?;
+            let ec = EcKey::from_private_components(&group, &sk, &public)?;
+            ec.check_key()?;
+            PKey::from_ec_key(ec)?
+        }
+        #[allow(unreachable_patterns)]
+        _ => return Err(Error::InvalidKem),
+    };
+    let sk = PrivateKey { kem, key };
+    let pk_sk = sk.public_key()?;
+    if pk_sk.encoded != pk {
+        return Err(Error::InvalidKeyType);
+    }
+    Ok((sk, pk_sk))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{derive_key_pair, generate_key_pair, import_key_pair, Config, HpkeR, HpkeS};
+    use crate::{
+        hpke::{Aead, Kdf, Kem},
+        init,
+    };
+
+    const INFO: &[u8] = b"info";
+    const AAD: &[u8] = b"aad";
+    const PT: &[u8] = b"message";
+
+    #[allow(clippy::similar_names)] // for sk_x and pk_x
+    #[test]
+    fn make() {
+        init();
+        let cfg = Config::default();
+        let (sk_r, mut pk_r) = generate_key_pair(cfg.kem()).unwrap();
+        let hpke_s = HpkeS::new(cfg, &mut pk_r, INFO).unwrap();
+        let _hpke_r = HpkeR::new(cfg, &pk_r, &sk_r, &hpke_s.enc().unwrap(), INFO).unwrap();
+    }
+
+    fn seal_open(cfg: Config) {
+        // Setup
+        init();
+        assert!(cfg.supported());
+        let (sk_r, mut pk_r) = generate_key_pair(cfg.kem()).unwrap();
+
+        // Send
+        let mut hpke_s = HpkeS::new(cfg, &mut pk_r, INFO).unwrap();
+        let enc = hpke_s.enc().unwrap();
+        assert_eq!(enc.len(), cfg.kem().n_enc());
+        let ct = hpke_s.seal(AAD, PT).unwrap();
+        let empty = hpke_s.seal(AAD, &[]).unwrap();
+
+        // Receive
+        let mut hpke_r = HpkeR::new(cfg, &pk_r, &sk_r, &enc, INFO).unwrap();
+        let pt = hpke_r.open(AAD, &ct).unwrap();
+        assert_eq!(&pt[..], PT);
+        assert!(hpke_r.open(AAD, &empty).unwrap().is_empty());
+    }
+
+    #[test]
+    fn seal_open_gcm() {
+        seal_open(Config::new(
+            Kem::X25519Sha256,
+            Kdf::HkdfSha256,
+            Aead::Aes128Gcm,
+        ));
+    }
+
+    #[test]
+    fn seal_open_chacha() {
+        seal_open(Config::new(
+            Kem::X25519Sha256,
+            Kdf::HkdfSha256,
+            Aead::ChaCha20Poly1305,
+        ));
+    }
+
+    #[test]
+    fn seal_open_p384() {
+        seal_open(Config::new(
+            Kem::P384Sha384,
+            Kdf::HkdfSha384,
+            Aead::Aes256Gcm,
+        ));
+    }
+
+    /// `DeriveKeyPair` for P-384.  RFC 9180 has no test vectors for
+    /// DHKEM(P-384, HKDF-SHA384), so these values were checked against a
+    /// separate implementation of Section 7.1.3.
+    #[test]
+    fn derive_p384() {
+        const SK: &str = "98c0889aab5610522699abe5970b7b7132022094127060b9\
+                          28018fb3c0e2aaae9da72e0c9cf8f909d91c1e1e58f7454a";
+        const PK: &str = "04986dc0a7d2b37e3b222ea7d25a32fc290c88c50b6a0acfdecadb83a285f19a3e\
+                          f0dbceeeecf54a9e7e02e4fb2c7bc075c24ba4c069bb3466ba3d35b29783bb51c7\
+                          4aa60ecfadacb1f4446327b36272176c58d687e5318e5537176f37cc846823";
+
+        init();
+        let ikm = (0..48).collect::<Vec<u8>>();
+        let (sk, pk) = derive_key_pair(Kem::P384Sha384, &ikm).unwrap();
+        assert_eq!(hex::encode(sk.key_data().unwrap()), SK);
+        assert_eq!(hex::encode(pk.key_data().unwrap()), PK);
+    }
+
+    #[test]
+    fn import() {
+        init();
+        for kem in [Kem::X25519Sha256, Kem::P384Sha384] {
+            let (sk, pk) = generate_key_pair(kem).unwrap();
+            let sk_enc = sk.key_data().unwrap();
+            let pk_enc = pk.key_data().unwrap();
+            let (sk_i, pk_i) = import_key_pair(kem, &sk_enc, &pk_enc).unwrap();
+            assert_eq!(sk_i.key_data().unwrap(), sk_enc);
+            assert_eq!(pk_i.key_data().unwrap(), pk_enc);
+
+            // A public key for a different private key is rejected.
+            let (_, other) = generate_key_pair(kem).unwrap();
+            assert!(import_key_pair(kem, &sk_enc, &other.key_data().unwrap()).is_err());
+        }
+    }
+
+    #[test]
+    fn send_hpkes() {
+        use std::{sync::mpsc, thread};
+
+        init();
+        let (tx, rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            let (_, mut pk_r) = generate_key_pair(Kem::X25519Sha256).unwrap();
+            let hpke_s = HpkeS::new(Config::default(), &mut pk_r, INFO).unwrap();
+            tx.send(hpke_s).unwrap();
+        });
+
+        let hpke_s = rx.recv().unwrap();
+        drop(hpke_s);
+    }
+}
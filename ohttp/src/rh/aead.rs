@@ -1,9 +1,13 @@
 #![allow(dead_code)] // TODO: remove
 
 use super::SymKey;
-use crate::{err::Res, hpke::Aead as AeadId};
-use aead::{AeadMut, Key, NewAead, Nonce, Payload};
+use crate::{
+    err::{Error, Res},
+    hpke::Aead as AeadId,
+};
+use aead::{AeadInPlace, Key, NewAead, Nonce, Tag};
 use aes_gcm::{Aes128Gcm, Aes256Gcm};
+use bytes::BytesMut;
 use chacha20poly1305::ChaCha20Poly1305;
 use std::convert::TryFrom;
 
@@ -29,25 +33,42 @@ enum AeadEngine {
 // Dispatch functions; this just shows how janky that this sort of abstraction can be.
 // If this grows too much, this is fairly clearly responsive to using a macro.
 impl AeadEngine {
-    fn encrypt(&mut self, nonce: &[u8], pt: Payload) -> Res<Vec<u8>> {
+    fn encrypt(&mut self, nonce: &[u8], aad: &[u8], buf: &mut [u8]) -> Res<[u8; TAG_LEN]> {
         let tag = match self {
-            Self::Aes128Gcm(e) => e.encrypt(Nonce::<Aes128Gcm>::from_slice(nonce), pt)?,
-            Self::Aes256Gcm(e) => e.encrypt(Nonce::<Aes256Gcm>::from_slice(nonce), pt)?,
-            Self::ChaCha20Poly1305(e) => {
-                e.encrypt(Nonce::<ChaCha20Poly1305>::from_slice(nonce), pt)?
+            Self::Aes128Gcm(e) => {
+                e.encrypt_in_place_detached(Nonce::<Aes128Gcm>::from_slice(nonce), aad, buf)?
+            }
+            Self::Aes256Gcm(e) => {
+                e.encrypt_in_place_detached(Nonce::<Aes256Gcm>::from_slice(nonce), aad, buf)?
             }
-        };
-        Ok(tag)
-    }
-    fn decrypt(&mut self, nonce: &[u8], pt: Payload) -> Res<Vec<u8>> {
-        let tag = match self {
-            Self::Aes128Gcm(e) => e.decrypt(Nonce::<Aes128Gcm>::from_slice(nonce), pt)?,
-            Self::Aes256Gcm(e) => e.decrypt(Nonce::<Aes256Gcm>::from_slice(nonce), pt)?,
             Self::ChaCha20Poly1305(e) => {
-                e.decrypt(Nonce::<ChaCha20Poly1305>::from_slice(nonce), pt)?
+                e.encrypt_in_place_detached(Nonce::<ChaCha20Poly1305>::from_slice(nonce), aad, buf)?
             }
         };
-        Ok(tag)
+        Ok(tag.into())
+    }
+    fn decrypt(&mut self, nonce: &[u8], aad: &[u8], buf: &mut [u8], tag: &[u8]) -> Res<()> {
+        match self {
+            Self::Aes128Gcm(e) => e.decrypt_in_place_detached(
+                Nonce::<Aes128Gcm>::from_slice(nonce),
+                aad,
+                buf,
+                Tag::<Aes128Gcm>::from_slice(tag),
+            )?,
+            Self::Aes256Gcm(e) => e.decrypt_in_place_detached(
+                Nonce::<Aes256Gcm>::from_slice(nonce),
+                aad,
+                buf,
+                Tag::<Aes256Gcm>::from_slice(tag),
+            )?,
+            Self::ChaCha20Poly1305(e) => e.decrypt_in_place_detached(
+                Nonce::<ChaCha20Poly1305>::from_slice(nonce),
+                aad,
+                buf,
+                Tag::<ChaCha20Poly1305>::from_slice(tag),
+            )?,
+        }
+        Ok(())
     }
 }
 
@@ -101,19 +122,43 @@ impl Aead {
     }
 
     pub fn seal(&mut self, aad: &[u8], pt: &[u8]) -> Res<Vec<u8>> {
+        let mut buf = BytesMut::with_capacity(pt.len() + TAG_LEN);
+        buf.extend_from_slice(pt);
+        self.seal_in_place(aad, &mut buf)?;
+        Ok(Vec::from(buf))
+    }
+
+    /// Encrypt the content of `buf` in place and add the tag.
+    /// This only allocates if `buf` doesn't have space for the tag.
+    pub fn seal_in_place(&mut self, aad: &[u8], buf: &mut BytesMut) -> Res<()> {
         assert_eq!(self.mode, Mode::Encrypt);
-        // A copy for the nonce generator to write into.  But we don't use the value.
         let nonce = self.nonce(self.seq);
         self.seq += 1;
-        let ct = self.engine.encrypt(&nonce, Payload { msg: pt, aad })?;
-        Ok(ct)
+        let tag = self.engine.encrypt(&nonce, aad, buf)?;
+        buf.extend_from_slice(&tag);
+        Ok(())
     }
 
     pub fn open(&mut self, aad: &[u8], seq: SequenceNumber, ct: &[u8]) -> Res<Vec<u8>> {
+        let mut buf = BytesMut::from(ct);
+        self.open_in_place(aad, seq, &mut buf)?;
+        Ok(Vec::from(buf))
+    }
+
+    /// Decrypt the content of `buf` in place and remove the tag.
+    pub fn open_in_place(
+        &mut self,
+        aad: &[u8],
+        seq: SequenceNumber,
+        buf: &mut BytesMut,
+    ) -> Res<()> {
         assert_eq!(self.mode, Mode::Decrypt);
+        let pt_len = buf.len().checked_sub(TAG_LEN).ok_or(Error::Truncated)?;
         let nonce = self.nonce(seq);
-        let pt = self.engine.decrypt(&nonce, Payload { msg: ct, aad })?;
-        Ok(pt)
+        let (pt, tag) = buf.split_at_mut(pt_len);
+        self.engine.decrypt(&nonce, aad, pt, tag)?;
+        buf.truncate(pt_len);
+        Ok(())
     }
 }
 
@@ -123,6 +168,7 @@ mod test {
         super::super::{hpke::Aead as AeadId, init},
         Aead, Mode, SequenceNumber, NONCE_LEN,
     };
+    use bytes::BytesMut;
 
     /// Check that the first invocation of encryption matches expected values.
     /// Also check decryption of the same.
@@ -144,6 +190,15 @@ mod test {
         let mut dec = Aead::new(Mode::Decrypt, algorithm, &k, *nonce).unwrap();
         let plaintext = dec.open(aad, 0, ct).unwrap();
         assert_eq!(&plaintext[..], pt);
+
+        // The same, but in place.
+        let mut buf = BytesMut::from(pt);
+        let mut enc = Aead::new(Mode::Encrypt, algorithm, &k, *nonce).unwrap();
+        enc.seal_in_place(aad, &mut buf).unwrap();
+        assert_eq!(&buf[..], ct);
+        let mut dec = Aead::new(Mode::Decrypt, algorithm, &k, *nonce).unwrap();
+        dec.open_in_place(aad, 0, &mut buf).unwrap();
+        assert_eq!(&buf[..], pt);
     }
 
     fn decrypt(
@@ -40,7 +40,7 @@ impl Hkdf {
         }
     }
 
-    #[cfg(test)]
+    #[cfg(any(test, feature = "pq"))]
     #[allow(clippy::unnecessary_wraps)]
     pub fn import_ikm(ikm: &[u8]) -> Res<SymKey> {
         Ok(SymKey::from(ikm))
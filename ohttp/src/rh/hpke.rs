@@ -1,25 +1,28 @@
 use super::SymKey;
 use crate::{
-    hpke::{Aead, Kdf, Kem},
+    hpke::{Aead, Kdf, Kem, Psk, ReceiverMode, SenderMode},
     Error, Res,
 };
 
-#[cfg(not(feature = "pq"))]
+#[cfg(not(feature = "pq-draft"))]
 use ::hpke as rust_hpke;
 
-#[cfg(feature = "pq")]
+#[cfg(feature = "pq-draft")]
 use ::hpke_pq as rust_hpke;
 
 use rust_hpke::{
     aead::{AeadCtxR, AeadCtxS, AeadTag, AesGcm128, AesGcm256, ChaCha20Poly1305},
     kdf::{HkdfSha256, HkdfSha384},
     kem::{DhP384HkdfSha384, Kem as KemTrait, X25519HkdfSha256},
-    setup_receiver, setup_sender, Deserializable, OpModeR, OpModeS, Serializable,
+    setup_receiver, setup_sender, Deserializable, OpModeR, OpModeS, PskBundle, Serializable,
 };
 
-#[cfg(feature = "pq")]
+#[cfg(feature = "pq-draft")]
 use rust_hpke::kem::X25519Kyber768Draft00;
 
+#[cfg(feature = "pq")]
+use super::pq;
+
 use ::rand::thread_rng;
 use std::ops::Deref;
 use tracing::trace;
@@ -76,8 +79,11 @@ pub enum PublicKey {
 
     P384(<DhP384HkdfSha384 as KemTrait>::PublicKey),
 
-    #[cfg(feature = "pq")]
+    #[cfg(feature = "pq-draft")]
     X25519Kyber768Draft00(<X25519Kyber768Draft00 as KemTrait>::PublicKey),
+
+    #[cfg(feature = "pq")]
+    Pq(pq::PublicKey),
 }
 
 impl PublicKey {
@@ -88,8 +94,11 @@ impl PublicKey {
 
             Self::X25519(k) => Vec::from(k.to_bytes().as_slice()),
 
-            #[cfg(feature = "pq")]
+            #[cfg(feature = "pq-draft")]
             Self::X25519Kyber768Draft00(k) => Vec::from(k.to_bytes().as_slice()),
+
+            #[cfg(feature = "pq")]
+            Self::Pq(k) => k.key_data(),
         })
     }
 }
@@ -109,8 +118,10 @@ impl std::fmt::Debug for PublicKey {
 pub enum PrivateKey {
     P384(<DhP384HkdfSha384 as KemTrait>::PrivateKey),
     X25519(<X25519HkdfSha256 as KemTrait>::PrivateKey),
-    #[cfg(feature = "pq")]
+    #[cfg(feature = "pq-draft")]
     X25519Kyber768Draft00(<X25519Kyber768Draft00 as KemTrait>::PrivateKey),
+    #[cfg(feature = "pq")]
+    Pq(pq::PrivateKey),
 }
 
 impl PrivateKey {
@@ -120,8 +131,11 @@ impl PrivateKey {
             Self::P384(k) => Vec::from(k.to_bytes().as_slice()),
             Self::X25519(k) => Vec::from(k.to_bytes().as_slice()),
 
-            #[cfg(feature = "pq")]
+            #[cfg(feature = "pq-draft")]
             Self::X25519Kyber768Draft00(k) => Vec::from(k.to_bytes().as_slice()),
+
+            #[cfg(feature = "pq")]
+            Self::Pq(k) => k.key_data(),
         })
     }
 }
@@ -148,7 +162,7 @@ enum SenderContextDhP384HkdfSha384HkdfSha384 {
     AesGcm256(Box<AeadCtxS<AesGcm256, HkdfSha384, DhP384HkdfSha384>>),
 }
 
-#[cfg(feature = "pq")]
+#[cfg(feature = "pq-draft")]
 enum SenderContextX25519Kyber768Draft00HkdfSha256 {
     AesGcm128(Box<AeadCtxS<AesGcm128, HkdfSha256, X25519Kyber768Draft00>>),
 }
@@ -161,7 +175,7 @@ enum SenderContextDhP384HkdfSha384 {
     HkdfSha384(SenderContextDhP384HkdfSha384HkdfSha384),
 }
 
-#[cfg(feature = "pq")]
+#[cfg(feature = "pq-draft")]
 enum SenderContextX25519Kyber768Draft00 {
     HkdfSha256(SenderContextX25519Kyber768Draft00HkdfSha256),
 }
@@ -171,8 +185,11 @@ enum SenderContext {
 
     DhP384HkdfSha384(SenderContextDhP384HkdfSha384),
 
-    #[cfg(feature = "pq")]
+    #[cfg(feature = "pq-draft")]
     X25519Kyber768Draft00(SenderContextX25519Kyber768Draft00),
+
+    #[cfg(feature = "pq")]
+    Pq(Box<pq::SenderContext>),
 }
 
 impl SenderContext {
@@ -202,13 +219,20 @@ impl SenderContext {
                 let tag = context.seal_in_place_detached(plaintext, aad)?;
                 Vec::from(tag.to_bytes().as_slice())
             }
-            #[cfg(feature = "pq")]
+            #[cfg(feature = "pq-draft")]
             Self::X25519Kyber768Draft00(SenderContextX25519Kyber768Draft00::HkdfSha256(
                 SenderContextX25519Kyber768Draft00HkdfSha256::AesGcm128(context),
             )) => {
                 let tag = context.seal_in_place_detached(plaintext, aad)?;
                 Vec::from(tag.to_bytes().as_slice())
             }
+            #[cfg(feature = "pq")]
+            Self::Pq(context) => {
+                let mut ct = context.seal(aad, plaintext)?;
+                let tag = ct.split_off(plaintext.len());
+                plaintext.copy_from_slice(&ct);
+                tag
+            }
         })
     }
 
@@ -234,17 +258,34 @@ impl SenderContext {
             )) => {
                 context.export(info, out_buf)?;
             }
-            #[cfg(feature = "pq")]
+            #[cfg(feature = "pq-draft")]
             Self::X25519Kyber768Draft00(SenderContextX25519Kyber768Draft00::HkdfSha256(
                 SenderContextX25519Kyber768Draft00HkdfSha256::AesGcm128(context),
             )) => {
                 context.export(info, out_buf)?;
             }
+            #[cfg(feature = "pq")]
+            Self::Pq(context) => {
+                context.export(info, out_buf)?;
+            }
         }
         Ok(())
     }
 }
 
+fn psk_bundle(psk: &Psk) -> Res<PskBundle<'_>> {
+    Ok(PskBundle::new(psk.psk(), psk.id())?)
+}
+
+/// The post-quantum KEMs only have the base and PSK modes.
+#[cfg(feature = "pq")]
+fn pq_psk(mode: crate::hpke::Mode, psk: Option<&Psk>) -> Res<Option<&Psk>> {
+    match mode {
+        crate::hpke::Mode::Base | crate::hpke::Mode::Psk => Ok(psk),
+        _ => Err(Error::Unsupported),
+    }
+}
+
 pub trait Exporter {
     fn export(&self, info: &[u8], len: usize) -> Res<SymKey>;
 }
@@ -258,7 +299,18 @@ pub struct HpkeS {
 
 impl HpkeS {
     /// Create a new context that uses the KEM mode for sending.
+    #[cfg(test)]
     pub fn new(config: Config, pk_r: &mut PublicKey, info: &[u8]) -> Res<Self> {
+        Self::with_mode(config, pk_r, info, &SenderMode::Base)
+    }
+
+    /// Create a new context for sending that uses the given HPKE mode.
+    pub fn with_mode(
+        config: Config,
+        pk_r: &mut PublicKey,
+        info: &[u8],
+        mode: &SenderMode,
+    ) -> Res<Self> {
         let mut csprng = thread_rng();
 
         macro_rules! dispatch_hpkes_new {
@@ -267,7 +319,7 @@ impl HpkeS {
                     $kemid:path => $kem:path,
                     $kdfid:path => $kdf:path,
                     $aeadid:path => $aead:path,
-                    $pke:path, $ctxt1:path, $ctxt2:path, $ctxt3:path $(,)?
+                    $pke:path, $ske:path, $ctxt1:path, $ctxt2:path, $ctxt3:path $(,)?
                 }),* $(,)?]
             } => {
                 match ($c, $pk) {
@@ -281,8 +333,25 @@ impl HpkeS {
                             },
                             $pke(pk_r),
                         ) => {
+                            let sender_keys = |sk: &crate::hpke::PrivateKey,
+                                               pk: &crate::hpke::PublicKey| {
+                                match (&sk.key, &pk.key) {
+                                    ($ske(sk), $pke(pk)) => Ok((sk.clone(), pk.clone())),
+                                    _ => Err(Error::InvalidKeyType),
+                                }
+                            };
+                            let op_mode = match mode {
+                                SenderMode::Base => OpModeS::Base,
+                                SenderMode::Psk(psk) => OpModeS::Psk(psk_bundle(psk)?),
+                                SenderMode::Auth(sk_s, pk_s) => {
+                                    OpModeS::Auth(sender_keys(sk_s, pk_s)?)
+                                }
+                                SenderMode::AuthPsk(sk_s, pk_s, psk) => {
+                                    OpModeS::AuthPsk(sender_keys(sk_s, pk_s)?, psk_bundle(psk)?)
+                                }
+                            };
                             let (enc, context) = setup_sender::<$aead, $kdf, $kem, _>(
-                                &OpModeS::Base,
+                                &op_mode,
                                 pk_r,
                                 info,
                                 $csprng,
@@ -298,12 +367,34 @@ impl HpkeS {
             };
         }
 
+        #[cfg(feature = "pq")]
+        if let PublicKey::Pq(pk_r) = pk_r {
+            let psk = match mode {
+                SenderMode::Psk(psk) => Some(psk),
+                _ => None,
+            };
+            let (context, enc) = pq::SenderContext::new(
+                config.kem,
+                config.kdf,
+                config.aead,
+                pk_r,
+                info,
+                pq_psk(mode.mode(), psk)?,
+            )?;
+            return Ok(Self {
+                context: SenderContext::Pq(Box::new(context)),
+                enc,
+                config,
+            });
+        }
+
         let (context, enc) = dispatch_hpkes_new! { (config, pk_r, &mut csprng): [
             {
                 Kem::X25519Sha256 => X25519HkdfSha256,
                 Kdf::HkdfSha256 => HkdfSha256,
                 Aead::Aes128Gcm => AesGcm128,
                 PublicKey::X25519,
+                PrivateKey::X25519,
                 SenderContext::X25519HkdfSha256,
                 SenderContextX25519HkdfSha256::HkdfSha256,
                 SenderContextX25519HkdfSha256HkdfSha256::AesGcm128,
@@ -313,6 +404,7 @@ impl HpkeS {
                 Kdf::HkdfSha256 => HkdfSha256,
                 Aead::ChaCha20Poly1305 => ChaCha20Poly1305,
                 PublicKey::X25519,
+                PrivateKey::X25519,
                 SenderContext::X25519HkdfSha256,
                 SenderContextX25519HkdfSha256::HkdfSha256,
                 SenderContextX25519HkdfSha256HkdfSha256::ChaCha20Poly1305,
@@ -322,6 +414,7 @@ impl HpkeS {
                 Kdf::HkdfSha384 => HkdfSha384,
                 Aead::Aes128Gcm => AesGcm128,
                 PublicKey::P384,
+                PrivateKey::P384,
                 SenderContext::DhP384HkdfSha384,
                 SenderContextDhP384HkdfSha384::HkdfSha384,
                 SenderContextDhP384HkdfSha384HkdfSha384::AesGcm128,
@@ -331,16 +424,18 @@ impl HpkeS {
                 Kdf::HkdfSha384 => HkdfSha384,
                 Aead::Aes256Gcm => AesGcm256,
                 PublicKey::P384,
+                PrivateKey::P384,
                 SenderContext::DhP384HkdfSha384,
                 SenderContextDhP384HkdfSha384::HkdfSha384,
                 SenderContextDhP384HkdfSha384HkdfSha384::AesGcm256,
             },
-            #[cfg(feature = "pq")]
+            #[cfg(feature = "pq-draft")]
             {
                 Kem::X25519Kyber768Draft00 => X25519Kyber768Draft00,
                 Kdf::HkdfSha256 => HkdfSha256,
                 Aead::Aes128Gcm => AesGcm128,
                 PublicKey::X25519Kyber768Draft00,
+                PrivateKey::X25519Kyber768Draft00,
                 SenderContext::X25519Kyber768Draft00,
                 SenderContextX25519Kyber768Draft00::HkdfSha256,
                 SenderContextX25519Kyber768Draft00HkdfSha256::AesGcm128,
@@ -397,7 +492,7 @@ enum ReceiverContextDhP384HkdfSha384HkdfSha384 {
     AesGcm256(Box<AeadCtxR<AesGcm256, HkdfSha384, DhP384HkdfSha384>>),
 }
 
-#[cfg(feature = "pq")]
+#[cfg(feature = "pq-draft")]
 enum ReceiverContextX25519Kyber768Draft00HkdfSha256 {
     AesGcm128(Box<AeadCtxR<AesGcm128, HkdfSha256, X25519Kyber768Draft00>>),
 }
@@ -410,7 +505,7 @@ enum ReceiverContextDhP384HkdfSha384 {
     HkdfSha384(ReceiverContextDhP384HkdfSha384HkdfSha384),
 }
 
-#[cfg(feature = "pq")]
+#[cfg(feature = "pq-draft")]
 enum ReceiverContextX25519Kyber768Draft00 {
     HkdfSha256(ReceiverContextX25519Kyber768Draft00HkdfSha256),
 }
@@ -419,8 +514,11 @@ enum ReceiverContext {
     X25519HkdfSha256(ReceiverContextX25519HkdfSha256),
     DhP384HkdfSha384(ReceiverContextDhP384HkdfSha384),
 
-    #[cfg(feature = "pq")]
+    #[cfg(feature = "pq-draft")]
     X25519Kyber768Draft00(ReceiverContextX25519Kyber768Draft00),
+
+    #[cfg(feature = "pq")]
+    Pq(Box<pq::ReceiverContext>),
 }
 
 impl ReceiverContext {
@@ -474,7 +572,7 @@ impl ReceiverContext {
                 context.open_in_place_detached(ct, aad, &tag)?;
                 ct
             }
-            #[cfg(feature = "pq")]
+            #[cfg(feature = "pq-draft")]
             Self::X25519Kyber768Draft00(ReceiverContextX25519Kyber768Draft00::HkdfSha256(
                 ReceiverContextX25519Kyber768Draft00HkdfSha256::AesGcm128(context),
             )) => {
@@ -487,6 +585,13 @@ impl ReceiverContext {
                 context.open_in_place_detached(ct, aad, &tag)?;
                 ct
             }
+            #[cfg(feature = "pq")]
+            Self::Pq(context) => {
+                let pt = context.open(aad, ciphertext)?;
+                let pt_len = pt.len();
+                ciphertext[..pt_len].copy_from_slice(&pt);
+                &ciphertext[..pt_len]
+            }
         })
     }
 
@@ -513,12 +618,17 @@ impl ReceiverContext {
                 context.export(info, out_buf)?;
             }
 
-            #[cfg(feature = "pq")]
+            #[cfg(feature = "pq-draft")]
             Self::X25519Kyber768Draft00(ReceiverContextX25519Kyber768Draft00::HkdfSha256(
                 ReceiverContextX25519Kyber768Draft00HkdfSha256::AesGcm128(context),
             )) => {
                 context.export(info, out_buf)?;
             }
+
+            #[cfg(feature = "pq")]
+            Self::Pq(context) => {
+                context.export(info, out_buf)?;
+            }
         }
         Ok(())
     }
@@ -532,13 +642,27 @@ pub struct HpkeR {
 
 impl HpkeR {
     /// Create a new context that uses the KEM mode for sending.
+    #[cfg(test)]
     #[allow(clippy::similar_names)]
     pub fn new(
+        config: Config,
+        pk_r: &PublicKey,
+        sk_r: &PrivateKey,
+        enc: &[u8],
+        info: &[u8],
+    ) -> Res<Self> {
+        Self::with_mode(config, pk_r, sk_r, enc, info, &ReceiverMode::Base)
+    }
+
+    /// Create a new context for receiving that uses the given HPKE mode.
+    #[allow(clippy::similar_names)]
+    pub fn with_mode(
         config: Config,
         _pk_r: &PublicKey,
         sk_r: &PrivateKey,
         enc: &[u8],
         info: &[u8],
+        mode: &ReceiverMode,
     ) -> Res<Self> {
         macro_rules! dispatch_hpker_new {
             {
@@ -546,7 +670,7 @@ impl HpkeR {
                     $kemid:path => $kem:path,
                     $kdfid:path => $kdf:path,
                     $aeadid:path => $aead:path,
-                    $ske:path, $ctxt1:path, $ctxt2:path, $ctxt3:path $(,)?
+                    $ske:path, $pke:path, $ctxt1:path, $ctxt2:path, $ctxt3:path $(,)?
             }),* $(,)?]
             } => {
                 match ($c, $sk) {
@@ -560,9 +684,24 @@ impl HpkeR {
                             },
                             $ske(sk_r),
                         ) => {
+                            let sender_key = |pk: &crate::hpke::PublicKey| {
+                                if let $pke(pk) = &pk.key {
+                                    Ok(pk.clone())
+                                } else {
+                                    Err(Error::InvalidKeyType)
+                                }
+                            };
+                            let op_mode = match mode {
+                                ReceiverMode::Base => OpModeR::Base,
+                                ReceiverMode::Psk(psk) => OpModeR::Psk(psk_bundle(psk)?),
+                                ReceiverMode::Auth(pk_s) => OpModeR::Auth(sender_key(pk_s)?),
+                                ReceiverMode::AuthPsk(pk_s, psk) => {
+                                    OpModeR::AuthPsk(sender_key(pk_s)?, psk_bundle(psk)?)
+                                }
+                            };
                             let enc = <$kem as KemTrait>::EncappedKey::from_bytes(enc)?;
                             let context = setup_receiver::<$aead, $kdf, $kem>(
-                                &OpModeR::Base,
+                                &op_mode,
                                 sk_r,
                                 &enc,
                                 info,
@@ -574,12 +713,34 @@ impl HpkeR {
                 }
             };
         }
+        #[cfg(feature = "pq")]
+        if let PrivateKey::Pq(sk_r) = sk_r {
+            let psk = match mode {
+                ReceiverMode::Psk(psk) => Some(psk),
+                _ => None,
+            };
+            let context = pq::ReceiverContext::new(
+                config.kem,
+                config.kdf,
+                config.aead,
+                sk_r,
+                enc,
+                info,
+                pq_psk(mode.mode(), psk)?,
+            )?;
+            return Ok(Self {
+                context: ReceiverContext::Pq(Box::new(context)),
+                config,
+            });
+        }
+
         let context = dispatch_hpker_new! {(config, sk_r): [
             {
                 Kem::X25519Sha256 => X25519HkdfSha256,
                 Kdf::HkdfSha256 => HkdfSha256,
                 Aead::Aes128Gcm => AesGcm128,
                 PrivateKey::X25519,
+                PublicKey::X25519,
                 ReceiverContext::X25519HkdfSha256,
                 ReceiverContextX25519HkdfSha256::HkdfSha256,
                 ReceiverContextX25519HkdfSha256HkdfSha256::AesGcm128,
@@ -589,6 +750,7 @@ impl HpkeR {
                 Kdf::HkdfSha256 => HkdfSha256,
                 Aead::ChaCha20Poly1305 => ChaCha20Poly1305,
                 PrivateKey::X25519,
+                PublicKey::X25519,
                 ReceiverContext::X25519HkdfSha256,
                 ReceiverContextX25519HkdfSha256::HkdfSha256,
                 ReceiverContextX25519HkdfSha256HkdfSha256::ChaCha20Poly1305,
@@ -598,6 +760,7 @@ impl HpkeR {
                 Kdf::HkdfSha384 => HkdfSha384,
                 Aead::Aes128Gcm => AesGcm128,
                 PrivateKey::P384,
+                PublicKey::P384,
                 ReceiverContext::DhP384HkdfSha384,
                 ReceiverContextDhP384HkdfSha384::HkdfSha384,
                 ReceiverContextDhP384HkdfSha384HkdfSha384::AesGcm128,
@@ -607,17 +770,19 @@ impl HpkeR {
                 Kdf::HkdfSha384 => HkdfSha384,
                 Aead::Aes256Gcm => AesGcm256,
                 PrivateKey::P384,
+                PublicKey::P384,
                 ReceiverContext::DhP384HkdfSha384,
                 ReceiverContextDhP384HkdfSha384::HkdfSha384,
                 ReceiverContextDhP384HkdfSha384HkdfSha384::AesGcm256,
             },
 
-            #[cfg(feature = "pq")]
+            #[cfg(feature = "pq-draft")]
             {
                 Kem::X25519Kyber768Draft00 => X25519Kyber768Draft00,
                 Kdf::HkdfSha256 => HkdfSha256,
                 Aead::Aes128Gcm => AesGcm128,
                 PrivateKey::X25519Kyber768Draft00,
+                PublicKey::X25519Kyber768Draft00,
                 ReceiverContext::X25519Kyber768Draft00,
                 ReceiverContextX25519Kyber768Draft00::HkdfSha256,
                 ReceiverContextX25519Kyber768Draft00HkdfSha256::AesGcm128,
@@ -641,10 +806,16 @@ impl HpkeR {
                 PublicKey::X25519(<X25519HkdfSha256 as KemTrait>::PublicKey::from_bytes(k)?)
             }
 
-            #[cfg(feature = "pq")]
+            #[cfg(feature = "pq-draft")]
             Kem::X25519Kyber768Draft00 => PublicKey::X25519Kyber768Draft00(
                 <X25519Kyber768Draft00 as KemTrait>::PublicKey::from_bytes(k)?,
             ),
+
+            #[cfg(feature = "pq")]
+            Kem::MlKem768 => PublicKey::Pq(pq::PublicKey::decode(kem, k)?),
+
+            #[cfg(feature = "xwing")]
+            Kem::XWing => PublicKey::Pq(pq::PublicKey::decode(kem, k)?),
         })
     }
 
@@ -686,7 +857,7 @@ pub fn generate_key_pair(kem: Kem) -> Res<(PrivateKey, PublicKey)> {
             (PrivateKey::X25519(sk), PublicKey::X25519(pk))
         }
 
-        #[cfg(feature = "pq")]
+        #[cfg(feature = "pq-draft")]
         Kem::X25519Kyber768Draft00 => {
             let (sk, pk) = X25519Kyber768Draft00::gen_keypair(&mut csprng);
             (
@@ -694,6 +865,18 @@ pub fn generate_key_pair(kem: Kem) -> Res<(PrivateKey, PublicKey)> {
                 PublicKey::X25519Kyber768Draft00(pk),
             )
         }
+
+        #[cfg(feature = "pq")]
+        Kem::MlKem768 => {
+            let (sk, pk) = pq::generate_key_pair(kem)?;
+            (PrivateKey::Pq(sk), PublicKey::Pq(pk))
+        }
+
+        #[cfg(feature = "xwing")]
+        Kem::XWing => {
+            let (sk, pk) = pq::generate_key_pair(kem)?;
+            (PrivateKey::Pq(sk), PublicKey::Pq(pk))
+        }
     };
     trace!("Generated key pair: sk={:?} pk={:?}", sk, pk);
     Ok((sk, pk))
@@ -712,7 +895,7 @@ pub fn derive_key_pair(kem: Kem, ikm: &[u8]) -> Res<(PrivateKey, PublicKey)> {
             (PrivateKey::X25519(sk), PublicKey::X25519(pk))
         }
 
-        #[cfg(feature = "pq")]
+        #[cfg(feature = "pq-draft")]
         Kem::X25519Kyber768Draft00 => {
             let (sk, pk) = X25519Kyber768Draft00::derive_keypair(ikm);
             (
@@ -720,14 +903,58 @@ pub fn derive_key_pair(kem: Kem, ikm: &[u8]) -> Res<(PrivateKey, PublicKey)> {
                 PublicKey::X25519Kyber768Draft00(pk),
             )
         }
+
+        #[cfg(feature = "pq")]
+        Kem::MlKem768 => {
+            let (sk, pk) = pq::derive_key_pair(kem, ikm)?;
+            (PrivateKey::Pq(sk), PublicKey::Pq(pk))
+        }
+
+        #[cfg(feature = "xwing")]
+        Kem::XWing => {
+            let (sk, pk) = pq::derive_key_pair(kem, ikm)?;
+            (PrivateKey::Pq(sk), PublicKey::Pq(pk))
+        }
     };
     trace!("Derived key pair: sk={:?} pk={:?}", sk, pk);
     Ok((sk, pk))
 }
 
+/// Import a key pair from the serialized private and public keys.
+pub fn import_key_pair(kem: Kem, sk: &[u8], pk: &[u8]) -> Res<(PrivateKey, PublicKey)> {
+    let sk = match kem {
+        Kem::P384Sha384 => {
+            PrivateKey::P384(<DhP384HkdfSha384 as KemTrait>::PrivateKey::from_bytes(sk)?)
+        }
+
+        Kem::X25519Sha256 => {
+            PrivateKey::X25519(<X25519HkdfSha256 as KemTrait>::PrivateKey::from_bytes(sk)?)
+        }
+
+        #[cfg(feature = "pq-draft")]
+        Kem::X25519Kyber768Draft00 => PrivateKey::X25519Kyber768Draft00(
+            <X25519Kyber768Draft00 as KemTrait>::PrivateKey::from_bytes(sk)?,
+        ),
+
+        #[cfg(feature = "pq")]
+        Kem::MlKem768 => {
+            let (sk, pk) = pq::import_key_pair(kem, sk, pk)?;
+            return Ok((PrivateKey::Pq(sk), PublicKey::Pq(pk)));
+        }
+
+        #[cfg(feature = "xwing")]
+        Kem::XWing => {
+            let (sk, pk) = pq::import_key_pair(kem, sk, pk)?;
+            return Ok((PrivateKey::Pq(sk), PublicKey::Pq(pk)));
+        }
+    };
+    let pk = HpkeR::decode_public_key(kem, pk)?;
+    Ok((sk, pk))
+}
+
 #[cfg(test)]
 mod test {
-    use super::{generate_key_pair, Config, HpkeR, HpkeS};
+    use super::{generate_key_pair, import_key_pair, Config, HpkeR, HpkeS};
     use crate::{
         hpke::{Aead, Kem},
         init,
@@ -780,9 +1007,35 @@ mod test {
         seal_open(Aead::ChaCha20Poly1305, Kem::X25519Sha256);
     }
 
-    #[cfg(feature = "pq")]
+    #[test]
+    fn import() {
+        init();
+        for kem in [Kem::X25519Sha256, Kem::P384Sha384] {
+            let (sk, pk) = generate_key_pair(kem).unwrap();
+            let sk_enc = sk.key_data().unwrap();
+            let pk_enc = pk.key_data().unwrap();
+            let (sk_i, pk_i) = import_key_pair(kem, &sk_enc, &pk_enc).unwrap();
+            assert_eq!(sk_i.key_data().unwrap(), sk_enc);
+            assert_eq!(pk_i.key_data().unwrap(), pk_enc);
+            assert!(import_key_pair(kem, &sk_enc[1..], &pk_enc).is_err());
+        }
+    }
+
+    #[cfg(feature = "pq-draft")]
     #[test]
     fn seal_open_xyber768d00() {
         seal_open(Aead::Aes128Gcm, Kem::X25519Kyber768Draft00);
     }
+
+    #[cfg(feature = "pq")]
+    #[test]
+    fn seal_open_mlkem768() {
+        seal_open(Aead::Aes128Gcm, Kem::MlKem768);
+    }
+
+    #[cfg(feature = "xwing")]
+    #[test]
+    fn seal_open_xwing() {
+        seal_open(Aead::ChaCha20Poly1305, Kem::XWing);
+    }
 }
@@ -7,6 +7,8 @@
 pub mod aead;
 pub mod hkdf;
 pub mod hpke;
+#[cfg(feature = "pq")]
+mod pq;
 
 use crate::err::Res;
 
@@ -38,10 +40,6 @@ impl AsRef<[u8]> for SymKey {
 
 impl std::fmt::Debug for SymKey {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        if let Ok(b) = self.key_data() {
-            write!(f, "SymKey {}", hex::encode(b))
-        } else {
-            write!(f, "Opaque SymKey")
-        }
+        write!(f, "SymKey [{} bytes]", self.0.len())
     }
 }
@@ -44,10 +44,12 @@ fn check_certificate(cert: &str, service_cert_pem: &str) -> Res<bool> {
     // Verify the endorsed certificate using the endorser's public key
     let result = endorsed_cert.verify(&public_key)?;
 
-    info!(
-        "{}",
-        "Certificate from key management service is trusted".green()
-    );
+    if result {
+        info!(
+            "{}",
+            "Certificate from key management service is trusted".green()
+        );
+    }
 
     Ok(result)
 }
@@ -122,7 +124,9 @@ fn check_signature(signing_cert: &str, signature: &str, root: &[u8]) -> Res<bool
     // Verify signature over root
     let is_valid = ecdsa_sig.verify(root, &public_key)?;
 
-    info!("  {}", "Receipt signature valid.".green());
+    if is_valid {
+        info!("  {}", "Receipt signature valid.".green());
+    }
     Ok(is_valid)
 }
 
@@ -131,7 +135,9 @@ pub fn verify(receipt_str: &str, service_cert: &str) -> Res<bool> {
     let receipt: Receipt = serde_json::from_str(receipt_str)?;
 
     // Check that the certificate used to sign the receipt is endorsed by the KMS
-    let _ = check_certificate(&receipt.cert, service_cert)?;
+    if !check_certificate(&receipt.cert, service_cert)? {
+        return Ok(false);
+    }
 
     // Compute leaf
     let leaf = compute_leaf(receipt.leaf_components)?;